@@ -1,11 +1,12 @@
 import { useState } from 'react';
 import { render } from 'react-dom';
-import { begin, msFlow } from './util/ipc'
+import { Account, begin, beginMulti, logout, msFlow } from './util/ipc'
 
 const App = () => {
     const [username, setUsername] = useState('');
     const [password, setPassword] = useState('');
     const [apiKey, setApiKey] = useState('');
+    const [accounts, setAccounts] = useState<Account[]>([]);
     return (<>
         <input placeholder="Username" onChange={e => setUsername(e.currentTarget.value)} value={username} />
         <input type="password" placeholder="Password" onChange={e => setPassword(e.currentTarget.value)} value={password} />
@@ -14,6 +15,26 @@ const App = () => {
         <button onClick={() => {
             msFlow(apiKey || undefined);
         }}>Login with Microsoft</button>
+        <button onClick={() => {
+            setUsername('');
+            setPassword('');
+            logout();
+        }}>Logout</button>
+        <ul>
+            {accounts.map((account, i) => <li key={account.port}>
+                {account.username} on port {account.port}
+                <button onClick={() => setAccounts(accounts.filter((_, j) => j != i))}>Remove</button>
+            </li>)}
+        </ul>
+        <button onClick={() => {
+            if (username && password) {
+                const port = accounts.reduce((port, account) => Math.max(port, account.port + 1), 25565);
+                setAccounts([...accounts, { username, password, port }]);
+                setUsername('');
+                setPassword('');
+            }
+        }}>Add account</button>
+        <button disabled={!accounts.length} onClick={() => beginMulti(accounts, apiKey || undefined)}>Start all</button>
     </>);
 };
 
@@ -1,3 +1,4 @@
+import { listen } from '@tauri-apps/api/event';
 import { invoke, InvokeArgs } from '@tauri-apps/api/tauri';
 
 interface BeginProxyOptions {
@@ -10,6 +11,37 @@ export function begin(opts?: BeginProxyOptions) {
     return invoke<void>('begin', opts as InvokeArgs);
 }
 
+export interface Account {
+    username: string;
+    password: string;
+    port: number;
+}
+
+// Runs one proxy per account, each on its own port
+export function beginMulti(accounts: Account[], apiKey?: string) {
+    return invoke<void>('begin_multi', { accounts, apiKey });
+}
+
 export function msFlow(apiKey?: string) {
     return invoke<void>('ms_flow', { apiKey });
-}
\ No newline at end of file
+}
+
+// Base64 encoded PNG, for use in a data URL
+export function getPlayerFace(uuid: string) {
+    return invoke<string>('get_player_face', { uuid });
+}
+
+export function logout() {
+    return invoke<void>('logout');
+}
+
+export type ProxyStatus =
+    | { status: 'ready' }
+    | { status: 'connected', server: string }
+    | { status: 'disconnected' }
+    | { status: 'error', message: string };
+
+// Resolves to a function that stops listening
+export function onProxyStatusChanged(callback: (status: ProxyStatus) => void) {
+    return listen<ProxyStatus>('proxy-status-changed', event => callback(event.payload));
+}
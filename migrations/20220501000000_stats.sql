@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS stats (
+    uuid TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    fkdr REAL,
+    wlr REAL,
+    level REAL,
+    PRIMARY KEY (uuid, timestamp)
+);
@@ -3,78 +3,299 @@
     windows_subsystem = "windows"
 )]
 
-use mc_proxy::{start, StartConfig};
+mod proxy_state;
+
+use mc_proxy::{
+    start_multi, start_oneshot, Config, ConnectionError, ProxyOptions, ProxyStatus, StartConfig,
+};
+use proxy_state::ProxyState;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_vec_pretty};
+use serde_json::{from_str, to_string, to_vec_pretty};
 use std::{
+    collections::HashMap,
     env::current_dir,
     fs::{create_dir_all, read_to_string, write},
     path::PathBuf,
-    net::TcpListener,
-    io::{Read, Write}
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    io::{Read, Write},
+    sync::Mutex,
+};
+use tauri::{
+    api::path::app_dir, AppHandle, ClipboardManager, CustomMenuItem, Icon, Manager, SystemTray,
+    SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem, WindowEvent,
 };
-use tauri::{api::path::app_dir, Manager};
 use percent_encoding::percent_decode_str;
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 struct AppState {
     username: Option<String>,
     password: Option<String>,
+    // Invalidated on logout
+    yggdrasil_access_token: Option<String>,
     api_key: Option<String>,
     access_token: Option<String>,
     file_path: Option<PathBuf>,
 }
 
+// Proxy settings that can be edited by hand and applied with /reload
+fn settings_path(state: &AppState) -> PathBuf {
+    state.file_path.as_ref().unwrap().with_file_name("settings.json")
+}
+
+// Stats snapshots for /history
+fn stats_db_path(state: &AppState) -> PathBuf {
+    state.file_path.as_ref().unwrap().with_file_name("stats.db")
+}
+
+// Players added with /watch
+fn watchlist_path(state: &AppState) -> PathBuf {
+    state
+        .file_path
+        .as_ref()
+        .unwrap()
+        .with_file_name("watchlist.json")
+}
+
+// The headless proxy's config, used instead of the saved account if it exists
+fn toml_config_path(state: &AppState) -> PathBuf {
+    state
+        .file_path
+        .as_ref()
+        .unwrap()
+        .with_file_name("mc-proxy.toml")
+}
+
+// The files next to the saved state, and status updates sent to the frontend
+fn proxy_options(app: &AppHandle, state: &AppState) -> ProxyOptions {
+    let app = app.clone();
+    ProxyOptions::new()
+        .config_path(settings_path(state))
+        .stats_db_path(stats_db_path(state))
+        .watchlist_path(watchlist_path(state))
+        .on_status(move |status| emit_status(&app, status))
+}
+
+// Sent to the frontend and the tray whenever the proxy's status changes
+const STATUS_EVENT: &str = "proxy-status-changed";
+
+fn emit_status(app: &AppHandle, status: ProxyStatus) {
+    let _ = app.emit_all(STATUS_EVENT, &status);
+    app.trigger_global(STATUS_EVENT, to_string(&status).ok());
+}
+
+// Grey while idle, green while playing and red once the proxy has stopped
+fn update_tray(app: &AppHandle, status: &ProxyStatus) {
+    let icon: &[u8] = match status {
+        ProxyStatus::Ready | ProxyStatus::Disconnected => {
+            include_bytes!("../icons/tray-disconnected.png")
+        }
+        ProxyStatus::Connected { .. } => include_bytes!("../icons/tray-connected.png"),
+        ProxyStatus::Error { .. } => include_bytes!("../icons/tray-error.png"),
+    };
+    let _ = app.tray_handle().set_icon(Icon::Raw(icon.to_vec()));
+    app.state::<ProxyState>().update(status);
+}
+
+fn tray() -> SystemTray {
+    SystemTray::new().with_menu(
+        SystemTrayMenu::new()
+            .add_item(CustomMenuItem::new("show", "Show"))
+            .add_item(CustomMenuItem::new("reconnect", "Reconnect"))
+            .add_item(CustomMenuItem::new("copy_server", "Copy current server"))
+            .add_native_item(SystemTrayMenuItem::Separator)
+            .add_item(CustomMenuItem::new("quit", "Quit")),
+    )
+}
+
+fn on_tray_event(app: &AppHandle, event: SystemTrayEvent) {
+    if let SystemTrayEvent::MenuItemClick { id, .. } = event {
+        match id.as_str() {
+            "show" => {
+                let window = app.get_window("main").unwrap();
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+            // Restarts the proxy if it is running, and otherwise starts it
+            // again with the saved account, e.g. after an error
+            "reconnect" => {
+                if !app.state::<ProxyState>().restart() {
+                    let app = app.clone();
+                    tauri::async_runtime::spawn(async move {
+                        let _ = begin(app.clone(), app.state(), None, None, None, None).await;
+                    });
+                }
+            }
+            "copy_server" => {
+                let server = app.state::<ProxyState>().server();
+                if let Some(server) = server {
+                    let _ = app.clipboard_manager().write_text(server);
+                }
+            }
+            "quit" => app.exit(0),
+            _ => {}
+        }
+    }
+}
+
+fn describe_start_error(err: Box<dyn std::error::Error + Send + Sync + 'static>) -> String {
+    match err.downcast_ref::<ConnectionError>() {
+        Some(ConnectionError::PortInUse { .. }) => {
+            format!("Please close Minecraft before starting the proxy: {}", err)
+        }
+        _ => err.to_string(),
+    }
+}
+
 #[tauri::command]
 async fn begin(
+    app: AppHandle,
     state: tauri::State<'_, AppState>,
     access_token: Option<String>,
     username: Option<String>,
     password: Option<String>,
     api_key: Option<String>,
 ) -> Result<(), String> {
-    if let Some(api_key) = api_key.or(state.api_key.as_ref().cloned()) {
-        if let Some(access_token) = access_token.or(state.access_token.as_ref().cloned()) {
-            write(
-                state.file_path.as_ref().unwrap(),
-                to_vec_pretty(&AppState {
-                    access_token: Some(access_token.clone()),
+    let res = app
+        .state::<ProxyState>()
+        .run(|| {
+            run_proxy(
+                &app,
+                &state,
+                access_token.clone(),
+                username.clone(),
+                password.clone(),
+                api_key.clone(),
+            )
+        })
+        .await;
+    let res = match res {
+        Some(res) => res,
+        None => return Err("the proxy is already running".into()),
+    };
+    if let Err(ref message) = res {
+        emit_status(&app, ProxyStatus::Error { message: message.clone() });
+    }
+    res
+}
+
+// Only returns once the proxy stops, which Reconnect can do in the meantime
+async fn run_until_stopped(
+    app: &AppHandle,
+    config: StartConfig,
+    api_key: String,
+    options: ProxyOptions,
+) -> Result<(), String> {
+    let (handle, shutdown) = start_oneshot(config, api_key, options)
+        .await
+        .map_err(describe_start_error)?;
+    let proxy = app.state::<ProxyState>();
+    proxy.set_shutdown(Some(shutdown));
+    let res = handle.await;
+    proxy.set_shutdown(None);
+    res.map_err(|err| err.to_string())?
+        .map_err(describe_start_error)
+}
+
+async fn run_proxy(
+    app: &AppHandle,
+    state: &AppState,
+    access_token: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    api_key: Option<String>,
+) -> Result<(), String> {
+    let toml_path = toml_config_path(state);
+    if toml_path.exists() {
+        let (config, api_key, options) = Config::from_file(&toml_path)
+            .map_err(|err| format!("{}: {}", toml_path.display(), err))?
+            .into_parts();
+        let status_app = app.clone();
+        let options = options.on_status(move |status| emit_status(&status_app, status));
+        return run_until_stopped(app, config, api_key, options).await;
+    }
+    let api_key = api_key
+        .or(state.api_key.as_ref().cloned())
+        .ok_or("no API key")?;
+    let (config, saved) = match access_token.or(state.access_token.as_ref().cloned()) {
+        Some(access_token) => (
+            StartConfig::Microsoft {
+                access_token: access_token.clone(),
+            },
+            AppState {
+                access_token: Some(access_token),
+                api_key: Some(api_key.clone()),
+                ..Default::default()
+            },
+        ),
+        None => {
+            let username = username
+                .or(state.username.as_ref().cloned())
+                .ok_or("no username")?;
+            let password = password
+                .or(state.password.as_ref().cloned())
+                .ok_or("no password")?;
+            (
+                StartConfig::Yggdrasil {
+                    username: username.clone(),
+                    password: password.clone(),
+                },
+                AppState {
+                    username: Some(username),
+                    password: Some(password),
                     api_key: Some(api_key.clone()),
                     ..Default::default()
-                })
-                .unwrap(),
+                },
             )
-            .map_err(|err| err.to_string())?;
-            start(StartConfig::Microsoft { access_token }, api_key)
-                .await
-                .map_err(|err| err.to_string())
-        } else {
-            if let Some(username) = username.or(state.username.as_ref().cloned()) {
-                if let Some(password) = password.or(state.password.as_ref().cloned()) {
-                    write(
-                        state.file_path.as_ref().unwrap(),
-                        to_vec_pretty(&AppState {
-                            username: Some(username.clone()),
-                            password: Some(password.clone()),
-                            api_key: Some(api_key.clone()),
-                            ..Default::default()
-                        })
-                        .unwrap(),
-                    )
-                    .map_err(|err| err.to_string())?;
-                    start(StartConfig::Yggdrasil { username, password }, api_key)
-                        .await
-                        .map_err(|err| err.to_string())
-                } else {
-                    Err("no password".into())
-                }
-            } else {
-                Err("no username".into())
-            }
         }
-    } else {
-        Err("no API key".into())
-    }
+    };
+    let file_path = state.file_path.clone().unwrap();
+    write(&file_path, to_vec_pretty(&saved).unwrap()).map_err(|err| err.to_string())?;
+    let options = proxy_options(app, state).on_access_token(move |access_token| {
+        let saved = AppState {
+            yggdrasil_access_token: Some(access_token.to_string()),
+            ..saved.clone()
+        };
+        let _ = write(&file_path, to_vec_pretty(&saved).unwrap());
+    });
+    run_until_stopped(app, config, api_key, options).await
+}
+
+#[derive(Deserialize)]
+struct Account {
+    username: String,
+    password: String,
+    port: u16,
+}
+
+// Accounts aren't saved, unlike with begin
+#[tauri::command]
+async fn begin_multi(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    accounts: Vec<Account>,
+    api_key: Option<String>,
+) -> Result<(), String> {
+    let api_key = api_key
+        .or(state.api_key.as_ref().cloned())
+        .ok_or("no API key")?;
+    start_multi(
+        accounts
+            .into_iter()
+            .map(|account| {
+                (
+                    StartConfig::Yggdrasil {
+                        username: account.username,
+                        password: account.password,
+                    },
+                    api_key.clone(),
+                    SocketAddr::from((Ipv4Addr::LOCALHOST, account.port)),
+                )
+            })
+            .collect(),
+        proxy_options(&app, &state),
+    )
+    .await
+    .map_err(describe_start_error)
 }
 
 #[tauri::command]
@@ -100,12 +321,44 @@ async fn ms_flow(state: tauri::State<'_, AppState>, window: tauri::Window, api_k
             percent_decode_str(encoded).decode_utf8().ok()
         }).ok_or("failed to extract access token")?.into_owned();
         target.hide().map_err(|err| err.to_string())?;
-        begin(state, Some(access_token), None, None, Some(api_key)).await
+        begin(window.app_handle(), state, Some(access_token), None, None, Some(api_key)).await
     } else {
         Err("no API key".to_string())
     }
 }
 
+// Base64 PNGs by UUID, kept for the whole session
+#[derive(Default)]
+struct FaceCache(Mutex<HashMap<String, String>>);
+
+#[tauri::command]
+async fn get_player_face(faces: tauri::State<'_, FaceCache>, uuid: String) -> Result<String, String> {
+    if let Some(face) = faces.0.lock().unwrap().get(&uuid) {
+        return Ok(face.clone());
+    }
+    let face = base64::encode(mc_proxy::player_face(&uuid).await.map_err(|err| err.to_string())?);
+    faces.0.lock().unwrap().insert(uuid, face.clone());
+    Ok(face)
+}
+
+#[tauri::command]
+async fn logout(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let file_path = state.file_path.as_ref().unwrap();
+    // begin() may have saved newer credentials than the ones loaded on startup
+    let saved: AppState = read_to_string(file_path).as_deref()
+        .map_or(Ok(Default::default()), from_str)
+        .unwrap_or_default();
+    if let (Some(username), Some(password)) = (saved.username, saved.password) {
+        mc_proxy::logout(
+            StartConfig::Yggdrasil { username, password },
+            saved.yggdrasil_access_token,
+        )
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    write(file_path, to_vec_pretty(&AppState::default()).unwrap()).map_err(|err| err.to_string())
+}
+
 fn main() {
     let ctx = tauri::generate_context!();
     let mut conf_file = app_dir(ctx.config()).unwrap_or(current_dir().unwrap());
@@ -117,7 +370,29 @@ fn main() {
     state.file_path = Some(conf_file);
     tauri::Builder::default()
         .manage(state)
-        .invoke_handler(tauri::generate_handler![begin, ms_flow])
+        .manage(FaceCache::default())
+        .manage(ProxyState::default())
+        .system_tray(tray())
+        .on_system_tray_event(on_tray_event)
+        .setup(|app| {
+            let handle = app.handle();
+            app.listen_global(STATUS_EVENT, move |event| {
+                if let Some(status) = event.payload().and_then(|payload| from_str(payload).ok()) {
+                    update_tray(&handle, &status);
+                }
+            });
+            Ok(())
+        })
+        // Closing the window keeps the proxy running in the tray
+        .on_window_event(|event| {
+            if let WindowEvent::CloseRequested { api, .. } = event.event() {
+                if event.window().label() == "main" {
+                    let _ = event.window().hide();
+                    api.prevent_close();
+                }
+            }
+        })
+        .invoke_handler(tauri::generate_handler![begin, begin_multi, ms_flow, get_player_face, logout])
         .run(ctx)
         .expect("error while running tauri application");
 }
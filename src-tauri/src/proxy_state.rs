@@ -0,0 +1,132 @@
+use mc_proxy::{ProxyStatus, ShutdownTrigger};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+// Whether the proxy is running, and the server the local client is on if any.
+// Kept apart from Tauri so the restart loop can be tested.
+#[derive(Default)]
+pub struct ProxyState {
+    running: AtomicBool,
+    // Stops the running proxy, once it is listening
+    shutdown: Mutex<Option<ShutdownTrigger>>,
+    // Makes run start the proxy again after it stops
+    restart: AtomicBool,
+    server: Mutex<Option<String>>,
+}
+
+impl ProxyState {
+    // Starts the proxy again each time it stops after restart was called, and
+    // returns how it stopped otherwise. None if it was already running.
+    pub async fn run<F: Future<Output = Result<(), String>>>(
+        &self,
+        mut start: impl FnMut() -> F,
+    ) -> Option<Result<(), String>> {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        let res = loop {
+            let res = start().await;
+            if !self.restart.swap(false, Ordering::SeqCst) {
+                break res;
+            }
+        };
+        self.running.store(false, Ordering::SeqCst);
+        Some(res)
+    }
+
+    // Set while the proxy is listening
+    pub fn set_shutdown(&self, shutdown: Option<ShutdownTrigger>) {
+        *self.shutdown.lock().unwrap() = shutdown;
+    }
+
+    // Stops the proxy so run starts it again. False if it isn't listening,
+    // e.g. after an error, in which case it has to be started from scratch.
+    pub fn restart(&self) -> bool {
+        let shutdown = self.shutdown.lock().unwrap().clone();
+        match shutdown {
+            Some(shutdown) => {
+                self.restart.store(true, Ordering::SeqCst);
+                shutdown.shutdown();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn update(&self, status: &ProxyStatus) {
+        *self.server.lock().unwrap() = match status {
+            ProxyStatus::Connected { server } => Some(server.clone()),
+            _ => None,
+        };
+    }
+
+    // For Copy current server in the tray
+    pub fn server(&self) -> Option<String> {
+        self.server.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProxyState;
+    use mc_proxy::{ProxyStatus, ShutdownTrigger};
+    use std::cell::Cell;
+    use tauri::async_runtime::block_on;
+
+    #[test]
+    fn restart_loop() {
+        let state = ProxyState::default();
+        let runs = Cell::new(0);
+        let res = block_on(state.run(|| {
+            runs.set(runs.get() + 1);
+            let state = &state;
+            let run = runs.get();
+            async move {
+                if run == 1 {
+                    // As if Reconnect was clicked while listening
+                    let shutdown = ShutdownTrigger::default();
+                    state.set_shutdown(Some(shutdown.clone()));
+                    assert!(state.restart());
+                    assert!(shutdown.is_shutdown());
+                    state.set_shutdown(None);
+                    Ok(())
+                } else {
+                    Err("stopped".to_string())
+                }
+            }
+        }));
+        assert_eq!(res, Some(Err("stopped".to_string())));
+        assert_eq!(runs.get(), 2);
+
+        // Not listening, so nothing to restart
+        assert!(!state.restart());
+        assert_eq!(block_on(state.run(|| async { Ok(()) })), Some(Ok(())));
+    }
+
+    #[test]
+    fn already_running() {
+        let state = ProxyState::default();
+        let res = block_on(state.run(|| async {
+            assert_eq!(state.run(|| async { Ok(()) }).await, None);
+            Ok(())
+        }));
+        assert_eq!(res, Some(Ok(())));
+        assert_eq!(block_on(state.run(|| async { Ok(()) })), Some(Ok(())));
+    }
+
+    #[test]
+    fn current_server() {
+        let state = ProxyState::default();
+        state.update(&ProxyStatus::Connected {
+            server: "mc.hypixel.net".into(),
+        });
+        assert_eq!(state.server().as_deref(), Some("mc.hypixel.net"));
+        state.update(&ProxyStatus::Disconnected);
+        assert_eq!(state.server(), None);
+    }
+}
@@ -0,0 +1,61 @@
+use mc_proxy::{dump_packets, export_pcap, DEFAULT_UPSTREAM};
+use std::{
+    env,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+const USAGE: &str = "usage: packet_dumper [--listen <port>] [--upstream <host[:port]>] \
+    [--capture <file>]\n       packet_dumper --export-pcap <capture> <output.pcap>";
+const DEFAULT_LISTEN_PORT: u16 = 25566;
+
+#[tokio::main]
+async fn main() {
+    let mut listen_port = DEFAULT_LISTEN_PORT;
+    let mut upstream = DEFAULT_UPSTREAM.to_string();
+    let mut capture = None;
+    let mut args = env::args().skip(1);
+    if env::args().nth(1).as_deref() == Some("--export-pcap") {
+        let paths = args.skip(1).collect::<Vec<_>>();
+        if paths.len() != 2 {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+        if let Err(err) = export_pcap(Path::new(&paths[0]), Path::new(&paths[1])) {
+            eprintln!("{}", err);
+            exit(1);
+        }
+        return;
+    }
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) if arg.starts_with("--") => value,
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(2);
+            }
+        };
+        match arg.as_str() {
+            "--listen" => match value.parse() {
+                Ok(port) => listen_port = port,
+                Err(_) => {
+                    eprintln!("invalid port {:?}", value);
+                    exit(2);
+                }
+            },
+            "--upstream" => upstream = value,
+            "--capture" => capture = Some(PathBuf::from(value)),
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(2);
+            }
+        }
+    }
+    let listen = SocketAddr::from((Ipv4Addr::LOCALHOST, listen_port));
+    println!("Dumping packets from {} to {}", listen, upstream);
+    if let Err(err) = dump_packets(listen, upstream, capture.as_deref()).await {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
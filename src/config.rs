@@ -0,0 +1,264 @@
+use crate::{
+    connection::packets::play::{
+        CHAT_POSITION_ACTION_BAR, CHAT_POSITION_CHAT, CHAT_POSITION_SYSTEM,
+    },
+    protocol::types::{Color, UUID},
+    StartConfig, LOCAL_PORT,
+};
+use serde::Deserialize;
+use serde_json::{Error as JSONError, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{read_to_string, write},
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tokio::io::Error as IOError;
+use toml::de::Error as TOMLError;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error")]
+    IO(#[from] IOError),
+    #[error("invalid configuration file")]
+    JSON(#[from] JSONError),
+    #[error("invalid configuration file: {0}")]
+    TOML(#[from] TOMLError),
+}
+
+fn default_listen_addr() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, LOCAL_PORT))
+}
+
+// What the headless proxy starts with, read from mc-proxy.toml. Unlike
+// DynamicConfig, changes need a restart. Unknown keys are an error, since
+// the file is only read on startup.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_listen_addr")]
+    pub listen_addr: SocketAddr,
+    // Hypixel if unset
+    pub upstream: Option<String>,
+    pub credentials: StartConfig,
+    pub api_key: String,
+    // Base URL of the session server logins are announced to, for accounts
+    // from third-party launchers. Mojang's if unset.
+    pub session_server: Option<String>,
+    // Reserved for compressing packets to the local client
+    pub compression_level: Option<u32>,
+    // How many local clients can be connected at once; more are turned away
+    pub max_connections: Option<usize>,
+    // Reserved for chat logging
+    pub log_path: Option<PathBuf>,
+    // Expect a PROXY protocol header on every connection, for running behind
+    // a load balancer
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    // The DynamicConfig JSON file, applied again by /reload
+    pub stat_config: Option<PathBuf>,
+    pub stats_db_path: Option<PathBuf>,
+    pub watchlist_path: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        let config: Config = toml::from_str(&read_to_string(path)?)?;
+        for key in config.reserved_keys() {
+            println!("Warning: {} is not supported yet and is ignored", key);
+        }
+        Ok(config)
+    }
+
+    // Reserved settings that were set anyway
+    fn reserved_keys(&self) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+        if self.compression_level.is_some() {
+            keys.push("compression_level");
+        }
+        if self.log_path.is_some() {
+            keys.push("log_path");
+        }
+        keys
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatTier {
+    pub min_fkdr: f64,
+    pub color: Color,
+}
+
+// Where the proxy's own messages, e.g. /stats replies, show up
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum ProxyMessagePosition {
+    Chat = CHAT_POSITION_CHAT,
+    System = CHAT_POSITION_SYSTEM,
+    // Shown one at a time, since each message replaces the last
+    ActionBar = CHAT_POSITION_ACTION_BAR,
+}
+
+// Settings that take effect on the next packet after /reload. Everything else
+// (the account, the listen address, the upstream server) needs a restart.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DynamicConfig {
+    // /stats * reports players above this in party chat
+    pub fkdr_threshold: f64,
+    // Colors FKDRs in /stats output; the highest matching tier wins
+    pub stat_tiers: Vec<StatTier>,
+    // Always reported by /stats, matched case-insensitively
+    pub blocked_players: Vec<String>,
+    // Overrides the API key the proxy was started with
+    pub api_key: Option<String>,
+    // Reserved for chat logging
+    pub log_path: Option<PathBuf>,
+    // Where /report keeps its own copy of reports, one JSON object per line
+    pub report_log_path: Option<PathBuf>,
+    // Where /export saves files, the home directory if unset
+    pub export_dir: Option<PathBuf>,
+    // Seconds a write to the local client may block for before the
+    // connection is dropped, e.g. when the game has frozen. Applies to
+    // connections made after /reload; unset waits forever.
+    pub client_write_timeout: Option<u64>,
+    // Upload speed in kilobits per second. When set, packets to the server
+    // are only compressed where that saves time at this speed and the ping
+    // the server reports, which is checked on every keep alive. Applies to
+    // connections made after /reload.
+    pub upload_bandwidth_kbps: Option<u64>,
+    // Shows the stats of everyone who joins the party, and enables /pstats
+    pub auto_party_stats: bool,
+    pub message_position: ProxyMessagePosition,
+    // Keys this version doesn't know, e.g. from a newer one, which are
+    // warned about rather than failing /reload
+    #[serde(flatten)]
+    unknown: HashMap<String, Value>,
+}
+
+impl Default for DynamicConfig {
+    fn default() -> Self {
+        DynamicConfig {
+            fkdr_threshold: 2.0,
+            stat_tiers: Vec::new(),
+            blocked_players: Vec::new(),
+            api_key: None,
+            log_path: None,
+            report_log_path: None,
+            export_dir: None,
+            client_write_timeout: None,
+            upload_bandwidth_kbps: None,
+            auto_party_stats: false,
+            message_position: ProxyMessagePosition::System,
+            unknown: HashMap::new(),
+        }
+    }
+}
+
+impl DynamicConfig {
+    pub fn from_file(path: &Path) -> Result<DynamicConfig, Error> {
+        let config: DynamicConfig = serde_json::from_str(&read_to_string(path)?)?;
+        for key in config.ignored_keys() {
+            println!("Warning: ignoring the {} setting", key);
+        }
+        Ok(config)
+    }
+
+    // Unknown keys, and reserved ones that were set anyway
+    fn ignored_keys(&self) -> Vec<&str> {
+        let mut keys = self.unknown.keys().map(String::as_str).collect::<Vec<_>>();
+        if self.log_path.is_some() {
+            keys.push("log_path");
+        }
+        keys.sort_unstable();
+        keys
+    }
+
+    pub fn fkdr_color(&self, fkdr: f64) -> Option<Color> {
+        self.stat_tiers
+            .iter()
+            .filter(|tier| fkdr >= tier.min_fkdr)
+            .max_by(|a, b| a.min_fkdr.total_cmp(&b.min_fkdr))
+            .map(|tier| tier.color)
+    }
+
+    pub fn is_blocked(&self, username: &str) -> bool {
+        self.blocked_players
+            .iter()
+            .any(|blocked| blocked.eq_ignore_ascii_case(username))
+    }
+}
+
+// The watchlist is a JSON array of UUIDs, and a missing file is an empty one
+pub fn load_watchlist(path: &Path) -> Result<HashSet<UUID>, Error> {
+    match read_to_string(path) {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(HashSet::new()),
+        res => Ok(serde_json::from_str(&res?)?),
+    }
+}
+
+pub fn save_watchlist(path: &Path, watchlist: &HashSet<UUID>) -> Result<(), Error> {
+    let mut uuids = watchlist.iter().map(UUID::to_string).collect::<Vec<_>>();
+    // Sorted so the file doesn't change on every save
+    uuids.sort();
+    write(path, serde_json::to_vec_pretty(&uuids)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, DynamicConfig};
+    use crate::StartConfig;
+
+    #[test]
+    fn toml_config() {
+        let config: Config = toml::from_str(
+            r#"
+            upstream = "localhost:25566"
+            api_key = "key"
+            stat_config = "settings.json"
+
+            [credentials]
+            type = "microsoft"
+            access_token = "token"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.listen_addr.to_string(), "127.0.0.1:25565");
+        assert_eq!(config.upstream.as_deref(), Some("localhost:25566"));
+        assert!(matches!(
+            config.credentials,
+            StartConfig::Microsoft { ref access_token } if access_token == "token"
+        ));
+        assert!(config.watchlist_path.is_none());
+        assert!(!config.proxy_protocol);
+
+        assert!(toml::from_str::<Config>("api_key = \"key\"").is_err());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let config = r#"
+            api_key = "key"
+            compression_level = 6
+
+            [credentials]
+            type = "microsoft"
+            access_token = "token"
+            "#;
+        assert_eq!(
+            toml::from_str::<Config>(config).unwrap().reserved_keys(),
+            ["compression_level"]
+        );
+        assert!(toml::from_str::<Config>(&config.replace("compression_level", "typo")).is_err());
+
+        let settings: DynamicConfig =
+            serde_json::from_str(r#"{"fkdr_threshold":3,"log_path":"chat.log","newer":true}"#)
+                .unwrap();
+        assert_eq!(settings.fkdr_threshold, 3.0);
+        assert_eq!(settings.ignored_keys(), ["log_path", "newer"]);
+    }
+}
@@ -5,26 +5,47 @@ use super::{
 };
 use crate::protocol::{
     error::Error as ProtocolError,
-    types::{Decode, Encode, VarInt},
+    types::{varint_to_bytes, Decode, Encode, VarInt},
     version::ProtocolVersion,
 };
 use async_compression::tokio::{bufread::ZlibDecoder, write::ZlibEncoder};
+use futures::stream::{self, Stream};
 use std::{
     cmp::{max, min},
-    future::Future,
+    collections::VecDeque,
     io::{Cursor, IoSlice},
+    mem::take,
     pin::Pin,
+    slice,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::{
     io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf},
-    pin,
+    time::timeout,
 };
 
+// Servers send a keep alive every 15 seconds, so a connection that has been
+// silent for much longer than that has hung
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+// Packet sizes kept for adaptive_threshold
+const RECENT_PACKET_SIZES: usize = 10;
+// Rough cost of zlib at the default level, as a fixed cost per packet and a
+// cost per byte, in nanoseconds
+const ZLIB_PACKET_COST_NS: f64 = 5000.0;
+const ZLIB_BYTE_COST_NS: f64 = 10.0;
+// Share of the bytes compression is assumed to save
+const ZLIB_SAVINGS: f64 = 0.5;
+// No packet is bigger, so nothing gets compressed
+const MAX_THRESHOLD: usize = 2097151;
+
 pub struct InboundConnection<R: AsyncReadExt + Unpin> {
     pub(super) conn: Decryptor<R>,
     pub(super) version: ProtocolVersion,
     pub(super) compressed: bool,
+    pub(super) read_timeout: Option<Duration>,
+    // Read by peek_first_byte, and the start of the next packet
+    peeked: Option<u8>,
 }
 
 pub enum IncomingInnerPacket<R: AsyncReadExt + Unpin> {
@@ -45,36 +66,45 @@ impl<R: AsyncReadExt + Unpin> AsyncRead for IncomingInnerPacket<R> {
     }
 }
 
+// Skips fields that aren't needed without allocating for them. Also works on
+// buffered packets, unlike IncomingInnerPacket::drain.
+pub async fn drain_exact<R: AsyncRead + Unpin>(src: &mut R, mut count: usize) -> io::Result<()> {
+    let mut buf = [0; 512];
+    while count > 0 {
+        let len = min(count, buf.len());
+        src.read_exact(&mut buf[..len]).await?;
+        count -= len;
+    }
+    Ok(())
+}
+
 impl<R: AsyncReadExt + Unpin> IncomingInnerPacket<R> {
-    pub async fn close(&mut self) -> Result<bool, Error> {
-        match self {
-            IncomingInnerPacket::Normal(reader) => {
-                if reader.remaining() == 0 {
-                    Ok(true)
-                } else {
-                    let mut buf = Vec::with_capacity(reader.remaining());
-                    reader.read_to_end(&mut buf).await?;
-                    Ok(false)
-                }
+    pub async fn drain(&mut self, count: usize) -> Result<(), Error> {
+        drain_exact(self, count).await?;
+        Ok(())
+    }
+
+    // Returns how many bytes were left
+    pub async fn drain_to_end(&mut self) -> Result<usize, Error> {
+        let mut buf = [0; 512];
+        let mut total = 0;
+        loop {
+            match self.read(&mut buf).await? {
+                0 => return Ok(total),
+                len => total += len,
             }
-            IncomingInnerPacket::Decompressed(reader) => {
-                if reader.remaining() == 0 {
-                    if reader.get_ref().get_ref().get_ref().remaining() == 0 {
-                        Ok(true)
-                    } else {
-                        Err(ProtocolError::Malformed.into())
-                    }
-                } else {
-                    let mut buf = Vec::with_capacity(reader.remaining());
-                    reader.read_to_end(&mut buf).await?;
-                    if reader.get_ref().get_ref().get_ref().remaining() == 0 {
-                        Ok(false)
-                    } else {
-                        Err(ProtocolError::Malformed.into())
-                    }
-                }
+        }
+    }
+
+    pub async fn close(&mut self) -> Result<bool, Error> {
+        let complete = self.drain_to_end().await? == 0;
+        if let IncomingInnerPacket::Decompressed(reader) = self {
+            // The decompressed size didn't match the compressed data
+            if reader.get_ref().get_ref().get_ref().remaining() != 0 {
+                return Err(ProtocolError::Malformed.into());
             }
         }
+        Ok(complete)
     }
 
     pub fn finished(&self) -> Result<(), Error> {
@@ -103,17 +133,168 @@ pub struct IncomingPacket<'a, R: AsyncReadExt + Unpin> {
     pub content: IncomingInnerPacket<&'a mut Decryptor<R>>,
 }
 
+// A packet decoded all at once rather than streamed
+#[derive(Clone, Debug)]
+pub struct TypedPacket<P> {
+    pub id: i32,
+    pub packet: P,
+}
+
+// A packet buffered in full, so that it can outlive the connection borrow,
+// e.g. to be queued or inspected before being forwarded
+#[derive(Clone, Debug)]
+pub struct OwnedPacket {
+    pub id: i32,
+    pub content: Vec<u8>,
+    position: usize,
+}
+
+impl OwnedPacket {
+    pub fn new(id: i32, content: Vec<u8>) -> OwnedPacket {
+        OwnedPacket {
+            id,
+            content,
+            position: 0,
+        }
+    }
+
+    // Like IncomingPacket::parse, but the content is kept so the packet can
+    // still be forwarded as it was
+    pub async fn parse<P: for<'b> Decode<'b, Cursor<Vec<u8>>>>(
+        &mut self,
+        version: ProtocolVersion,
+    ) -> Result<TypedPacket<P>, ProtocolError> {
+        let mut content = Cursor::new(take(&mut self.content));
+        let packet = P::decode(&mut content, version).await;
+        let complete = content.position() as usize == content.get_ref().len();
+        self.content = content.into_inner();
+        let packet = packet?;
+        if !complete {
+            return Err(ProtocolError::Malformed);
+        }
+        Ok(TypedPacket {
+            id: self.id,
+            packet,
+        })
+    }
+}
+
+impl AsyncRead for OwnedPacket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let rest = &self.content[self.position..];
+        let amt = min(rest.len(), buf.remaining());
+        buf.put_slice(&rest[..amt]);
+        self.position += amt;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncReadExt + Unpin> IncomingPacket<'_, R> {
+    // Reads whatever is left of the content
+    pub async fn into_owned(mut self) -> Result<OwnedPacket, Error> {
+        let mut content = Vec::with_capacity(self.len);
+        self.content.read_to_end(&mut content).await?;
+        self.content.finished()?;
+        Ok(OwnedPacket::new(self.id, content))
+    }
+
+    // Buffers the whole packet first, so best kept to packets that are small or
+    // needed in full anyway
+    pub async fn parse<P: for<'b> Decode<'b, Cursor<Vec<u8>>>>(
+        mut self,
+        version: ProtocolVersion,
+    ) -> Result<TypedPacket<P>, Error> {
+        let mut buf = Vec::with_capacity(self.len);
+        self.content.read_to_end(&mut buf).await?;
+        self.content.finished()?;
+        let mut content = Cursor::new(buf);
+        let packet = P::decode(&mut content, version).await?;
+        if content.position() as usize != content.get_ref().len() {
+            return Err(ProtocolError::Malformed.into());
+        }
+        Ok(TypedPacket {
+            id: self.id,
+            packet,
+        })
+    }
+}
+
 impl<R: AsyncReadExt + Unpin> InboundConnection<R> {
     pub fn new(reader: R, version: ProtocolVersion) -> InboundConnection<R> {
         InboundConnection {
             conn: Decryptor::new(reader),
             version,
             compressed: false,
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            peeked: None,
         }
     }
 
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.conn.buffered_size() + self.peeked.is_some() as usize
+    }
+
+    // The first byte of the next packet, which next_packet still reads. That
+    // is the start of the packet's length, not its ID.
+    pub async fn peek_first_byte(&mut self) -> Result<u8, Error> {
+        if let Some(byte) = self.peeked {
+            return Ok(byte);
+        }
+        let byte = self.conn.read_u8().await?;
+        self.peeked = Some(byte);
+        Ok(byte)
+    }
+
+    // How long next_packet waits for a packet to start before failing with
+    // Error::Timeout. None waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    // For consumers that would rather use stream combinators than a loop.
+    // Every packet is buffered, and the stream ends after the first error,
+    // which includes the connection closing.
+    pub fn into_stream(
+        mut self,
+        version: ProtocolVersion,
+    ) -> impl Stream<Item = Result<OwnedPacket, Error>> {
+        self.set_version(version);
+        stream::try_unfold(self, |mut conn| async move {
+            let packet = conn.next_owned_packet().await?;
+            Ok(Some((packet, conn)))
+        })
+    }
+
+    pub async fn next_owned_packet(&mut self) -> Result<OwnedPacket, Error> {
+        self.next_packet().await?.into_owned().await
+    }
+
     pub async fn next_packet(&mut self) -> Result<IncomingPacket<'_, R>, Error> {
-        let len = VarInt::decode(&mut self.conn, self.version).await?.0;
+        let peeked = self.peeked.take();
+        let mut conn = peeked
+            .as_ref()
+            .map_or(&[][..], slice::from_ref)
+            .chain(&mut self.conn);
+        let len = VarInt::decode(&mut conn, self.version);
+        let len = match self.read_timeout {
+            Some(read_timeout) => timeout(read_timeout, len)
+                .await
+                .map_err(|_| Error::Timeout)??,
+            None => len.await?,
+        }
+        .0;
         if len > 2097151 {
             Err(Error::PacketTooBig(len as usize))
         } else if len < 0 {
@@ -206,7 +387,6 @@ pub enum OutgoingInnerPacket<W: AsyncWriteExt + Unpin> {
     Normal(Limit<W>),
     UnknownLength {
         vec: Vec<u8>,
-        version: ProtocolVersion,
         len: usize,
         shutting_down: bool,
         tgt: W,
@@ -214,7 +394,6 @@ pub enum OutgoingInnerPacket<W: AsyncWriteExt + Unpin> {
     Compressed {
         vec: Limit<MaybeZlibVec>,
         cache: Vec<u8>,
-        version: ProtocolVersion,
         len: usize,
         shutting_down: bool,
         known_len: bool,
@@ -239,7 +418,6 @@ impl<W: AsyncWriteExt + Unpin> OutgoingInnerPacket<W> {
                             MaybeZlibVec::Some(ZlibEncoder::new(Vec::with_capacity(len >> 1))),
                             len,
                         ),
-                        version,
                         len: 0,
                         cache: Vec::new(),
                         shutting_down: false,
@@ -249,7 +427,6 @@ impl<W: AsyncWriteExt + Unpin> OutgoingInnerPacket<W> {
                 } else {
                     Ok(OutgoingInnerPacket::Compressed {
                         vec: Limit::new(MaybeZlibVec::None, len),
-                        version,
                         len: 0,
                         cache: Vec::with_capacity(len + 1),
                         shutting_down: false,
@@ -260,7 +437,6 @@ impl<W: AsyncWriteExt + Unpin> OutgoingInnerPacket<W> {
             } else {
                 Ok(OutgoingInnerPacket::Compressed {
                     vec: Limit::new(MaybeZlibVec::Some(ZlibEncoder::new(Vec::new())), usize::MAX),
-                    version,
                     len: 0,
                     cache: Vec::with_capacity(threshold + 1),
                     shutting_down: false,
@@ -278,7 +454,6 @@ impl<W: AsyncWriteExt + Unpin> OutgoingInnerPacket<W> {
         } else {
             Ok(OutgoingInnerPacket::UnknownLength {
                 vec: Vec::new(),
-                version,
                 len: 0,
                 shutting_down: false,
                 tgt,
@@ -289,10 +464,64 @@ impl<W: AsyncWriteExt + Unpin> OutgoingInnerPacket<W> {
 
 pub type OutgoingPacket<'a, W> = OutgoingInnerPacket<&'a mut Encryptor<W>>;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionMode {
+    // How every connection starts, until the server sets a threshold
+    Disabled,
+    // Everything over the threshold the server set is compressed
+    Server(usize),
+    // Never below the server's threshold, since receivers reject compressed
+    // packets under it, but raised by adaptive_threshold when the network is
+    // fast enough that compressing costs more time than it saves. Packets
+    // over the server's threshold may be sent uncompressed.
+    Adaptive { server: usize, threshold: usize },
+}
+
+impl CompressionMode {
+    // None if nothing is compressed, not even the packet length
+    pub fn threshold(&self) -> Option<usize> {
+        match *self {
+            CompressionMode::Disabled => None,
+            CompressionMode::Server(threshold) | CompressionMode::Adaptive { threshold, .. } => {
+                Some(threshold)
+            }
+        }
+    }
+}
+
+// The smallest packet worth compressing, from how long it takes to send the
+// bytes compression saves against how long compressing takes
+fn break_even_threshold(
+    server: usize,
+    latency_ms: u32,
+    bandwidth_bps: u64,
+    recent_sizes: &VecDeque<usize>,
+) -> usize {
+    if bandwidth_bps == 0 {
+        return server;
+    }
+    let byte_ns = 8e9 / bandwidth_bps as f64;
+    // When sending a typical packet takes a good part of a round trip, the
+    // bandwidth is what holds packets up, so everything possible is compressed
+    if !recent_sizes.is_empty() {
+        let average = recent_sizes.iter().sum::<usize>() as f64 / recent_sizes.len() as f64;
+        if average * byte_ns * 10.0 > f64::from(latency_ms) * 1e6 {
+            return server;
+        }
+    }
+    let saved_byte_ns = ZLIB_SAVINGS * byte_ns - ZLIB_BYTE_COST_NS;
+    if saved_byte_ns <= 0.0 {
+        return MAX_THRESHOLD;
+    }
+    let threshold = (ZLIB_PACKET_COST_NS / saved_byte_ns).min(MAX_THRESHOLD as f64) as usize;
+    max(server, threshold)
+}
+
 pub struct OutboundConnection<W: AsyncWriteExt + Unpin> {
     pub(super) conn: Encryptor<W>,
     pub(super) version: ProtocolVersion,
-    pub(super) compress_threshold: Option<usize>,
+    pub(super) compression: CompressionMode,
+    recent_sizes: VecDeque<usize>,
 }
 
 impl<W: AsyncWriteExt + Unpin> AsyncWrite for OutgoingInnerPacket<W> {
@@ -383,7 +612,6 @@ impl<W: AsyncWriteExt + Unpin> AsyncWrite for OutgoingInnerPacket<W> {
             }),
             OutgoingInnerPacket::UnknownLength {
                 vec,
-                version,
                 len,
                 shutting_down,
                 tgt,
@@ -395,18 +623,9 @@ impl<W: AsyncWriteExt + Unpin> AsyncWrite for OutgoingInnerPacket<W> {
                             Error::PacketTooBig(*len),
                         )));
                     }
-                    let mut encode_buf = [0u8; 5];
-                    let mut encode_buf = Cursor::new(encode_buf.as_mut());
-                    {
-                        let len_encode = VarInt(*len as i32).encode(&mut encode_buf, *version);
-                        pin!(len_encode);
-                        if len_encode.poll(cx) == Poll::Pending {
-                            unreachable!("VarInt encode on cursor was not immediately ready");
-                        }
-                    }
-                    let end_pos = encode_buf.position() as usize;
+                    let (encode_buf, end_pos) = varint_to_bytes(*len as i32);
                     Pin::new(&mut *tgt)
-                        .poll_write(cx, &encode_buf.into_inner()[..end_pos])
+                        .poll_write(cx, &encode_buf[..end_pos])
                         .ready()??;
                     *shutting_down = true;
                 }
@@ -424,7 +643,6 @@ impl<W: AsyncWriteExt + Unpin> AsyncWrite for OutgoingInnerPacket<W> {
             OutgoingInnerPacket::Compressed {
                 vec,
                 cache,
-                version,
                 len,
                 shutting_down,
                 known_len,
@@ -456,26 +674,15 @@ impl<W: AsyncWriteExt + Unpin> AsyncWrite for OutgoingInnerPacket<W> {
                         )));
                     }
 
-                    let mut encode_buf = [0u8; 10];
-                    let mut encode_buf = Cursor::new(encode_buf.as_mut());
-                    {
-                        let total_len_encode =
-                            VarInt(true_len as i32).encode(&mut encode_buf, *version);
-                        pin!(total_len_encode);
-                        if total_len_encode.poll(cx) == Poll::Pending {
-                            unreachable!("VarInt encode on cursor was not immediately ready");
-                        }
-                    }
-                    {
-                        let len_encode = uncompressed_len_varint.encode(&mut encode_buf, *version);
-                        pin!(len_encode);
-                        if len_encode.poll(cx) == Poll::Pending {
-                            unreachable!("VarInt encode on cursor was not immediately ready");
-                        }
-                    }
-                    let end_pos = encode_buf.position() as usize;
+                    // Total length and uncompressed length
+                    let (total_len_buf, total_len_len) = varint_to_bytes(true_len as i32);
+                    let (len_buf, len_len) = varint_to_bytes(uncompressed_len_varint.0);
+                    let mut encode_buf = [0u8; VarInt::MAX_ENCODED_LEN * 2];
+                    encode_buf[..total_len_len].copy_from_slice(&total_len_buf[..total_len_len]);
+                    encode_buf[total_len_len..total_len_len + len_len]
+                        .copy_from_slice(&len_buf[..len_len]);
                     Pin::new(&mut *tgt)
-                        .poll_write(cx, &encode_buf.into_inner()[..end_pos])
+                        .poll_write(cx, &encode_buf[..total_len_len + len_len])
                         .ready()??;
                     *len = compressed.len();
                     *shutting_down = true;
@@ -524,24 +731,442 @@ impl<W: AsyncWriteExt + Unpin> OutboundConnection<W> {
         OutboundConnection {
             conn: Encryptor::new(writer),
             version,
-            compress_threshold: None,
+            compression: CompressionMode::Disabled,
+            recent_sizes: VecDeque::with_capacity(RECENT_PACKET_SIZES),
+        }
+    }
+
+    pub fn compress_threshold(&self) -> Option<usize> {
+        self.compression.threshold()
+    }
+
+    pub fn compression_mode(&self) -> CompressionMode {
+        self.compression
+    }
+
+    // Called when the server sets a threshold, which adaptive mode starts over
+    // from
+    pub(super) fn enable_compression(&mut self, threshold: usize) {
+        self.compression = match self.compression {
+            CompressionMode::Adaptive { .. } => CompressionMode::Adaptive {
+                server: threshold,
+                threshold,
+            },
+            _ => CompressionMode::Server(threshold),
+        };
+    }
+
+    // Lets adaptive_threshold raise the threshold. Returns false if the server
+    // hasn't turned compression on, so there is nothing to adapt.
+    pub fn enable_adaptive_compression(&mut self) -> bool {
+        match self.compression {
+            CompressionMode::Disabled => false,
+            CompressionMode::Server(server) => {
+                self.compression = CompressionMode::Adaptive {
+                    server,
+                    threshold: server,
+                };
+                true
+            }
+            CompressionMode::Adaptive { .. } => true,
+        }
+    }
+
+    // Picks the threshold for the measured network from the sizes of the last
+    // few packets, returning the one now in use. Only changes anything in
+    // adaptive mode.
+    pub fn adaptive_threshold(
+        &mut self,
+        measured_latency_ms: u32,
+        current_bandwidth_bps: u64,
+    ) -> Option<usize> {
+        if let CompressionMode::Adaptive { server, .. } = self.compression {
+            self.compression = CompressionMode::Adaptive {
+                server,
+                threshold: break_even_threshold(
+                    server,
+                    measured_latency_ms,
+                    current_bandwidth_bps,
+                    &self.recent_sizes,
+                ),
+            };
         }
+        self.compression.threshold()
+    }
+
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    // Writes that block for longer fail with Error::Timeout. None, the
+    // default, waits forever.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.conn.set_write_timeout(timeout);
     }
 
     pub async fn create_packet(
         &mut self,
-        id: i32,
+        id: impl Into<i32>,
         len: Option<usize>,
     ) -> Result<OutgoingPacket<'_, W>, Error> {
-        let id = VarInt(id);
+        let id = VarInt(id.into());
+        // Only adaptive_threshold looks at them
+        if let (Some(len), CompressionMode::Adaptive { .. }) = (len, self.compression) {
+            if self.recent_sizes.len() == RECENT_PACKET_SIZES {
+                self.recent_sizes.pop_front();
+            }
+            self.recent_sizes.push_back(len);
+        }
         let mut packet = OutgoingInnerPacket::new(
             &mut self.conn,
             len.map(|s| s + id.len()),
-            self.compress_threshold,
+            self.compression.threshold(),
             self.version,
         )
         .await?;
         id.encode(&mut packet, self.version).await?;
         Ok(packet)
     }
+
+    // Copies the rest of a packet that was read from another connection
+    pub async fn forward_packet<R: AsyncReadExt + Unpin>(
+        &mut self,
+        packet: &mut IncomingPacket<'_, R>,
+    ) -> Result<(), Error> {
+        let mut out_packet = self.create_packet(packet.id, Some(packet.len)).await?;
+        io::copy(&mut packet.content, &mut out_packet).await?;
+        out_packet.shutdown().await?;
+        packet.content.finished()
+    }
+
+    // Owned packets hold the decompressed content, so this compresses it again
+    // if and only if this connection's threshold calls for it, whatever the
+    // connection it was read from did
+    pub async fn emit_owned(&mut self, packet: &OwnedPacket) -> Result<(), Error> {
+        let mut out_packet = self
+            .create_packet(packet.id, Some(packet.content.len()))
+            .await?;
+        out_packet.write_all(&packet.content).await?;
+        out_packet.shutdown().await?;
+        Ok(())
+    }
+
+    // Reads the next packet from inbound and forwards it unchanged, returning
+    // its ID and length
+    pub async fn pipe_packet_from<R: AsyncReadExt + Unpin>(
+        &mut self,
+        inbound: &mut InboundConnection<R>,
+    ) -> Result<(i32, usize), Error> {
+        let mut packet = inbound.next_packet().await?;
+        self.forward_packet(&mut packet).await?;
+        Ok((packet.id, packet.len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompressionMode, Error, InboundConnection, OutboundConnection, OwnedPacket};
+    use crate::{
+        connection::packets::play::KeepAlive,
+        protocol::{
+            types::{Decode, Encode},
+            version::ProtocolVersion,
+        },
+    };
+    use futures::stream::StreamExt;
+    use std::time::Duration;
+    use tokio::{
+        io::{duplex, AsyncWriteExt},
+        test,
+    };
+
+    #[test]
+    async fn write_timeout() {
+        // Nothing reads the other end, so writes block once the pipe is full
+        let (client, _server) = duplex(64);
+        let mut outbound = OutboundConnection::new(client, ProtocolVersion::V1_8_9);
+        outbound.set_write_timeout(Some(Duration::from_millis(10)));
+        let mut packet = outbound.create_packet(0, Some(256)).await.unwrap();
+        let err = packet.write_all(&[0; 256]).await.unwrap_err();
+        assert!(matches!(Error::from(err), Error::Timeout));
+    }
+
+    #[test]
+    async fn read_timeout() {
+        let (_client, server) = duplex(64);
+        let mut inbound = InboundConnection::new(server, ProtocolVersion::V1_8_9);
+        inbound.set_read_timeout(Some(Duration::from_millis(10)));
+        assert!(matches!(inbound.next_packet().await, Err(Error::Timeout)));
+    }
+
+    #[test]
+    async fn peek_first_byte() {
+        let version = ProtocolVersion::V1_12;
+        let (client, server) = duplex(512);
+        let mut outbound = OutboundConnection::new(client, version);
+        let mut inbound = InboundConnection::new(server, version);
+        // Long enough for the length to take two bytes
+        let content = vec![3; 200];
+        outbound
+            .emit_owned(&OwnedPacket::new(0x0E, content.clone()))
+            .await
+            .unwrap();
+        outbound.conn.flush().await.unwrap();
+
+        assert_eq!(inbound.peek_first_byte().await.unwrap(), 0xC9);
+        assert_eq!(inbound.peek_first_byte().await.unwrap(), 0xC9);
+        assert_eq!(inbound.buffered(), 1);
+        let packet = inbound.next_owned_packet().await.unwrap();
+        assert_eq!((packet.id, packet.content), (0x0E, content));
+        assert_eq!(inbound.buffered(), 0);
+    }
+
+    #[test]
+    async fn version_change() {
+        let (client, server) = duplex(64);
+        let mut outbound = OutboundConnection::new(client, ProtocolVersion::V1_16);
+        let mut inbound = InboundConnection::new(server, ProtocolVersion::V1_16);
+        outbound.set_version(ProtocolVersion::V1_8_9);
+        inbound.set_version(ProtocolVersion::V1_8_9);
+
+        // Encoded as a VarInt before 1.12 rather than a long
+        let keep_alive = KeepAlive(300);
+        let version = outbound.version();
+        let mut packet = outbound
+            .create_packet(0, Some(keep_alive.len(version)))
+            .await
+            .unwrap();
+        keep_alive.encode(&mut packet, version).await.unwrap();
+        packet.shutdown().await.unwrap();
+        drop(packet);
+        outbound.conn.flush().await.unwrap();
+
+        let version = inbound.version();
+        let mut packet = inbound.next_packet().await.unwrap();
+        assert_eq!(packet.len, 2);
+        assert_eq!(
+            KeepAlive::decode(&mut packet.content, version)
+                .await
+                .unwrap(),
+            keep_alive
+        );
+        packet.content.finished().unwrap();
+    }
+
+    #[test]
+    async fn parse() {
+        let (client, server) = duplex(64);
+        let version = ProtocolVersion::V1_16;
+        let mut outbound = OutboundConnection::new(client, version);
+        let mut inbound = InboundConnection::new(server, version);
+
+        let keep_alive = KeepAlive(-5);
+        let mut packet = outbound
+            .create_packet(0x1F, Some(keep_alive.len(version)))
+            .await
+            .unwrap();
+        keep_alive.encode(&mut packet, version).await.unwrap();
+        packet.shutdown().await.unwrap();
+        drop(packet);
+        outbound.conn.flush().await.unwrap();
+
+        let parsed = inbound
+            .next_packet()
+            .await
+            .unwrap()
+            .parse::<KeepAlive>(version)
+            .await
+            .unwrap();
+        assert_eq!((parsed.id, parsed.packet), (0x1F, keep_alive));
+    }
+
+    #[test]
+    async fn into_stream() {
+        let (client, server) = duplex(64);
+        let version = ProtocolVersion::V1_12;
+        let mut outbound = OutboundConnection::new(client, version);
+        let inbound = InboundConnection::new(server, ProtocolVersion::V1_16);
+
+        for i in 0..2 {
+            let keep_alive = KeepAlive(i);
+            let mut packet = outbound
+                .create_packet(0x1F, Some(keep_alive.len(version)))
+                .await
+                .unwrap();
+            keep_alive.encode(&mut packet, version).await.unwrap();
+            packet.shutdown().await.unwrap();
+        }
+        outbound.conn.flush().await.unwrap();
+        drop(outbound);
+
+        let packets: Vec<_> = inbound.into_stream(version).collect().await;
+        assert_eq!(packets.len(), 3);
+        for (i, packet) in packets[..2].iter().enumerate() {
+            let mut packet = packet.as_ref().unwrap().clone();
+            assert_eq!(packet.id, 0x1F);
+            assert_eq!(
+                KeepAlive::decode(&mut packet, version).await.unwrap(),
+                KeepAlive(i as i64)
+            );
+        }
+        // The connection closing
+        assert!(packets[2].is_err());
+    }
+
+    #[test]
+    async fn pipe_packet_from() {
+        let version = ProtocolVersion::V1_12;
+        let (client, proxy_in) = duplex(64);
+        let (proxy_out, server) = duplex(64);
+        let mut client = OutboundConnection::new(client, version);
+        let mut proxy_in = InboundConnection::new(proxy_in, version);
+        let mut proxy_out = OutboundConnection::new(proxy_out, version);
+        let mut server = InboundConnection::new(server, version);
+        // Compressed on the way out only
+        proxy_out.compression = CompressionMode::Server(0);
+        server.compressed = true;
+
+        let keep_alive = KeepAlive(7);
+        let mut packet = client
+            .create_packet(0x0B, Some(keep_alive.len(version)))
+            .await
+            .unwrap();
+        keep_alive.encode(&mut packet, version).await.unwrap();
+        packet.shutdown().await.unwrap();
+        drop(packet);
+        client.conn.flush().await.unwrap();
+
+        assert_eq!(
+            proxy_out.pipe_packet_from(&mut proxy_in).await.unwrap(),
+            (0x0B, 8)
+        );
+        proxy_out.conn.flush().await.unwrap();
+        let parsed = server
+            .next_packet()
+            .await
+            .unwrap()
+            .parse::<KeepAlive>(version)
+            .await
+            .unwrap();
+        assert_eq!((parsed.id, parsed.packet), (0x0B, keep_alive));
+    }
+
+    #[test]
+    async fn drain() {
+        let version = ProtocolVersion::V1_12;
+        let content = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        for compressed in [false, true] {
+            let (client, server) = duplex(2048);
+            let mut outbound = OutboundConnection::new(client, version);
+            let mut inbound = InboundConnection::new(server, version);
+            if compressed {
+                outbound.compression = CompressionMode::Server(0);
+                inbound.compressed = true;
+            }
+            outbound
+                .emit_owned(&OwnedPacket::new(0, content.clone()))
+                .await
+                .unwrap();
+            outbound.conn.flush().await.unwrap();
+
+            let mut packet = inbound.next_packet().await.unwrap();
+            packet.content.drain(600).await.unwrap();
+            assert_eq!(
+                u8::decode(&mut packet.content, version).await.unwrap(),
+                content[600]
+            );
+            assert_eq!(packet.content.drain_to_end().await.unwrap(), 399);
+            packet.content.finished().unwrap();
+            assert!(packet.content.drain(1).await.is_err());
+        }
+    }
+
+    #[test]
+    async fn adaptive_threshold() {
+        let version = ProtocolVersion::V1_12;
+        let (client, server) = duplex(8192);
+        let mut outbound = OutboundConnection::new(client, version);
+        let mut inbound = InboundConnection::new(server, version);
+        assert!(!outbound.enable_adaptive_compression());
+        assert_eq!(outbound.adaptive_threshold(20, 1_000_000_000), None);
+
+        outbound.enable_compression(256);
+        inbound.compressed = true;
+        // Only adapts once asked to
+        assert_eq!(outbound.adaptive_threshold(20, 1_000_000_000), Some(256));
+        assert!(outbound.enable_adaptive_compression());
+        // Compressing takes longer than sending on a fast network
+        assert_eq!(
+            outbound.adaptive_threshold(20, 1_000_000_000),
+            Some(2097151)
+        );
+        assert_eq!(outbound.adaptive_threshold(20, 200_000_000), Some(500));
+        // Never below the server's threshold
+        assert_eq!(outbound.adaptive_threshold(20, 1_000_000), Some(256));
+        assert_eq!(outbound.adaptive_threshold(20, 0), Some(256));
+
+        // Sent uncompressed despite being over the server's threshold
+        outbound.adaptive_threshold(20, 200_000_000);
+        let content = vec![7; 4000];
+        outbound
+            .emit_owned(&OwnedPacket::new(0, content.clone()))
+            .await
+            .unwrap();
+        outbound.conn.flush().await.unwrap();
+        assert_eq!(inbound.next_owned_packet().await.unwrap().content, content);
+        // Sending a packet that big takes most of a round trip at this speed
+        assert_eq!(outbound.adaptive_threshold(1, 200_000_000), Some(256));
+
+        outbound.enable_compression(512);
+        assert_eq!(
+            outbound.compression_mode(),
+            CompressionMode::Adaptive {
+                server: 512,
+                threshold: 512
+            }
+        );
+    }
+
+    #[test]
+    async fn emit_owned_round_trip() {
+        let version = ProtocolVersion::V1_12;
+        let captured = OwnedPacket::new(0x0F, b"hello, replayed world".to_vec());
+        // Every combination of compression on the way in and out
+        for (compress_in, compress_out) in
+            [(false, false), (false, true), (true, false), (true, true)]
+        {
+            let (client, proxy_in) = duplex(256);
+            let (proxy_out, server) = duplex(256);
+            let mut client = OutboundConnection::new(client, version);
+            let mut proxy_in = InboundConnection::new(proxy_in, version);
+            let mut proxy_out = OutboundConnection::new(proxy_out, version);
+            let mut server = InboundConnection::new(server, version);
+            if compress_in {
+                client.compression = CompressionMode::Server(0);
+                proxy_in.compressed = true;
+            }
+            if compress_out {
+                proxy_out.compression = CompressionMode::Server(0);
+                server.compressed = true;
+            }
+
+            client.emit_owned(&captured).await.unwrap();
+            client.conn.flush().await.unwrap();
+            let packet = proxy_in.next_owned_packet().await.unwrap();
+            proxy_out.emit_owned(&packet).await.unwrap();
+            proxy_out.conn.flush().await.unwrap();
+            let emitted = server.next_owned_packet().await.unwrap();
+            assert_eq!(
+                (emitted.id, emitted.content),
+                (captured.id, captured.content.clone()),
+                "compressed in: {}, out: {}",
+                compress_in,
+                compress_out
+            );
+        }
+    }
 }
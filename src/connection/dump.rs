@@ -0,0 +1,397 @@
+use super::{
+    codec::{InboundConnection, OutboundConnection, OwnedPacket},
+    Client, ServerConnection, State,
+};
+use crate::protocol::types::{varint_to_bytes, Decode, VarInt};
+use futures::stream::TryStreamExt;
+use std::{
+    convert::{TryFrom, TryInto},
+    error::Error as StdError,
+    fmt::Write as _,
+    fs::File,
+    io::{BufReader, BufWriter, Cursor, ErrorKind, Read, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    pin, try_join,
+};
+
+// Clientbound login packets, the same in every supported version
+const LOGIN_DISCONNECT_ID: i32 = 0;
+const LOGIN_ENCRYPTION_REQUEST_ID: i32 = 1;
+const LOGIN_SUCCESS_ID: i32 = 2;
+const LOGIN_SET_COMPRESSION_ID: i32 = 3;
+const LOGIN_PLUGIN_REQUEST_ID: i32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Serverbound = 0,
+    Clientbound = 1,
+}
+
+// Every record is the connection number (u32), direction (u8), milliseconds
+// since the dump started (u64), packet ID (i32) and body length (u32), all
+// big endian, followed by the body
+struct Capture {
+    file: Mutex<File>,
+    started: Instant,
+}
+
+const CAPTURE_HEADER_LEN: usize = 21;
+
+fn capture_record(
+    conn_id: u32,
+    direction: Direction,
+    millis: u64,
+    packet: &OwnedPacket,
+) -> Vec<u8> {
+    let mut record = Vec::with_capacity(CAPTURE_HEADER_LEN + packet.content.len());
+    record.extend_from_slice(&conn_id.to_be_bytes());
+    record.push(direction as u8);
+    record.extend_from_slice(&millis.to_be_bytes());
+    record.extend_from_slice(&packet.id.to_be_bytes());
+    record.extend_from_slice(&(packet.content.len() as u32).to_be_bytes());
+    record.extend_from_slice(&packet.content);
+    record
+}
+
+impl Capture {
+    fn record(
+        &self,
+        conn_id: u32,
+        direction: Direction,
+        packet: &OwnedPacket,
+    ) -> std::io::Result<()> {
+        let millis = self.started.elapsed().as_millis() as u64;
+        let record = capture_record(conn_id, direction, millis, packet);
+        self.file.lock().unwrap().write_all(&record)
+    }
+}
+
+// Wireshark's first link type for private use, which tools/minecraft.lua
+// registers its dissector for
+const DLT_USER0: u32 = 147;
+// Packets can be up to 2 MiB, but Wireshark refuses larger snapshot lengths
+const PCAP_SNAPLEN: u32 = 262144;
+
+// Converts a capture to the classic pcap format. Each frame holds the
+// connection number (u32, big endian), the direction (u8) and then the packet
+// as it would be sent uncompressed: VarInt length, VarInt ID and the body.
+// Capture timestamps are relative to the start of the dump, so the frames are
+// dated from the Unix epoch.
+fn write_pcap<R: Read, W: Write>(mut capture: R, mut out: W) -> std::io::Result<()> {
+    out.write_all(&0xA1B2C3D4u32.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&4u16.to_le_bytes())?;
+    // Time zone offset and timestamp accuracy
+    out.write_all(&[0; 8])?;
+    out.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+    out.write_all(&DLT_USER0.to_le_bytes())?;
+    let mut header = [0; CAPTURE_HEADER_LEN];
+    loop {
+        match capture.read_exact(&mut header) {
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            res => res?,
+        }
+        let millis = u64::from_be_bytes(header[5..13].try_into().unwrap());
+        let (id, id_len) = varint_to_bytes(i32::from_be_bytes(header[13..17].try_into().unwrap()));
+        let mut body = vec![0; u32::from_be_bytes(header[17..21].try_into().unwrap()) as usize];
+        capture.read_exact(&mut body)?;
+        let (len, len_len) = varint_to_bytes((id_len + body.len()) as i32);
+        let mut frame = Vec::with_capacity(5 + len_len + id_len + body.len());
+        frame.extend_from_slice(&header[..5]);
+        frame.extend_from_slice(&len[..len_len]);
+        frame.extend_from_slice(&id[..id_len]);
+        frame.extend_from_slice(&body);
+        let included = frame.len().min(PCAP_SNAPLEN as usize);
+        out.write_all(&((millis / 1000) as u32).to_le_bytes())?;
+        out.write_all(&((millis % 1000 * 1000) as u32).to_le_bytes())?;
+        out.write_all(&(included as u32).to_le_bytes())?;
+        out.write_all(&(frame.len() as u32).to_le_bytes())?;
+        out.write_all(&frame[..included])?;
+    }
+}
+
+// For opening a capture made with dump_packets in Wireshark
+pub fn export_pcap(capture_path: &Path, output_path: &Path) -> std::io::Result<()> {
+    let capture = BufReader::new(File::open(capture_path)?);
+    let mut out = BufWriter::new(File::create(output_path)?);
+    write_pcap(capture, &mut out)?;
+    out.flush()
+}
+
+// Offset, 16 bytes in hex and the printable ones as ASCII on each line
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        write!(out, "    {:04x} ", i * 16).unwrap();
+        for byte in line {
+            write!(out, " {:02x}", byte).unwrap();
+        }
+        out.push_str(&"   ".repeat(16 - line.len()));
+        out.push_str("  |");
+        out.extend(line.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+    out
+}
+
+fn print_packet(conn_id: u32, direction: Direction, state: State, packet: &OwnedPacket) {
+    println!(
+        "[#{} {} {:?}] id 0x{:02X}, {} bytes\n{}",
+        conn_id,
+        match direction {
+            Direction::Serverbound => "C->S",
+            Direction::Clientbound => "S->C",
+        },
+        state,
+        packet.id,
+        packet.content.len(),
+        hex_dump(&packet.content)
+    );
+}
+
+async fn forward_packet<R: AsyncReadExt + Unpin, W: AsyncWriteExt + Unpin>(
+    conn_id: u32,
+    direction: Direction,
+    state: State,
+    inbound: &mut InboundConnection<R>,
+    outbound: &mut OutboundConnection<W>,
+    capture: Option<&Capture>,
+) -> Result<OwnedPacket, Box<dyn StdError + Send + Sync + 'static>> {
+    let packet = inbound.next_owned_packet().await?;
+    write_packet(conn_id, direction, state, &packet, outbound, capture).await?;
+    Ok(packet)
+}
+
+async fn write_packet<W: AsyncWriteExt + Unpin>(
+    conn_id: u32,
+    direction: Direction,
+    state: State,
+    packet: &OwnedPacket,
+    outbound: &mut OutboundConnection<W>,
+    capture: Option<&Capture>,
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    print_packet(conn_id, direction, state, packet);
+    if let Some(capture) = capture {
+        capture.record(conn_id, direction, packet)?;
+    }
+    outbound.emit_owned(packet).await?;
+    Ok(())
+}
+
+async fn forward_stream<R: AsyncReadExt + Unpin, W: AsyncWriteExt + Unpin>(
+    conn_id: u32,
+    direction: Direction,
+    state: State,
+    inbound: InboundConnection<R>,
+    outbound: &mut OutboundConnection<W>,
+    capture: Option<&Capture>,
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let version = inbound.version();
+    let packets = inbound.into_stream(version);
+    pin!(packets);
+    while let Some(packet) = packets.try_next().await? {
+        write_packet(conn_id, direction, state, &packet, outbound, capture).await?;
+    }
+    Ok(())
+}
+
+async fn dump_connection(
+    conn_id: u32,
+    mut conn: ServerConnection,
+    upstream: &str,
+    capture: Option<&Capture>,
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    conn.accept_handshake().await?;
+    println!(
+        "[#{}] {:?} handshake for {:?} using {:?}",
+        conn_id,
+        conn.state(),
+        conn.hostname(),
+        conn.version()
+    );
+    let mut client = Client::connect(upstream, conn.version()).await?;
+    client.handshake(conn.state()).await?;
+    let mut state = conn.state();
+    if state == State::Login {
+        // Strictly request and response until the login succeeds, which makes
+        // it easy to switch on compression at the right time
+        forward_packet(
+            conn_id,
+            Direction::Serverbound,
+            state,
+            &mut conn.inbound,
+            &mut client.outbound,
+            capture,
+        )
+        .await?;
+        loop {
+            let packet = forward_packet(
+                conn_id,
+                Direction::Clientbound,
+                state,
+                &mut client.inbound,
+                &mut conn.outbound,
+                capture,
+            )
+            .await?;
+            match packet.id {
+                LOGIN_DISCONNECT_ID => return Ok(()),
+                LOGIN_ENCRYPTION_REQUEST_ID => {
+                    return Err("the server is in online mode, so the session is encrypted \
+                        and can't be dumped"
+                        .into())
+                }
+                LOGIN_SUCCESS_ID => break,
+                LOGIN_SET_COMPRESSION_ID => {
+                    let threshold =
+                        VarInt::decode(&mut Cursor::new(&packet.content), conn.version()).await?;
+                    // Negative thresholds turn compression off
+                    if let Ok(threshold) = usize::try_from(threshold.0) {
+                        client.inbound.compressed = true;
+                        client.outbound.enable_compression(threshold);
+                        conn.inbound.compressed = true;
+                        conn.outbound.enable_compression(threshold);
+                    }
+                }
+                LOGIN_PLUGIN_REQUEST_ID => {
+                    forward_packet(
+                        conn_id,
+                        Direction::Serverbound,
+                        state,
+                        &mut conn.inbound,
+                        &mut client.outbound,
+                        capture,
+                    )
+                    .await?;
+                }
+                _ => {}
+            }
+        }
+        state = State::Play;
+    }
+    let ServerConnection {
+        inbound: server_inbound,
+        outbound: mut server_outbound,
+        ..
+    } = conn;
+    let Client {
+        inbound,
+        mut outbound,
+        ..
+    } = client;
+    // Either side closing ends the dump, which shows up as an error
+    try_join!(
+        forward_stream(
+            conn_id,
+            Direction::Serverbound,
+            state,
+            server_inbound,
+            &mut outbound,
+            capture,
+        ),
+        forward_stream(
+            conn_id,
+            Direction::Clientbound,
+            state,
+            inbound,
+            &mut server_outbound,
+            capture,
+        ),
+    )?;
+    Ok(())
+}
+
+// Forwards every connection to upstream without touching it, printing each
+// packet along the way and optionally recording them to a file for replay.
+// Only works with servers in offline mode since encryption can't be undone.
+pub async fn dump_packets(
+    listen: SocketAddr,
+    upstream: String,
+    capture_path: Option<&Path>,
+) -> Result<(), Box<dyn StdError + Send + Sync + 'static>> {
+    let listener = TcpListener::bind(listen).await?;
+    let capture = match capture_path {
+        Some(path) => Some(Arc::new(Capture {
+            file: Mutex::new(File::create(path)?),
+            started: Instant::now(),
+        })),
+        None => None,
+    };
+    let upstream = Arc::new(upstream);
+    let next_conn_id = AtomicU32::new(1);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let conn_id = next_conn_id.fetch_add(1, Ordering::Relaxed);
+        println!("[#{}] Connection from {}", conn_id, addr);
+        let capture = capture.clone();
+        let upstream = upstream.clone();
+        tokio::spawn(async move {
+            let conn = ServerConnection::new(stream).await;
+            if let Err(err) = dump_connection(conn_id, conn, &upstream, capture.as_deref()).await {
+                println!("[#{}] Connection closed: {}", conn_id, err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capture_record, hex_dump, write_pcap, Direction, OwnedPacket, DLT_USER0};
+
+    #[test]
+    fn hex_dump_lines() {
+        assert_eq!(
+            hex_dump(b"\x00Hello, world!\xFF\x10\x20tail"),
+            "    0000  00 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 ff 10  |.Hello, world!..|\n\
+             \x20   0010  20 74 61 69 6c                                   | tail|\n"
+        );
+    }
+
+    #[test]
+    fn pcap_export() {
+        let mut capture = capture_record(
+            3,
+            Direction::Clientbound,
+            1500,
+            &OwnedPacket::new(0x40, b"bye".to_vec()),
+        );
+        capture.extend(capture_record(
+            3,
+            Direction::Serverbound,
+            2000,
+            &OwnedPacket::new(0x01, Vec::new()),
+        ));
+        let mut pcap = Vec::new();
+        write_pcap(&capture[..], &mut pcap).unwrap();
+
+        assert_eq!(&pcap[..4], &[0xD4, 0xC3, 0xB2, 0xA1]);
+        assert_eq!(&pcap[20..24], &DLT_USER0.to_le_bytes());
+        let frame = [0, 0, 0, 3, 1, 4, 0x40, b'b', b'y', b'e'];
+        let mut first = vec![1, 0, 0, 0, 0x20, 0xA1, 0x07, 0];
+        first.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        first.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        first.extend_from_slice(&frame);
+        assert_eq!(&pcap[24..24 + first.len()], &first[..]);
+        // ts 2s, 0us, then a 7 byte frame with only the length and ID
+        assert_eq!(
+            &pcap[24 + first.len()..],
+            &[2, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 3, 0, 1, 1]
+        );
+    }
+}
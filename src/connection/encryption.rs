@@ -1,3 +1,4 @@
+use super::error::Error as ConnectionError;
 use aes::Aes128;
 use cfb8::{
     cipher::{AsyncStreamCipher, NewCipher},
@@ -5,19 +6,66 @@ use cfb8::{
 };
 use std::{
     cmp::min,
+    future::Future,
+    io::IoSlice,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    time::{sleep, Sleep},
 };
-use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 
 type Encryption = Cfb8<Aes128>;
 
+#[derive(Debug, Error)]
+pub enum Error {
+    // The key is only ever sent once per connection
+    #[error("encryption was already enabled")]
+    AlreadyEncrypted,
+}
+
+// Fails writes that stay blocked for longer than the timeout, e.g. because
+// the other end stopped reading
+#[derive(Default)]
+struct WriteDeadline {
+    timeout: Option<Duration>,
+    // Started by the first write that blocks, reset once one goes through
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl WriteDeadline {
+    fn poll<T>(&mut self, cx: &mut Context<'_>, res: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        let timeout = match (res, self.timeout) {
+            (Poll::Pending, Some(timeout)) => timeout,
+            (Poll::Pending, None) => return Poll::Pending,
+            (res, _) => {
+                self.sleep = None;
+                return res;
+            }
+        };
+        let sleep = self.sleep.get_or_insert_with(|| Box::pin(sleep(timeout)));
+        if sleep.as_mut().poll(cx).is_ready() {
+            self.sleep = None;
+            Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                ConnectionError::Timeout,
+            )))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
 pub struct Encryptor<W: AsyncWrite + Unpin> {
     cipher: Option<Encryption>,
     tgt: W,
     buffer: Box<[u8]>,
     pos: usize,
     cap: usize,
+    deadline: WriteDeadline,
 }
 
 const BUFFER_SIZE: usize = 8192;
@@ -30,24 +78,37 @@ impl<W: AsyncWrite + Unpin> Encryptor<W> {
             buffer: vec![0u8; BUFFER_SIZE].into_boxed_slice(),
             pos: 0,
             cap: 0,
+            deadline: Default::default(),
         }
     }
 
-    pub fn set_key(&mut self, key: [u8; 16]) -> bool {
+    // None waits forever
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline.timeout = timeout;
+    }
+
+    pub fn set_key(&mut self, key: [u8; 16]) -> Result<(), Error> {
         if self.cipher.is_some() {
-            false
+            Err(Error::AlreadyEncrypted)
         } else {
             self.cipher = Some(Encryption::new_from_slices(&key, &key).unwrap());
-            true
+            Ok(())
         }
     }
 
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.tgt
+    }
+
     fn flush_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
         if self.cipher.is_some() {
             while self.pos != self.cap {
-                self.pos += Pin::new(&mut self.tgt)
-                    .poll_write(cx, &self.buffer[self.pos..self.cap])
-                    .ready()??;
+                let res = Pin::new(&mut self.tgt).poll_write(cx, &self.buffer[self.pos..self.cap]);
+                self.pos += self.deadline.poll(cx, res).ready()??;
             }
         }
         Poll::Ready(Ok(()))
@@ -72,10 +133,48 @@ impl<W: AsyncWriteExt + Unpin> AsyncWrite for Encryptor<W> {
             let _ = this.flush_buffer(cx)?;
             Poll::Ready(Ok(cap))
         } else {
-            Pin::new(&mut self.tgt).poll_write(cx, buf)
+            let res = Pin::new(&mut self.tgt).poll_write(cx, buf);
+            self.deadline.poll(cx, res)
         }
     }
 
+    // Gathers as much as fits into the buffer and encrypts it in one go, so a
+    // packet's length and body are one cipher call and one write
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        if self.cipher.is_some() {
+            self.flush_buffer(cx).ready()??;
+            let this = self.get_mut();
+            let mut cap = 0;
+            for buf in bufs {
+                let len = min(buf.len(), BUFFER_SIZE - cap);
+                this.buffer[cap..cap + len].copy_from_slice(&buf[..len]);
+                cap += len;
+                if cap == BUFFER_SIZE {
+                    break;
+                }
+            }
+            this.pos = 0;
+            this.cap = cap;
+            this.cipher
+                .as_mut()
+                .unwrap()
+                .encrypt(&mut this.buffer[..cap]);
+            let _ = this.flush_buffer(cx)?;
+            Poll::Ready(Ok(cap))
+        } else {
+            let res = Pin::new(&mut self.tgt).poll_write_vectored(cx, bufs);
+            self.deadline.poll(cx, res)
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.cipher.is_some() || self.tgt.is_write_vectored()
+    }
+
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
         self.flush_buffer(cx)
     }
@@ -85,7 +184,8 @@ impl<W: AsyncWriteExt + Unpin> AsyncWrite for Encryptor<W> {
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), io::Error>> {
         self.flush_buffer(cx).ready()??;
-        Pin::new(&mut self.tgt).poll_shutdown(cx)
+        let res = Pin::new(&mut self.tgt).poll_shutdown(cx);
+        self.deadline.poll(cx, res)
     }
 }
 
@@ -99,14 +199,28 @@ impl<R: AsyncReadExt + Unpin> Decryptor<R> {
         Decryptor { src, cipher: None }
     }
 
-    pub fn set_key(&mut self, key: [u8; 16]) -> bool {
+    pub fn set_key(&mut self, key: [u8; 16]) -> Result<(), Error> {
         if self.cipher.is_some() {
-            false
+            Err(Error::AlreadyEncrypted)
         } else {
             self.cipher = Some(Encryption::new_from_slices(&key, &key).unwrap());
-            true
+            Ok(())
         }
     }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.src
+    }
+
+    // Bytes read from the source but not yet returned. Reads go straight to the
+    // source, which zero-copy forwarding relies on, so nothing is ever held back.
+    pub fn buffered_size(&self) -> usize {
+        0
+    }
 }
 
 impl<R: AsyncReadExt + Unpin> AsyncRead for Decryptor<R> {
@@ -123,3 +237,58 @@ impl<R: AsyncReadExt + Unpin> AsyncRead for Decryptor<R> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Decryptor, Encryptor, Error, BUFFER_SIZE};
+    use std::io::IoSlice;
+    use tokio::io::{empty, sink, AsyncWriteExt};
+
+    #[test]
+    fn rekey() {
+        let mut encryptor = Encryptor::new(sink());
+        assert!(encryptor.set_key([1; 16]).is_ok());
+        assert!(encryptor.is_encrypted());
+        assert!(matches!(
+            encryptor.set_key([2; 16]),
+            Err(Error::AlreadyEncrypted)
+        ));
+        let mut decryptor = Decryptor::new(empty());
+        assert!(decryptor.set_key([1; 16]).is_ok());
+        assert!(matches!(
+            decryptor.set_key([1; 16]),
+            Err(Error::AlreadyEncrypted)
+        ));
+    }
+
+    #[tokio::test]
+    async fn vectored_write() {
+        let header = [0x0B];
+        let body = vec![7; BUFFER_SIZE + 10];
+        for encrypted in [false, true] {
+            let mut scalar = Encryptor::new(Vec::new());
+            let mut vectored = Encryptor::new(Vec::new());
+            if encrypted {
+                scalar.set_key([3; 16]).unwrap();
+                vectored.set_key([3; 16]).unwrap();
+            }
+            scalar.write_all(&header).await.unwrap();
+            scalar.write_all(&body).await.unwrap();
+            scalar.flush().await.unwrap();
+
+            let mut written = vectored
+                .write_vectored(&[IoSlice::new(&header), IoSlice::new(&body)])
+                .await
+                .unwrap();
+            if encrypted {
+                // Only as much as fits in the buffer
+                assert_eq!(written, BUFFER_SIZE);
+            }
+            while written < header.len() + body.len() {
+                written += vectored.write(&body[written - 1..]).await.unwrap();
+            }
+            vectored.flush().await.unwrap();
+            assert_eq!(scalar.get_ref(), vectored.get_ref());
+        }
+    }
+}
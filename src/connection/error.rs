@@ -1,3 +1,4 @@
+use super::encryption::Error as EncryptionError;
 use crate::protocol::{error::Error as ProtocolError, types::Chat};
 use crate::web::error::Error as WebError;
 use reqwest::Error as HTTPError;
@@ -6,15 +7,19 @@ use tokio::io::Error as IOError;
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("invalid target")]
-    InvalidTarget,
+    #[error("invalid target {0:?}")]
+    InvalidTarget(String),
     #[error("I/O error")]
-    IO(#[from] IOError),
+    IO(IOError),
     #[error("invalid protocol state")]
     InvalidState,
-    #[error("protocol error")]
+    #[error("protocol error: {0}")]
     Protocol(#[from] ProtocolError),
-    #[error("disconnected")]
+    #[error("encryption error: {0}")]
+    Encryption(#[from] EncryptionError),
+    #[error("unsupported protocol version {0}, the proxy may need to be updated")]
+    UnsupportedProtocolVersion(i32),
+    #[error("disconnected: {}", .0.to_plain_text())]
     Disconnected(Box<Chat<'static>>),
     #[error("no credentials")]
     NoCredentials,
@@ -28,4 +33,29 @@ pub enum Error {
     InvalidPacketSize(i32),
     #[error("incomplete packet")]
     IncompletePacket,
+    #[error("connection timed out")]
+    Timeout,
+    #[error("invalid PROXY protocol header")]
+    InvalidProxyHeader,
+    #[error("unsupported PROXY protocol version or command")]
+    UnsupportedProxyProtocol,
+    #[error("unexpected keep alive {0}")]
+    UnexpectedKeepAlive(i64),
+    #[error(
+        "port {port} is already in use, is Minecraft running?{}",
+        .free_port.map_or(String::new(), |port| format!(" (port {} is free)", port))
+    )]
+    PortInUse { port: u16, free_port: Option<u16> },
+}
+
+// Errors raised while writing a packet have to pass through AsyncWrite as I/O
+// errors, so they are unwrapped again here
+impl From<IOError> for Error {
+    fn from(err: IOError) -> Error {
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            *err.into_inner().unwrap().downcast::<Error>().unwrap()
+        } else {
+            Error::IO(err)
+        }
+    }
 }
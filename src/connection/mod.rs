@@ -1,12 +1,19 @@
 pub mod codec;
+pub mod dump;
 mod encryption;
 pub mod error;
 pub mod packets;
+pub mod proxy_protocol;
 mod util;
+pub mod zero_copy;
 
-use tokio::net::{
-    tcp::{OwnedReadHalf, OwnedWriteHalf},
-    TcpStream,
+use std::net::{Ipv6Addr, SocketAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
 };
 use trust_dns_resolver::{
     config::{ResolverConfig, ResolverOpts},
@@ -15,7 +22,7 @@ use trust_dns_resolver::{
 
 use super::protocol::version::ProtocolVersion;
 
-use codec::{InboundConnection, OutboundConnection};
+use codec::{InboundConnection, IncomingPacket, OutboundConnection, OwnedPacket};
 use error::Error;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -26,25 +33,102 @@ pub enum State {
     Play = 3,
 }
 
-pub struct Client {
-    pub outbound: OutboundConnection<OwnedWriteHalf>,
-    pub inbound: InboundConnection<OwnedReadHalf>,
-    pub host: String,
-    pub port: u16,
+// Splits a target into host and port, rejecting anything that could never resolve
+fn parse_target(target: &str) -> Result<(String, Option<u16>), Error> {
+    let invalid = || Error::InvalidTarget(target.into());
+    let (host, port) = if let Some(rest) = target.strip_prefix('[') {
+        let (addr, rest) = rest.split_once(']').ok_or_else(invalid)?;
+        addr.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+        let port = match rest {
+            "" => None,
+            rest => Some(rest.strip_prefix(':').ok_or_else(invalid)?),
+        };
+        (addr, port)
+    } else {
+        let (host, port) = match target.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (target, None),
+        };
+        if host.is_empty()
+            || !host
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-')
+        {
+            return Err(invalid());
+        }
+        (host, port)
+    };
+    let port = match port {
+        Some(port) => match port.parse() {
+            Ok(0) | Err(_) => return Err(invalid()),
+            Ok(port) => Some(port),
+        },
+        None => None,
+    };
+    Ok((host.to_string(), port))
+}
+
+// Everything both ends of the proxy have in common. The address type tells
+// them apart, so methods only one of them has go on the aliases below.
+pub struct Connection<R: AsyncReadExt + Unpin, W: AsyncWriteExt + Unpin, A> {
+    pub outbound: OutboundConnection<W>,
+    pub inbound: InboundConnection<R>,
     pub state: State,
     pub version: ProtocolVersion,
+    pub address: A,
+}
+
+impl<R: AsyncReadExt + Unpin, W: AsyncWriteExt + Unpin, A> Connection<R, W, A> {
+    fn from_halves(
+        reader: R,
+        writer: W,
+        version: ProtocolVersion,
+        address: A,
+    ) -> Connection<R, W, A> {
+        Connection {
+            outbound: OutboundConnection::new(writer, version),
+            inbound: InboundConnection::new(reader, version),
+            state: State::Handshaking,
+            version,
+            address,
+        }
+    }
+
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    pub fn compress_threshold(&self) -> Option<usize> {
+        self.outbound.compress_threshold()
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub async fn next_packet(&mut self) -> Result<IncomingPacket<'_, R>, Error> {
+        self.inbound.next_packet().await
+    }
+
+    pub async fn send_packet(&mut self, packet: &OwnedPacket) -> Result<(), Error> {
+        self.outbound.emit_owned(packet).await
+    }
+}
+
+// The server a client connects to, as sent in the handshake
+pub struct ServerAddress {
+    pub host: String,
+    pub port: u16,
 }
 
+// A connection to the upstream server
+pub type Client = Connection<OwnedReadHalf, OwnedWriteHalf, ServerAddress>;
+
 impl Client {
     pub async fn connect(target: &str, version: ProtocolVersion) -> Result<Client, Error> {
+        let (domain, port) = parse_target(target)?;
         let resolver =
             AsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()).unwrap();
-        let mut target = target.split(':');
-        let domain = target.next().unwrap().to_string();
-        let port = target.next().map_or(Ok(None), |port| match port.parse() {
-            Err(_) => Err(Error::InvalidTarget),
-            Ok(port) => Ok(Some(port)),
-        })?;
         let (host, port) = if let Ok(srv) = resolver
             .srv_lookup(["_minecraft._tcp.", domain.as_str()].concat())
             .await
@@ -57,36 +141,139 @@ impl Client {
         };
         let ip_addr = match resolver.lookup_ip(host.as_str()).await {
             Ok(lookup) => lookup.iter().next().unwrap(),
-            Err(_) => return Err(Error::InvalidTarget),
+            Err(_) => return Err(Error::InvalidTarget(target.into())),
         };
         let (read_half, write_half) = TcpStream::connect((ip_addr, port)).await?.into_split();
-        Ok(Client {
-            outbound: OutboundConnection::new(write_half, version),
-            inbound: InboundConnection::new(read_half, version),
-            state: State::Handshaking,
-            host,
-            port,
+        Ok(Connection::from_halves(
+            read_half,
+            write_half,
             version,
-        })
+            ServerAddress { host, port },
+        ))
     }
 }
 
-pub struct ServerConnection {
-    pub outbound: OutboundConnection<OwnedWriteHalf>,
-    pub inbound: InboundConnection<OwnedReadHalf>,
-    pub state: State,
-    pub version: ProtocolVersion,
+// The address the local client connected to, empty until the handshake,
+// and where it connected from
+pub struct ClientAddress {
+    pub hostname: String,
+    pub peer: Option<SocketAddr>,
 }
 
+// A connection from the local client
+pub type ServerConnection = Connection<OwnedReadHalf, OwnedWriteHalf, ClientAddress>;
+
 impl ServerConnection {
     pub async fn new(conn: TcpStream) -> ServerConnection {
+        let peer = conn.peer_addr().ok();
+        ServerConnection::with_peer(conn, peer)
+    }
+
+    // For connections from a load balancer, which starts them with a PROXY
+    // protocol header saying who the client really is
+    pub async fn new_behind_proxy(mut conn: TcpStream) -> Result<ServerConnection, Error> {
+        let header = proxy_protocol::read_header(&mut conn).await?;
+        let peer = match header.addresses {
+            Some((source, _)) => Some(source),
+            None => conn.peer_addr().ok(),
+        };
+        Ok(ServerConnection::with_peer(conn, peer))
+    }
+
+    fn with_peer(conn: TcpStream, peer: Option<SocketAddr>) -> ServerConnection {
         const INIT_VERSION: ProtocolVersion = ProtocolVersion::V1_16;
         let (read_half, write_half) = conn.into_split();
-        ServerConnection {
-            outbound: OutboundConnection::new(write_half, INIT_VERSION),
-            inbound: InboundConnection::new(read_half, INIT_VERSION),
-            state: State::Handshaking,
-            version: INIT_VERSION,
+        Connection::from_halves(
+            read_half,
+            write_half,
+            INIT_VERSION,
+            ClientAddress {
+                hostname: String::new(),
+                peer,
+            },
+        )
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.address.hostname
+    }
+
+    // Where the client connected from, which is the address from the PROXY
+    // protocol header for connections behind a load balancer
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.address.peer
+    }
+}
+
+// Both ends of a local TCP connection. ServerConnection and Client are built
+// on TcpStream halves, so tests of them need a real socket rather than a
+// tokio::io::duplex.
+#[cfg(test)]
+pub(crate) async fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    (client.unwrap(), server.unwrap().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{error::Error, parse_target};
+    use std::error::Error as StdError;
+
+    // Errors cross task boundaries as Box<dyn Error + Send + Sync>
+    #[test]
+    fn error_is_send_sync() {
+        fn boxed<E: StdError + Send + Sync + 'static>(
+            err: E,
+        ) -> Box<dyn StdError + Send + Sync + 'static> {
+            err.into()
+        }
+        assert_eq!(
+            boxed(Error::InvalidState).to_string(),
+            "invalid protocol state"
+        );
+    }
+
+    #[test]
+    fn valid_targets() {
+        assert_eq!(
+            parse_target("mc.hypixel.net").unwrap(),
+            ("mc.hypixel.net".to_string(), None)
+        );
+        assert_eq!(
+            parse_target("play-2.example.com:25566").unwrap(),
+            ("play-2.example.com".to_string(), Some(25566))
+        );
+        assert_eq!(
+            parse_target("[::1]:25565").unwrap(),
+            ("::1".to_string(), Some(25565))
+        );
+        assert_eq!(
+            parse_target("[fe80::1]").unwrap(),
+            ("fe80::1".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn invalid_targets() {
+        for target in [
+            "",
+            "not_a_valid_target!@#$",
+            "mc.hypixel.net:0",
+            "mc.hypixel.net:65536",
+            "mc.hypixel.net:",
+            "mc.hypixel.net:25565:25565",
+            ":25565",
+            "[::1",
+            "[::g]:25565",
+            "[::1]25565",
+            "::1",
+        ] {
+            match parse_target(target) {
+                Err(Error::InvalidTarget(rejected)) => assert_eq!(rejected, target),
+                res => panic!("{:?} was not rejected: {:?}", target, res),
+            }
         }
     }
 }
@@ -1,25 +1,34 @@
-use crate::connection::{error::Error, Client, ServerConnection, State};
+use crate::connection::{codec::InboundConnection, error::Error, Client, ServerConnection, State};
 use crate::protocol::{
     error::Error as ProtocolError,
-    types::{Decode, Encode, LengthCappedString, VarInt},
+    types::{Chat, Decode, Encode, LengthCappedString, VarInt},
+    version::ProtocolVersion,
 };
-use std::{convert::TryInto, io::Cursor};
-use tokio::io::AsyncWriteExt;
+use std::{
+    convert::{TryFrom, TryInto},
+    io::Cursor,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+// Protocol version, server address, port and next state
+const HANDSHAKE_MAX_LEN: usize = VarInt::MAX_ENCODED_LEN
+    + LengthCappedString::<255>::max_bytes()
+    + (u16::BITS / 8) as usize
+    + VarInt::MAX_ENCODED_LEN;
 
 impl Client {
     pub async fn handshake(&mut self, next_state: State) -> Result<(), Error> {
         if self.state == State::Handshaking
             && (next_state == State::Status || next_state == State::Login)
         {
-            // handshake packet at most 265 bytes across all versions, present and future
-            let mut packet_buffer = [0; 265];
+            let mut packet_buffer = [0; HANDSHAKE_MAX_LEN];
             let mut packet: Cursor<&mut [u8]> = Cursor::new(&mut packet_buffer);
             VarInt(self.version as i32)
                 .encode(&mut packet, self.version)
                 .await?;
-            let server_address: LengthCappedString<255> = self.host.as_str().try_into()?;
+            let server_address: LengthCappedString<255> = self.address.host.as_str().try_into()?;
             server_address.encode(&mut packet, self.version).await?;
-            self.port.encode(&mut packet, self.version).await?;
+            self.address.port.encode(&mut packet, self.version).await?;
             VarInt(next_state as i32)
                 .encode(&mut packet, self.version)
                 .await?;
@@ -37,31 +46,196 @@ impl Client {
     }
 }
 
+// What the client sends first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HandshakePacket {
+    // As sent, which may be a version the proxy can't relay
+    pub protocol: i32,
+    // The closest supported version, or the latest for unknown ones
+    pub version: ProtocolVersion,
+    // As sent, so possibly with a Forge marker or a trailing dot
+    pub host: String,
+    pub port: u16,
+    pub next_state: State,
+}
+
 impl ServerConnection {
+    // Only reads from inbound, so nothing changes if the handshake is invalid
+    pub async fn parse_handshake<R: AsyncReadExt + Unpin>(
+        inbound: &mut InboundConnection<R>,
+    ) -> Result<HandshakePacket, Error> {
+        // The handshake is the same in every version
+        let decode_version = inbound.version();
+        let mut packet = inbound.next_packet().await?;
+        if packet.id != 0 {
+            return Err(ProtocolError::Malformed.into());
+        }
+        let protocol = VarInt::decode(&mut packet.content, decode_version).await?.0;
+        // Newer clients can still ping, so the server shows up in their list.
+        // They are turned away when logging in instead.
+        let version = ProtocolVersion::try_from(protocol).unwrap_or(ProtocolVersion::LATEST);
+        let host = LengthCappedString::<256>::decode(&mut packet.content, version)
+            .await?
+            .0
+            .into_owned();
+        let port = u16::decode(&mut packet.content, version).await?;
+        let next_state = match VarInt::decode(&mut packet.content, version).await?.0 {
+            1 => State::Status,
+            2 => State::Login,
+            _ => return Err(ProtocolError::Malformed.into()),
+        };
+        packet.content.finished()?;
+        Ok(HandshakePacket {
+            protocol,
+            version,
+            host,
+            port,
+            next_state,
+        })
+    }
+
+    pub fn apply_handshake(&mut self, handshake: HandshakePacket) -> Result<(), Error> {
+        if self.state != State::Handshaking {
+            return Err(Error::InvalidState);
+        }
+        // The connection was created before the client's version was known
+        self.version = handshake.version;
+        self.inbound.set_version(handshake.version);
+        self.outbound.set_version(handshake.version);
+        // Forge appends its marker after a NUL and some clients keep the
+        // trailing dot of a fully qualified domain
+        self.address.hostname = handshake
+            .host
+            .split('\0')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+        self.state = handshake.next_state;
+        Ok(())
+    }
+
     pub async fn accept_handshake(&mut self) -> Result<(), Error> {
-        if self.state == State::Handshaking {
-            let mut packet = self.inbound.next_packet().await?;
-            if packet.id != 0 {
-                return Err(ProtocolError::Malformed.into());
+        if self.state != State::Handshaking {
+            return Err(Error::InvalidState);
+        }
+        let handshake = ServerConnection::parse_handshake(&mut self.inbound).await?;
+        let protocol = handshake.protocol;
+        let supported = ProtocolVersion::try_from(protocol).is_ok();
+        self.apply_handshake(handshake)?;
+        if self.state == State::Login && !supported {
+            // The client shows this on its login screen
+            let reason = Chat::Raw(
+                format!(
+                    "§cThis Minecraft version isn't supported by the proxy, use {} or earlier",
+                    ProtocolVersion::LATEST
+                )
+                .into(),
+            );
+            let mut packet = self.outbound.create_packet(0, None).await?;
+            reason.encode(&mut packet, self.version).await?;
+            packet.shutdown().await?;
+            return Err(Error::UnsupportedProtocolVersion(protocol));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{
+            codec::InboundConnection, error::Error, socket_pair, ServerConnection, State,
+        },
+        protocol::{
+            types::{Chat, Decode},
+            version::ProtocolVersion,
+        },
+    };
+    use tokio::{
+        io::{duplex, AsyncWriteExt},
+        test,
+    };
+
+    #[test]
+    async fn hostname() {
+        let (mut client, server) = socket_pair().await;
+        let mut conn = ServerConnection::new(server).await;
+
+        let host = b"Alt.Example.com.\0FML\0";
+        let mut packet = vec![0, 47, host.len() as u8];
+        packet.extend_from_slice(host);
+        packet.extend_from_slice(&[0x63, 0xDD, 2]);
+        client.write_u8(packet.len() as u8).await.unwrap();
+        client.write_all(&packet).await.unwrap();
+
+        conn.accept_handshake().await.unwrap();
+        assert_eq!(conn.hostname(), "alt.example.com");
+        assert_eq!(conn.version(), ProtocolVersion::V1_8_9);
+        assert_eq!(conn.state(), State::Login);
+    }
+
+    #[test]
+    async fn unsupported_version() {
+        // -1, and 759 (1.19) which signs chat
+        for (version, encoded) in [
+            (-1, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F][..]),
+            (759, &[0xF7, 0x05]),
+        ] {
+            let (mut client, server) = socket_pair().await;
+            let mut conn = ServerConnection::new(server).await;
+
+            // The version followed by an empty host, port 25565 and login
+            let mut packet = vec![0];
+            packet.extend_from_slice(encoded);
+            packet.extend_from_slice(&[0, 0x63, 0xDD, 2]);
+            client.write_u8(packet.len() as u8).await.unwrap();
+            client.write_all(&packet).await.unwrap();
+
+            match conn.accept_handshake().await {
+                Err(Error::UnsupportedProtocolVersion(v)) if v == version => {}
+                res => panic!("unexpected result {:?}", res),
             }
-            self.version = VarInt::decode(&mut packet.content, self.version)
-                .await?
-                .0
-                .try_into()?;
-            let _host = LengthCappedString::<256>::decode(&mut packet.content, self.version)
-                .await?
-                .0;
-            let _port = u16::decode(&mut packet.content, self.version).await?;
-            let next_state = match VarInt::decode(&mut packet.content, self.version).await?.0 {
-                1 => State::Status,
-                2 => State::Login,
-                _ => return Err(ProtocolError::Malformed.into()),
-            };
-            packet.content.finished()?;
-            self.state = next_state;
-            Ok(())
-        } else {
-            Err(Error::InvalidState)
+            // Told why in a login disconnect
+            let mut inbound = InboundConnection::new(client, ProtocolVersion::LATEST);
+            let mut disconnect = inbound.next_packet().await.unwrap();
+            assert_eq!(disconnect.id, 0);
+            let reason = Chat::decode(&mut disconnect.content, ProtocolVersion::LATEST)
+                .await
+                .unwrap();
+            assert!(reason.to_plain_text().contains("isn't supported"));
         }
     }
+
+    #[test]
+    async fn unsupported_version_status() {
+        let (mut client, server) = socket_pair().await;
+        let mut conn = ServerConnection::new(server).await;
+
+        // 759 (1.19), an empty host, port 25565 and status
+        let packet = [0, 0xF7, 0x05, 0, 0x63, 0xDD, 1];
+        client.write_u8(packet.len() as u8).await.unwrap();
+        client.write_all(&packet).await.unwrap();
+
+        conn.accept_handshake().await.unwrap();
+        assert_eq!(conn.version(), ProtocolVersion::LATEST);
+        assert_eq!(conn.state(), State::Status);
+    }
+
+    #[test]
+    async fn invalid_handshake_changes_nothing() {
+        let (mut client, server) = duplex(64);
+        let init_version = ProtocolVersion::V1_16;
+        let mut inbound = InboundConnection::new(server, init_version);
+
+        // A supported version, but next state 3 doesn't exist
+        let packet = [0, 47, 0, 0x63, 0xDD, 3];
+        client.write_u8(packet.len() as u8).await.unwrap();
+        client.write_all(&packet).await.unwrap();
+
+        assert!(ServerConnection::parse_handshake(&mut inbound)
+            .await
+            .is_err());
+        assert_eq!(inbound.version(), init_version);
+    }
 }
@@ -1,9 +1,13 @@
 use crate::connection::{error::Error, Client, ServerConnection, State};
 use crate::protocol::{
     error::Error as ProtocolError,
-    types::{serde_raw_uuid, Chat, Decode, Encode, Identifier, LengthCappedString, VarInt, UUID},
+    types::{
+        decode_impl, decode_inner_impl, encode_impl, encode_inner_impl, serde_raw_uuid, Chat,
+        Decode, Encode, Identifier, LengthCappedString, VarInt, UUID,
+    },
     version::ProtocolVersion,
 };
+use crate::web::mojang::SessionServerConfig;
 use rand::{thread_rng, Rng};
 use reqwest::Client as HTTPClient;
 use rsa::{
@@ -39,15 +43,70 @@ pub struct Player<'a> {
     pub uuid: UUID,
 }
 
+// The UUID is a hyphenated string before 1.16
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginSuccessPacket {
+    pub uuid: UUID,
+    pub username: String,
+}
+
+impl LoginSuccessPacket {
+    pub fn len(&self, version: ProtocolVersion) -> usize {
+        let uuid_len = if version.has_uuid_in_login_success() {
+            16
+        } else {
+            VarInt(36).len() + 36
+        };
+        uuid_len + VarInt(self.username.len() as i32).len() + self.username.len()
+    }
+}
+
+decode_impl!(LoginSuccessPacket, src, version, {
+    Ok(LoginSuccessPacket {
+        uuid: if version.has_uuid_in_login_success() {
+            UUID::decode(src, version).await?
+        } else {
+            LengthCappedString::<36>::decode(src, version)
+                .await?
+                .0
+                .parse()?
+        },
+        username: LengthCappedString::<16>::decode(src, version)
+            .await?
+            .0
+            .into_owned(),
+    })
+});
+
+encode_impl!(LoginSuccessPacket, self, tgt, version, {
+    if version.has_uuid_in_login_success() {
+        self.uuid.encode(tgt, version).await?;
+    } else {
+        LengthCappedString::<36>(Cow::Borrowed(unsafe {
+            from_utf8_unchecked(&self.uuid.to_ascii_bytes_hyphenated())
+        }))
+        .encode(tgt, version)
+        .await?;
+    }
+    LengthCappedString::<16>(Cow::Owned(self.username))
+        .encode(tgt, version)
+        .await
+});
+
 impl Client {
     #[allow(clippy::type_complexity)]
     pub const NO_LOGIN_PLUGIN_HANDLER: fn(Cow<str>, Vec<u8>) -> Ready<Option<Vec<u8>>> =
         |_, _| ready(None);
+    pub const NO_COOKIE_HANDLER: fn(Identifier<'static>) -> Option<Vec<u8>> = |_| None;
+    // Cookie payloads are capped by the vanilla client
+    pub const MAX_COOKIE_LEN: usize = 5120;
     pub async fn login<'a, P: Future<Output = Option<impl AsRef<[u8]>>>>(
         &mut self,
         mut client: Option<HTTPClient>,
+        session_server: &SessionServerConfig,
         authenticator: impl Authenticator,
         mut plugin_handler: impl FnMut(Cow<'a, str>, Vec<u8>) -> P,
+        mut cookie_handler: impl FnMut(Identifier<'static>) -> Option<Vec<u8>>,
     ) -> Result<Player<'a>, Error> {
         if self.state == State::Login {
             let username = authenticator.username();
@@ -73,7 +132,7 @@ impl Client {
                                 .into_owned(),
                         )));
                     }
-                    1 => {
+                    1 if self.version.supports_encryption() => {
                         let server_id =
                             LengthCappedString::<20>::decode(&mut packet.content, self.version)
                                 .await?;
@@ -157,7 +216,7 @@ impl Client {
                         client
                             .take()
                             .unwrap_or_default()
-                            .post("https://sessionserver.mojang.com/session/minecraft/join")
+                            .post(&session_server.join_url)
                             .json(&FullLoginCredentials {
                                 access_token: &credentials.access_token,
                                 selected_profile: credentials.uuid,
@@ -220,39 +279,24 @@ impl Client {
                             .await?;
                         response_packet.write_all(&encrypted_verify_token).await?;
                         response_packet.shutdown().await?;
-                        if !self.inbound.conn.set_key(shared_secret)
-                            || !self.outbound.conn.set_key(shared_secret)
-                        {
-                            return Err(ProtocolError::Malformed.into());
-                        }
+                        self.inbound.conn.set_key(shared_secret)?;
+                        self.outbound.conn.set_key(shared_secret)?;
                     }
                     2 => {
-                        let response = Player {
-                            uuid: if self.version < ProtocolVersion::V1_16 {
-                                LengthCappedString::<36>::decode(&mut packet.content, self.version)
-                                    .await?
-                                    .0
-                                    .parse()?
-                            } else {
-                                UUID::decode(&mut packet.content, self.version).await?
-                            },
-                            username: LengthCappedString::<16>::decode(
-                                &mut packet.content,
-                                self.version,
-                            )
-                            .await?
-                            .0,
-                        };
-                        packet.content.finished()?;
+                        let LoginSuccessPacket { uuid, username } =
+                            packet.parse(self.version).await?.packet;
                         self.state = State::Play;
-                        break Ok(response);
+                        break Ok(Player {
+                            uuid,
+                            username: Cow::Owned(username),
+                        });
                     }
-                    3 => {
+                    3 if self.version.supports_compression() => {
                         let new_threshold =
                             VarInt::decode(&mut packet.content, self.version).await?.0 as usize;
                         packet.content.finished()?;
                         self.inbound.compressed = true;
-                        self.outbound.compress_threshold = Some(new_threshold);
+                        self.outbound.enable_compression(new_threshold);
                     }
                     4 => {
                         let message_id = VarInt::decode(&mut packet.content, self.version).await?;
@@ -292,6 +336,35 @@ impl Client {
                             response_packet.shutdown().await?;
                         }
                     }
+                    5 => {
+                        // Cookie Request, added in 1.20.5
+                        let key = Identifier::decode(&mut packet.content, self.version).await?;
+                        packet.content.finished()?;
+                        let key_len = VarInt(key.0.len() as i32).len() + key.0.len();
+                        let payload = cookie_handler(key.clone());
+                        let payload_len = match payload {
+                            Some(ref payload) if payload.len() > Client::MAX_COOKIE_LEN => {
+                                return Err(ProtocolError::Malformed.into());
+                            }
+                            Some(ref payload) => VarInt(payload.len() as i32).len() + payload.len(),
+                            None => 0,
+                        };
+                        let mut response_packet = self
+                            .outbound
+                            .create_packet(4, Some(key_len + 1 + payload_len))
+                            .await?;
+                        key.encode(&mut response_packet, self.version).await?;
+                        if let Some(payload) = payload {
+                            (true).encode(&mut response_packet, self.version).await?;
+                            VarInt(payload.len() as i32)
+                                .encode(&mut response_packet, self.version)
+                                .await?;
+                            response_packet.write_all(&payload).await?;
+                        } else {
+                            (false).encode(&mut response_packet, self.version).await?;
+                        }
+                        response_packet.shutdown().await?;
+                    }
                     _ => return Err(ProtocolError::Malformed.into()),
                 }
             }
@@ -338,29 +411,15 @@ impl ServerConnection {
                 ServerLoginCredentials::OfflineMode(player) => player,
                 ServerLoginCredentials::OnlineMode => todo!(),
             };
-            if self.version < ProtocolVersion::V1_16 {
-                let mut out_packet = self
-                    .outbound
-                    .create_packet(2, Some(player.username.len() + 38))
-                    .await?;
-                LengthCappedString::<36>(Cow::Borrowed(unsafe {
-                    from_utf8_unchecked(&player.uuid.to_ascii_bytes_hyphenated())
-                }))
-                .encode(&mut out_packet, self.version)
+            let success = LoginSuccessPacket {
+                uuid: player.uuid,
+                username: player.username.to_string(),
+            };
+            let mut out_packet = self
+                .outbound
+                .create_packet(2, Some(success.len(self.version)))
                 .await?;
-                LengthCappedString::<16>(Cow::Borrowed(&player.username))
-                    .encode(&mut out_packet, self.version)
-                    .await?;
-            } else {
-                let mut out_packet = self
-                    .outbound
-                    .create_packet(2, Some(player.username.len() + 17))
-                    .await?;
-                player.uuid.encode(&mut out_packet, self.version).await?;
-                LengthCappedString::<16>(Cow::Borrowed(&player.username))
-                    .encode(&mut out_packet, self.version)
-                    .await?;
-            }
+            success.encode(&mut out_packet, self.version).await?;
             self.state = State::Play;
             Ok(player)
         } else {
@@ -368,3 +427,31 @@ impl ServerConnection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::LoginSuccessPacket;
+    use crate::{
+        connection::codec::OwnedPacket,
+        protocol::{types::Encode, version::ProtocolVersion},
+    };
+    use tokio::test;
+
+    #[test]
+    async fn login_success() {
+        let success = LoginSuccessPacket {
+            uuid: "853c80ef-3c37-49fd-aa49-938b674adae6".parse().unwrap(),
+            username: "jeb_".into(),
+        };
+        for version in [ProtocolVersion::V1_8_9, ProtocolVersion::V1_16] {
+            let mut content = Vec::new();
+            success.clone().encode(&mut content, version).await.unwrap();
+            assert_eq!(content.len(), success.len(version));
+            let parsed = OwnedPacket::new(2, content)
+                .parse::<LoginSuccessPacket>(version)
+                .await
+                .unwrap();
+            assert_eq!(parsed.packet, success);
+        }
+    }
+}
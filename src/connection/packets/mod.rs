@@ -1,3 +1,4 @@
 pub mod handshaking;
 pub mod login;
+pub mod play;
 pub mod status;
@@ -0,0 +1,592 @@
+use crate::{
+    connection::{
+        codec::{drain_exact, OutboundConnection},
+        error::Error as ConnectionError,
+        ServerConnection, State,
+    },
+    protocol::{
+        error::Error,
+        types::{
+            decode_impl, decode_inner_impl, encode_impl, encode_inner_impl, handle_io_err, Chat,
+            Decode, Encode, LengthCappedString, VarInt, UUID,
+        },
+        version::ProtocolVersion,
+    },
+};
+use std::{borrow::Cow, collections::VecDeque, convert::TryFrom, sync::Mutex, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time, try_join,
+};
+
+pub fn clientbound_keep_alive_id(version: ProtocolVersion) -> i32 {
+    match version {
+        ProtocolVersion::V1_8_9 => 0x00,
+        ProtocolVersion::V1_12 | ProtocolVersion::V1_16_4 => 0x1F,
+        ProtocolVersion::V1_14_4 | ProtocolVersion::V1_16 => 0x20,
+    }
+}
+
+pub fn serverbound_keep_alive_id(version: ProtocolVersion) -> i32 {
+    match version {
+        ProtocolVersion::V1_8_9 => 0x00,
+        ProtocolVersion::V1_12 => 0x0B,
+        ProtocolVersion::V1_14_4 => 0x0F,
+        ProtocolVersion::V1_16 | ProtocolVersion::V1_16_4 => 0x10,
+    }
+}
+
+pub fn clientbound_chat_id(version: ProtocolVersion) -> i32 {
+    match version {
+        ProtocolVersion::V1_8_9 => 0x02,
+        ProtocolVersion::V1_12 => 0x0F,
+        ProtocolVersion::V1_14_4 | ProtocolVersion::V1_16 | ProtocolVersion::V1_16_4 => 0x0E,
+    }
+}
+
+pub fn clientbound_plugin_message_id(version: ProtocolVersion) -> i32 {
+    match version {
+        ProtocolVersion::V1_8_9 => 0x3F,
+        ProtocolVersion::V1_12 | ProtocolVersion::V1_14_4 | ProtocolVersion::V1_16 => 0x18,
+        ProtocolVersion::V1_16_4 => 0x17,
+    }
+}
+
+pub fn clientbound_disconnect_id(version: ProtocolVersion) -> i32 {
+    match version {
+        ProtocolVersion::V1_8_9 => 0x40,
+        ProtocolVersion::V1_12 | ProtocolVersion::V1_14_4 | ProtocolVersion::V1_16 => 0x1A,
+        ProtocolVersion::V1_16_4 => 0x19,
+    }
+}
+
+// Renamed along with the other channels in 1.13
+fn brand_channel(version: ProtocolVersion) -> &'static str {
+    if version.is_at_least(ProtocolVersion::V1_14_4) {
+        "minecraft:brand"
+    } else {
+        "MC|Brand"
+    }
+}
+
+pub const DEFAULT_BRAND: &str = "mc-proxy";
+
+// Chat message positions
+pub const CHAT_POSITION_CHAT: u8 = 0;
+pub const CHAT_POSITION_SYSTEM: u8 = 1;
+pub const CHAT_POSITION_ACTION_BAR: u8 = 2;
+
+// Helpers for the packets the proxy sends to the local client itself
+impl<W: AsyncWriteExt + Unpin> OutboundConnection<W> {
+    pub async fn write_chat(
+        &mut self,
+        chat: Chat<'_>,
+        position: u8,
+    ) -> Result<(), ConnectionError> {
+        if position > CHAT_POSITION_ACTION_BAR {
+            return Err(Error::Malformed.into());
+        }
+        let version = self.version();
+        let mut packet = self
+            .create_packet(clientbound_chat_id(version), None)
+            .await?;
+        ChatPacket {
+            message: chat.into_owned(),
+            position,
+            sender: UUID([0; 16]),
+        }
+        .encode(&mut packet, version)
+        .await?;
+        packet.shutdown().await?;
+        Ok(())
+    }
+
+    pub async fn write_plugin_message(
+        &mut self,
+        channel: &str,
+        data: &[u8],
+    ) -> Result<(), ConnectionError> {
+        let version = self.version();
+        let channel = LengthCappedString::<32767>(Cow::Borrowed(channel));
+        let mut packet = self
+            .create_packet(
+                clientbound_plugin_message_id(version),
+                Some(VarInt(channel.0.len() as i32).len() + channel.0.len() + data.len()),
+            )
+            .await?;
+        channel.encode(&mut packet, version).await?;
+        packet.write_all(data).await?;
+        packet.shutdown().await?;
+        Ok(())
+    }
+
+    pub async fn write_disconnect(&mut self, reason: Chat<'_>) -> Result<(), ConnectionError> {
+        let version = self.version();
+        let mut packet = self
+            .create_packet(clientbound_disconnect_id(version), None)
+            .await?;
+        reason.encode(&mut packet, version).await?;
+        packet.shutdown().await?;
+        Ok(())
+    }
+}
+
+// Same format in both directions
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeepAlive(pub i64);
+
+impl KeepAlive {
+    pub fn len(&self, version: ProtocolVersion) -> usize {
+        if version.is_at_least(ProtocolVersion::V1_12) {
+            8
+        } else {
+            VarInt(self.0 as i32).len()
+        }
+    }
+}
+
+decode_impl!(KeepAlive, src, version, {
+    Ok(KeepAlive(if version.is_at_least(ProtocolVersion::V1_12) {
+        i64::decode(src, version).await?
+    } else {
+        VarInt::decode(src, version).await?.0 as i64
+    }))
+});
+
+encode_impl!(KeepAlive, self, tgt, version, {
+    if version.is_at_least(ProtocolVersion::V1_12) {
+        self.0.encode(tgt, version).await
+    } else {
+        VarInt(self.0 as i32).encode(tgt, version).await
+    }
+});
+
+// Clientbound chat
+#[derive(Clone, Debug)]
+pub struct ChatPacket {
+    pub message: Chat<'static>,
+    pub position: u8,
+    // All zeroes for messages not sent by a player, and before 1.16, where it
+    // isn't sent at all
+    pub sender: UUID,
+}
+
+decode_impl!(ChatPacket, src, version, {
+    Ok(ChatPacket {
+        message: Chat::decode(src, version).await?.into_owned(),
+        position: u8::decode(src, version).await?,
+        sender: if version.is_at_least(ProtocolVersion::V1_16) {
+            UUID::decode(src, version).await?
+        } else {
+            UUID([0; 16])
+        },
+    })
+});
+
+encode_impl!(ChatPacket, self, tgt, version, {
+    self.message.encode(tgt, version).await?;
+    self.position.encode(tgt, version).await?;
+    if version.is_at_least(ProtocolVersion::V1_16) {
+        self.sender.encode(tgt, version).await?;
+    }
+    Ok(())
+});
+
+// What a Player Info packet says about one player. Properties (i.e. skins)
+// and display names aren't needed and are skipped.
+#[derive(Clone, Debug)]
+pub enum PlayerInfoAction {
+    Add {
+        name: String,
+        gamemode: u8,
+        ping: i32,
+    },
+    UpdateGamemode(u8),
+    UpdateLatency(i32),
+    UpdateDisplayName,
+    Remove,
+}
+
+// Every player in the packet has the same kind of action
+#[derive(Clone, Debug)]
+pub struct PlayerInfoPacket {
+    pub players: Vec<(UUID, PlayerInfoAction)>,
+}
+
+decode_impl!(PlayerInfoPacket, src, version, {
+    let action = VarInt::decode(src, version).await?.0;
+    let num_players = VarInt::decode(src, version).await?.0;
+    let mut players = Vec::new();
+    for _ in 0..num_players {
+        let uuid = UUID::decode(src, version).await?;
+        players.push((
+            uuid,
+            match action {
+                0 => {
+                    let name = LengthCappedString::<16>::decode(src, version)
+                        .await?
+                        .0
+                        .into_owned();
+                    for _ in 0..VarInt::decode(src, version).await?.0 {
+                        // Name, value and signature
+                        skip_string(src, version).await?;
+                        skip_string(src, version).await?;
+                        if bool::decode(src, version).await? {
+                            skip_string(src, version).await?;
+                        }
+                    }
+                    let gamemode = VarInt::decode(src, version).await?.0 as u8;
+                    let ping = VarInt::decode(src, version).await?.0;
+                    decode_optional_chat(src, version).await?;
+                    PlayerInfoAction::Add {
+                        name,
+                        gamemode,
+                        ping,
+                    }
+                }
+                1 => PlayerInfoAction::UpdateGamemode(VarInt::decode(src, version).await?.0 as u8),
+                2 => PlayerInfoAction::UpdateLatency(VarInt::decode(src, version).await?.0),
+                3 => {
+                    decode_optional_chat(src, version).await?;
+                    PlayerInfoAction::UpdateDisplayName
+                }
+                4 => PlayerInfoAction::Remove,
+                _ => return Err(Error::Malformed),
+            },
+        ));
+    }
+    Ok(PlayerInfoPacket { players })
+});
+
+impl ServerConnection {
+    // The server brand shown on the local client's debug screen
+    pub async fn send_brand(&mut self, brand: &str) -> Result<(), ConnectionError> {
+        if self.state != State::Play {
+            return Err(ConnectionError::InvalidState);
+        }
+        let mut data = Vec::new();
+        LengthCappedString::<32767>(Cow::Borrowed(brand))
+            .encode(&mut data, self.version)
+            .await?;
+        self.outbound
+            .write_plugin_message(brand_channel(self.version), &data)
+            .await
+    }
+
+    // Sends the keep alives the upstream server would when there is none, so
+    // the local client doesn't time out. Everything else the client sends is
+    // dropped, and answering a keep alive that wasn't sent, or was already
+    // answered, is an error.
+    pub async fn keep_alive_loop(&mut self, interval: Duration) -> Result<(), ConnectionError> {
+        if self.state != State::Play {
+            return Err(ConnectionError::InvalidState);
+        }
+        // Clients that lag answer late, by which time the next keep alive
+        // may have been sent
+        const MAX_PENDING_KEEP_ALIVES: usize = 4;
+        let version = self.version;
+        let pending = Mutex::new(VecDeque::new());
+        let outbound = &mut self.outbound;
+        let inbound = &mut self.inbound;
+        try_join!(
+            async {
+                let mut ticker = time::interval(interval);
+                let mut id = 0;
+                loop {
+                    ticker.tick().await;
+                    id += 1;
+                    let keep_alive = KeepAlive(id);
+                    {
+                        let mut pending = pending.lock().unwrap();
+                        if pending.len() == MAX_PENDING_KEEP_ALIVES {
+                            pending.pop_front();
+                        }
+                        pending.push_back(id);
+                    }
+                    let mut packet = outbound
+                        .create_packet(
+                            clientbound_keep_alive_id(version),
+                            Some(keep_alive.len(version)),
+                        )
+                        .await?;
+                    keep_alive.encode(&mut packet, version).await?;
+                    packet.shutdown().await?;
+                }
+                #[allow(unreachable_code)]
+                Ok::<(), ConnectionError>(())
+            },
+            async {
+                loop {
+                    let mut packet = inbound.next_packet().await?;
+                    if packet.id != serverbound_keep_alive_id(version) {
+                        packet.content.close().await?;
+                        continue;
+                    }
+                    let keep_alive = packet.parse::<KeepAlive>(version).await?.packet;
+                    let mut pending = pending.lock().unwrap();
+                    match pending.iter().position(|&id| id == keep_alive.0) {
+                        Some(i) => {
+                            pending.remove(i);
+                        }
+                        None => return Err(ConnectionError::UnexpectedKeepAlive(keep_alive.0)),
+                    }
+                }
+                #[allow(unreachable_code)]
+                Ok::<(), ConnectionError>(())
+            }
+        )?;
+        Ok(())
+    }
+}
+
+// Only the length is decoded, and the rest is drained
+async fn skip_string<R: AsyncReadExt + Unpin>(
+    src: &mut R,
+    version: ProtocolVersion,
+) -> Result<(), Error> {
+    let len =
+        usize::try_from(VarInt::decode(src, version).await?.0).map_err(|_| Error::Malformed)?;
+    drain_exact(src, len).await.map_err(handle_io_err)
+}
+
+// Display names in Player Info and similar fields are prefixed with whether
+// they are present
+pub(crate) async fn decode_optional_chat<R: AsyncReadExt + Unpin>(
+    src: &mut R,
+    version: ProtocolVersion,
+) -> Result<Option<Chat<'static>>, Error> {
+    Ok(if bool::decode(src, version).await? {
+        Some(Chat::decode(src, version).await?.into_owned())
+    } else {
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        brand_channel, clientbound_chat_id, clientbound_keep_alive_id,
+        clientbound_plugin_message_id, serverbound_keep_alive_id, KeepAlive, PlayerInfoAction,
+        PlayerInfoPacket, CHAT_POSITION_SYSTEM, DEFAULT_BRAND,
+    };
+    use crate::{
+        connection::{
+            codec::{InboundConnection, OutboundConnection, OwnedPacket},
+            error::Error,
+            socket_pair, ServerConnection, State,
+        },
+        protocol::{
+            types::{Chat, Decode, Encode, LengthCappedString, VarInt, UUID},
+            version::ProtocolVersion,
+        },
+    };
+    use std::time::Duration;
+    use tokio::{
+        io::{duplex, AsyncWriteExt},
+        test,
+    };
+
+    #[test]
+    async fn write_chat() {
+        for version in [
+            ProtocolVersion::V1_8_9,
+            ProtocolVersion::V1_16,
+            ProtocolVersion::V1_16_4,
+        ] {
+            let (client, server) = duplex(256);
+            let mut outbound = OutboundConnection::new(client, version);
+            let mut inbound = InboundConnection::new(server, version);
+            outbound
+                .write_chat(Chat::Raw("§aHello".into()), CHAT_POSITION_SYSTEM)
+                .await
+                .unwrap();
+            outbound.conn.flush().await.unwrap();
+
+            let mut packet = inbound.next_packet().await.unwrap();
+            assert_eq!(packet.id, clientbound_chat_id(version));
+            match Chat::decode(&mut packet.content, version).await.unwrap() {
+                Chat::Raw(text) => assert_eq!(text, "§aHello"),
+                chat => panic!("unexpected chat {:?}", chat),
+            }
+            assert_eq!(
+                u8::decode(&mut packet.content, version).await.unwrap(),
+                CHAT_POSITION_SYSTEM
+            );
+            if version.is_at_least(ProtocolVersion::V1_16) {
+                assert_eq!(
+                    UUID::decode(&mut packet.content, version).await.unwrap(),
+                    UUID([0; 16])
+                );
+            }
+            packet.content.finished().unwrap();
+        }
+    }
+
+    #[test]
+    async fn player_info_skips_properties() {
+        let version = ProtocolVersion::V1_8_9;
+        let uuid = UUID::new_v4();
+        let mut content = Vec::new();
+        VarInt(0).encode(&mut content, version).await.unwrap();
+        VarInt(1).encode(&mut content, version).await.unwrap();
+        uuid.encode(&mut content, version).await.unwrap();
+        LengthCappedString::<16>("Steve".into())
+            .encode(&mut content, version)
+            .await
+            .unwrap();
+        let properties = [
+            ("textures", "a".repeat(1000), Some("b".repeat(700))),
+            ("other", "c".into(), None),
+        ];
+        VarInt(properties.len() as i32)
+            .encode(&mut content, version)
+            .await
+            .unwrap();
+        for (name, value, signature) in properties {
+            for field in [name.to_string(), value] {
+                LengthCappedString::<32767>(field.into())
+                    .encode(&mut content, version)
+                    .await
+                    .unwrap();
+            }
+            signature
+                .is_some()
+                .encode(&mut content, version)
+                .await
+                .unwrap();
+            if let Some(signature) = signature {
+                LengthCappedString::<32767>(signature.into())
+                    .encode(&mut content, version)
+                    .await
+                    .unwrap();
+            }
+        }
+        // gamemode, ping, has display name
+        VarInt(1).encode(&mut content, version).await.unwrap();
+        VarInt(42).encode(&mut content, version).await.unwrap();
+        false.encode(&mut content, version).await.unwrap();
+
+        let info = OwnedPacket::new(0x38, content)
+            .parse::<PlayerInfoPacket>(version)
+            .await
+            .unwrap()
+            .packet;
+        assert_eq!(info.players.len(), 1);
+        assert_eq!(info.players[0].0, uuid);
+        match &info.players[0].1 {
+            PlayerInfoAction::Add {
+                name,
+                gamemode,
+                ping,
+            } => assert_eq!((name.as_str(), *gamemode, *ping), ("Steve", 1, 42)),
+            action => panic!("unexpected action {:?}", action),
+        }
+    }
+
+    #[test]
+    async fn send_brand() {
+        for version in [ProtocolVersion::V1_8_9, ProtocolVersion::V1_16] {
+            let (client, server) = socket_pair().await;
+            let mut inbound = InboundConnection::new(client, version);
+            let mut conn = ServerConnection::new(server).await;
+            conn.version = version;
+            conn.outbound.set_version(version);
+            assert!(matches!(
+                conn.send_brand(DEFAULT_BRAND).await,
+                Err(Error::InvalidState)
+            ));
+            conn.state = State::Play;
+            conn.send_brand(DEFAULT_BRAND).await.unwrap();
+
+            let mut packet = inbound.next_packet().await.unwrap();
+            assert_eq!(packet.id, clientbound_plugin_message_id(version));
+            for expected in [brand_channel(version), DEFAULT_BRAND] {
+                assert_eq!(
+                    LengthCappedString::<32767>::decode(&mut packet.content, version)
+                        .await
+                        .unwrap()
+                        .0,
+                    expected
+                );
+            }
+            packet.content.finished().unwrap();
+        }
+    }
+
+    #[test]
+    async fn keep_alive_loop() {
+        let version = ProtocolVersion::V1_8_9;
+        let (client, server) = socket_pair().await;
+        let (read_half, write_half) = client.into_split();
+        let mut inbound = InboundConnection::new(read_half, version);
+        let mut outbound = OutboundConnection::new(write_half, version);
+        let mut conn = ServerConnection::new(server).await;
+        conn.version = version;
+        conn.inbound.set_version(version);
+        conn.outbound.set_version(version);
+
+        assert!(matches!(
+            conn.keep_alive_loop(Duration::from_secs(60)).await,
+            Err(Error::InvalidState)
+        ));
+        conn.state = State::Play;
+        let server =
+            tokio::spawn(async move { conn.keep_alive_loop(Duration::from_secs(60)).await });
+
+        // Sent right away, then answered along with something to ignore
+        let packet = inbound.next_packet().await.unwrap();
+        assert_eq!(packet.id, clientbound_keep_alive_id(version));
+        let keep_alive = packet.parse::<KeepAlive>(version).await.unwrap().packet;
+        let mut answer = Vec::new();
+        keep_alive.encode(&mut answer, version).await.unwrap();
+        let answer = OwnedPacket::new(serverbound_keep_alive_id(version), answer);
+        outbound
+            .emit_owned(&OwnedPacket::new(0x01, vec![0]))
+            .await
+            .unwrap();
+        outbound.emit_owned(&answer).await.unwrap();
+
+        // Answered twice
+        outbound.emit_owned(&answer).await.unwrap();
+        match server.await.unwrap() {
+            Err(Error::UnexpectedKeepAlive(id)) => assert_eq!(id, keep_alive.0),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    async fn keep_alive_answered_late() {
+        let version = ProtocolVersion::V1_8_9;
+        let (client, server) = socket_pair().await;
+        let (read_half, write_half) = client.into_split();
+        let mut inbound = InboundConnection::new(read_half, version);
+        let mut outbound = OutboundConnection::new(write_half, version);
+        let mut conn = ServerConnection::new(server).await;
+        conn.version = version;
+        conn.inbound.set_version(version);
+        conn.outbound.set_version(version);
+        conn.state = State::Play;
+        let server =
+            tokio::spawn(async move { conn.keep_alive_loop(Duration::from_secs(1)).await });
+
+        let mut answers = Vec::new();
+        for _ in 0..2 {
+            let packet = inbound.next_packet().await.unwrap();
+            let keep_alive = packet.parse::<KeepAlive>(version).await.unwrap().packet;
+            let mut answer = Vec::new();
+            keep_alive.encode(&mut answer, version).await.unwrap();
+            answers.push((
+                keep_alive.0,
+                OwnedPacket::new(serverbound_keep_alive_id(version), answer),
+            ));
+        }
+        // The first is still accepted after the second was sent, but only once
+        outbound.emit_owned(&answers[0].1).await.unwrap();
+        outbound.emit_owned(&answers[1].1).await.unwrap();
+        outbound.emit_owned(&answers[0].1).await.unwrap();
+        match server.await.unwrap() {
+            Err(Error::UnexpectedKeepAlive(id)) => assert_eq!(id, answers[0].0),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
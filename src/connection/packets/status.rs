@@ -1,6 +1,6 @@
 use std::borrow::Cow;
 
-use crate::connection::{error::Error, Client, State};
+use crate::connection::{codec::OwnedPacket, error::Error, Client, ServerConnection, State};
 use crate::protocol::types::Chat;
 use crate::protocol::{
     error::Error as ProtocolError,
@@ -8,37 +8,46 @@ use crate::protocol::{
 };
 
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use serde_with::skip_serializing_none;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Version<'a> {
-    name: Cow<'a, str>,
-    protocol: i32,
+    pub name: Cow<'a, str>,
+    pub protocol: i32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SamplePlayer<'a> {
-    name: Cow<'a, str>,
-    id: UUID,
+    pub name: Cow<'a, str>,
+    pub id: UUID,
 }
 
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Players<'a> {
-    max: usize,
-    online: usize,
-    sample: Option<Vec<SamplePlayer<'a>>>,
+    pub max: usize,
+    pub online: usize,
+    pub sample: Option<Vec<SamplePlayer<'a>>>,
 }
 
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Status<'a> {
-    version: Version<'a>,
-    players: Players<'a>,
-    description: Chat<'a>,
-    favicon: Option<Cow<'a, str>>,
+    pub version: Version<'a>,
+    pub players: Players<'a>,
+    pub description: Chat<'a>,
+    pub favicon: Option<Cow<'a, str>>,
+    // Anything else the server sent, e.g. Forge's mod list, which is passed
+    // on unchanged
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+// Changes what a server says about itself before the local client sees it,
+// e.g. the MOTD or player count
+pub type StatusTransform = dyn Fn(Status<'static>) -> Status<'static> + Send + Sync;
+
 impl Client {
     pub async fn status(&mut self) -> Result<Status<'_>, Error> {
         if self.state == State::Status {
@@ -75,3 +84,163 @@ impl Client {
         }
     }
 }
+
+impl ServerConnection {
+    // Answers a status ping from the local client with the status of the
+    // server client is connected to. Without a transform the response is
+    // forwarded untouched. The ping that follows is answered here, since the
+    // latency to the server is not what the local client is measuring.
+    pub async fn status_proxy(
+        &mut self,
+        client: &mut Client,
+        transform: Option<&StatusTransform>,
+    ) -> Result<(), Error> {
+        if self.state != State::Status || client.state != State::Status {
+            return Err(Error::InvalidState);
+        }
+        let version = self.version;
+        let packet = self.inbound.next_packet().await?;
+        if packet.id != 0 || packet.len != 0 {
+            return Err(ProtocolError::Malformed.into());
+        }
+        client.outbound.create_packet(0, Some(0)).await?;
+        match transform {
+            Some(transform) => {
+                let mut packet = client.inbound.next_packet().await?;
+                if packet.id != 0 {
+                    return Err(ProtocolError::Malformed.into());
+                }
+                let status_str =
+                    LengthCappedString::<32767>::decode(&mut packet.content, client.version)
+                        .await?
+                        .0;
+                packet.content.finished()?;
+                // The transform can only change statuses that fit Status, so
+                // anything else is passed on as it is
+                let status_str = match serde_json::from_str(&status_str) {
+                    Ok(status) => Cow::Owned(
+                        serde_json::to_string(&transform(status))
+                            .map_err(|err| ProtocolError::InvalidJson(err.to_string()))?,
+                    ),
+                    Err(_) => status_str,
+                };
+                let mut content = Vec::new();
+                LengthCappedString::<32767>(status_str)
+                    .encode(&mut content, version)
+                    .await?;
+                self.send_packet(&OwnedPacket::new(0, content)).await?;
+            }
+            None => {
+                self.outbound.pipe_packet_from(&mut client.inbound).await?;
+            }
+        }
+        // The pong is the ping's payload sent straight back
+        let ping = self.inbound.next_owned_packet().await?;
+        if ping.id != 1 || ping.content.len() != 8 {
+            return Err(ProtocolError::Malformed.into());
+        }
+        self.send_packet(&ping).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Status;
+    use crate::{
+        connection::{
+            codec::{InboundConnection, OutboundConnection},
+            error::Error,
+            socket_pair, Client, Connection, ServerAddress, ServerConnection, State,
+        },
+        protocol::{
+            types::{Chat, Decode, Encode, LengthCappedString},
+            version::ProtocolVersion,
+        },
+    };
+    use std::borrow::Cow;
+    use tokio::{io::AsyncWriteExt, test};
+
+    const STATUS: &str = r#"{"version":{"name":"Requires MC 1.8 / 1.18","protocol":47},"players":{"max":200000,"online":95000},"description":"Hypixel Network","modinfo":{"type":"FML","modList":[]}}"#;
+
+    #[test]
+    async fn status_proxy() {
+        let version = ProtocolVersion::V1_8_9;
+        let (local, proxy) = socket_pair().await;
+        let (upstream, server) = socket_pair().await;
+        let (read_half, write_half) = local.into_split();
+        let mut local_in = InboundConnection::new(read_half, version);
+        let mut local_out = OutboundConnection::new(write_half, version);
+        let (read_half, write_half) = server.into_split();
+        let mut server_in = InboundConnection::new(read_half, version);
+        let mut server_out = OutboundConnection::new(write_half, version);
+
+        let mut conn = ServerConnection::new(proxy).await;
+        conn.version = version;
+        conn.inbound.set_version(version);
+        conn.outbound.set_version(version);
+        let (read_half, write_half) = upstream.into_split();
+        let mut client: Client = Connection::from_halves(
+            read_half,
+            write_half,
+            version,
+            ServerAddress {
+                host: "mc.hypixel.net".into(),
+                port: 25565,
+            },
+        );
+        client.state = State::Status;
+        assert!(matches!(
+            conn.status_proxy(&mut client, None).await,
+            Err(Error::InvalidState)
+        ));
+        conn.state = State::Status;
+        let proxy = tokio::spawn(async move {
+            conn.status_proxy(
+                &mut client,
+                Some(&|mut status: Status<'static>| {
+                    status.description = Chat::Raw("§aAlt server".into());
+                    status.players.online = 1;
+                    status
+                }),
+            )
+            .await
+        });
+
+        local_out.create_packet(0, Some(0)).await.unwrap();
+        let packet = server_in.next_packet().await.unwrap();
+        assert_eq!((packet.id, packet.len), (0, 0));
+        let mut packet = server_out.create_packet(0, None).await.unwrap();
+        LengthCappedString::<32767>(Cow::Borrowed(STATUS))
+            .encode(&mut packet, version)
+            .await
+            .unwrap();
+        packet.shutdown().await.unwrap();
+
+        let mut packet = local_in.next_packet().await.unwrap();
+        assert_eq!(packet.id, 0);
+        let status_str = LengthCappedString::<32767>::decode(&mut packet.content, version)
+            .await
+            .unwrap()
+            .0;
+        let status: Status = serde_json::from_str(&status_str).unwrap();
+        assert_eq!(status.description, Chat::Raw("§aAlt server".into()));
+        assert_eq!((status.players.online, status.players.max), (1, 200000));
+        assert_eq!(status.version.name, "Requires MC 1.8 / 1.18");
+        // Fields Status doesn't know about survive the transform
+        assert_eq!(status.extra["modinfo"]["type"], "FML");
+
+        // The ping is answered by the proxy, not the server
+        42i64
+            .encode(
+                &mut local_out.create_packet(1, Some(8)).await.unwrap(),
+                version,
+            )
+            .await
+            .unwrap();
+        let mut packet = local_in.next_packet().await.unwrap();
+        assert_eq!(packet.id, 1);
+        assert_eq!(i64::decode(&mut packet.content, version).await.unwrap(), 42);
+        proxy.await.unwrap().unwrap();
+    }
+}
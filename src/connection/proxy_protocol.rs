@@ -0,0 +1,354 @@
+use super::error::Error;
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::from_utf8,
+};
+use tokio::io::AsyncReadExt;
+
+// Starts every version 2 header, and can't be mistaken for a version 1 one
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// Including "PROXY " and the CRLF at the end
+const V1_MAX_LEN: usize = 107;
+const V2_LOCAL: u8 = 0x0;
+const V2_PROXY: u8 = 0x1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressFamily {
+    Unspecified,
+    Inet,
+    Inet6,
+    Unix,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportProtocol {
+    Unspecified,
+    Stream,
+    Datagram,
+}
+
+// What a load balancer in front of the proxy says about the connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub family: AddressFamily,
+    pub transport: TransportProtocol,
+    // The client and the address it connected to. None for health checks
+    // from the load balancer itself and for Unix sockets.
+    pub addresses: Option<(SocketAddr, SocketAddr)>,
+}
+
+// Reads the PROXY protocol header of either version, and nothing after it
+pub async fn read_header<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<ProxyHeader, Error> {
+    // Both versions are at least this long
+    let mut start = [0; 12];
+    reader.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        read_v2(reader).await
+    } else if start.starts_with(b"PROXY ") {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() == V1_MAX_LEN {
+                return Err(Error::InvalidProxyHeader);
+            }
+            line.push(reader.read_u8().await?);
+        }
+        parse_v1(&line[..line.len() - 2])
+    } else {
+        Err(Error::InvalidProxyHeader)
+    }
+}
+
+// e.g. PROXY TCP4 192.0.2.1 198.51.100.1 56324 25565
+fn parse_v1(line: &[u8]) -> Result<ProxyHeader, Error> {
+    let line = from_utf8(line).map_err(|_| Error::InvalidProxyHeader)?;
+    let mut fields = line.split(' ').skip(1);
+    let family = match fields.next() {
+        Some("TCP4") => AddressFamily::Inet,
+        Some("TCP6") => AddressFamily::Inet6,
+        // Anything may follow, so the rest is ignored
+        Some("UNKNOWN") => {
+            return Ok(ProxyHeader {
+                family: AddressFamily::Unspecified,
+                transport: TransportProtocol::Unspecified,
+                addresses: None,
+            })
+        }
+        _ => return Err(Error::InvalidProxyHeader),
+    };
+    let fields = fields.collect::<Vec<_>>();
+    let (source, destination, source_port, destination_port) = match fields[..] {
+        [source, destination, source_port, destination_port] => {
+            (source, destination, source_port, destination_port)
+        }
+        _ => return Err(Error::InvalidProxyHeader),
+    };
+    let ip = |addr: &str| -> Result<IpAddr, Error> {
+        let ip = match family {
+            AddressFamily::Inet => addr.parse::<Ipv4Addr>().map(IpAddr::V4),
+            _ => addr.parse::<Ipv6Addr>().map(IpAddr::V6),
+        };
+        ip.map_err(|_| Error::InvalidProxyHeader)
+    };
+    // Ports can't have leading zeroes or signs
+    let port = |port: &str| -> Result<u16, Error> {
+        match port.parse::<u16>() {
+            Ok(parsed) if parsed.to_string() == port => Ok(parsed),
+            _ => Err(Error::InvalidProxyHeader),
+        }
+    };
+    Ok(ProxyHeader {
+        family,
+        transport: TransportProtocol::Stream,
+        addresses: Some((
+            SocketAddr::new(ip(source)?, port(source_port)?),
+            SocketAddr::new(ip(destination)?, port(destination_port)?),
+        )),
+    })
+}
+
+// The signature is followed by the version and command, the family and
+// transport, the length of the rest, then the addresses and any TLVs
+async fn read_v2<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<ProxyHeader, Error> {
+    let version_command = reader.read_u8().await?;
+    if version_command >> 4 != 2 {
+        return Err(Error::UnsupportedProxyProtocol);
+    }
+    let family_transport = reader.read_u8().await?;
+    let mut rest = vec![0; reader.read_u16().await? as usize];
+    reader.read_exact(&mut rest).await?;
+    let family = match family_transport >> 4 {
+        0x0 => AddressFamily::Unspecified,
+        0x1 => AddressFamily::Inet,
+        0x2 => AddressFamily::Inet6,
+        0x3 => AddressFamily::Unix,
+        _ => return Err(Error::InvalidProxyHeader),
+    };
+    let transport = match family_transport & 0xF {
+        0x0 => TransportProtocol::Unspecified,
+        0x1 => TransportProtocol::Stream,
+        0x2 => TransportProtocol::Datagram,
+        _ => return Err(Error::InvalidProxyHeader),
+    };
+    let addresses = match version_command & 0xF {
+        // Sent by the load balancer itself, so the addresses mean nothing
+        V2_LOCAL => None,
+        V2_PROXY => match family {
+            AddressFamily::Inet if rest.len() >= 12 => {
+                let ip = |at: usize| {
+                    IpAddr::V4(Ipv4Addr::from(
+                        <[u8; 4]>::try_from(&rest[at..at + 4]).unwrap(),
+                    ))
+                };
+                Some(addresses(ip(0), ip(4), &rest[8..12]))
+            }
+            AddressFamily::Inet6 if rest.len() >= 36 => {
+                let ip = |at: usize| {
+                    IpAddr::V6(Ipv6Addr::from(
+                        <[u8; 16]>::try_from(&rest[at..at + 16]).unwrap(),
+                    ))
+                };
+                Some(addresses(ip(0), ip(16), &rest[32..36]))
+            }
+            AddressFamily::Inet | AddressFamily::Inet6 => return Err(Error::InvalidProxyHeader),
+            AddressFamily::Unspecified | AddressFamily::Unix => None,
+        },
+        _ => return Err(Error::UnsupportedProxyProtocol),
+    };
+    Ok(ProxyHeader {
+        family,
+        transport,
+        addresses,
+    })
+}
+
+fn addresses(source: IpAddr, destination: IpAddr, ports: &[u8]) -> (SocketAddr, SocketAddr) {
+    (
+        SocketAddr::new(source, u16::from_be_bytes([ports[0], ports[1]])),
+        SocketAddr::new(destination, u16::from_be_bytes([ports[2], ports[3]])),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_header, AddressFamily, ProxyHeader, TransportProtocol, V2_SIGNATURE};
+    use crate::connection::error::Error;
+    use std::net::{Ipv6Addr, SocketAddr};
+    use tokio::{io::AsyncReadExt, test};
+
+    // The header followed by the start of a handshake, which must be left
+    // unread
+    async fn parse(header: &[u8]) -> Result<ProxyHeader, Error> {
+        let bytes = [header, &[0x10, 0x00]].concat();
+        let mut reader = &bytes[..];
+        let res = read_header(&mut reader).await;
+        if res.is_ok() {
+            assert_eq!(reader.read_u16().await.unwrap(), 0x1000);
+        }
+        res
+    }
+
+    fn v2(version_command: u8, family_transport: u8, rest: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([version_command, family_transport]);
+        header.extend((rest.len() as u16).to_be_bytes());
+        header.extend(rest);
+        header
+    }
+
+    fn addrs(source: &str, destination: &str) -> Option<(SocketAddr, SocketAddr)> {
+        Some((source.parse().unwrap(), destination.parse().unwrap()))
+    }
+
+    #[test]
+    async fn v1() {
+        assert_eq!(
+            parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 25565\r\n")
+                .await
+                .unwrap(),
+            ProxyHeader {
+                family: AddressFamily::Inet,
+                transport: TransportProtocol::Stream,
+                addresses: addrs("192.0.2.1:56324", "198.51.100.1:25565"),
+            }
+        );
+        assert_eq!(
+            parse(b"PROXY TCP6 2001:db8::1 ::ffff:198.51.100.1 56324 25565\r\n")
+                .await
+                .unwrap(),
+            ProxyHeader {
+                family: AddressFamily::Inet6,
+                transport: TransportProtocol::Stream,
+                addresses: addrs("[2001:db8::1]:56324", "[::ffff:198.51.100.1]:25565"),
+            }
+        );
+        for header in [&b"PROXY UNKNOWN\r\n"[..], b"PROXY UNKNOWN ::1 ::1 1 2\r\n"] {
+            assert_eq!(
+                parse(header).await.unwrap(),
+                ProxyHeader {
+                    family: AddressFamily::Unspecified,
+                    transport: TransportProtocol::Unspecified,
+                    addresses: None,
+                }
+            );
+        }
+    }
+
+    #[test]
+    async fn invalid_v1() {
+        for header in [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 198.51.100.1 56324 25565\r\n",
+            b"PROXY TCP6 192.0.2.1 198.51.100.1 56324 25565\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 056324 25565\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 65536 25565\r\n",
+            b"PROXY TCP4  192.0.2.1 198.51.100.1 56324 25565\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 25565\r\n",
+            b"GET / HTTP/1.1\r\n",
+            // A handshake without a header
+            b"\x10\x00\xbc\x05\x0elocalhost\x63\xdd\x02",
+        ] {
+            assert!(
+                matches!(parse(header).await, Err(Error::InvalidProxyHeader)),
+                "{:?}",
+                String::from_utf8_lossy(header)
+            );
+        }
+        // Longer than any valid header, without a line ending
+        assert!(matches!(
+            parse(&[b"PROXY TCP6 ", &[b'1'; 200][..]].concat()).await,
+            Err(Error::InvalidProxyHeader)
+        ));
+    }
+
+    #[test]
+    async fn v2_inet() {
+        let rest = [192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x63, 0xDD];
+        assert_eq!(
+            parse(&v2(0x21, 0x11, &rest)).await.unwrap(),
+            ProxyHeader {
+                family: AddressFamily::Inet,
+                transport: TransportProtocol::Stream,
+                addresses: addrs("192.0.2.1:56324", "198.51.100.1:25565"),
+            }
+        );
+        // TLVs after the addresses are skipped
+        let with_tlvs = [&rest[..], &[0x04, 0x00, 0x01, 0xFF]].concat();
+        assert_eq!(
+            parse(&v2(0x21, 0x12, &with_tlvs)).await.unwrap().transport,
+            TransportProtocol::Datagram
+        );
+    }
+
+    #[test]
+    async fn v2_inet6() {
+        let mut rest = Vec::new();
+        rest.extend("2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        rest.extend(Ipv6Addr::LOCALHOST.octets());
+        rest.extend([0xDC, 0x04, 0x63, 0xDD]);
+        assert_eq!(
+            parse(&v2(0x21, 0x21, &rest)).await.unwrap(),
+            ProxyHeader {
+                family: AddressFamily::Inet6,
+                transport: TransportProtocol::Stream,
+                addresses: addrs("[2001:db8::1]:56324", "[::1]:25565"),
+            }
+        );
+    }
+
+    #[test]
+    async fn v2_without_addresses() {
+        // Health checks from the load balancer, with or without addresses
+        for (family_transport, rest) in [(0x00, &[][..]), (0x11, &[0; 12][..])] {
+            assert_eq!(
+                parse(&v2(0x20, family_transport, rest))
+                    .await
+                    .unwrap()
+                    .addresses,
+                None
+            );
+        }
+        assert_eq!(
+            parse(&v2(0x21, 0x31, &[0; 216])).await.unwrap(),
+            ProxyHeader {
+                family: AddressFamily::Unix,
+                transport: TransportProtocol::Stream,
+                addresses: None,
+            }
+        );
+        assert_eq!(
+            parse(&v2(0x21, 0x00, &[])).await.unwrap().family,
+            AddressFamily::Unspecified
+        );
+    }
+
+    #[test]
+    async fn invalid_v2() {
+        // Unknown commands and versions
+        for version_command in [0x22, 0x2F, 0x11, 0x31] {
+            assert!(matches!(
+                parse(&v2(version_command, 0x11, &[0; 12])).await,
+                Err(Error::UnsupportedProxyProtocol)
+            ));
+        }
+        for (family_transport, rest) in [
+            // Too short for the addresses
+            (0x11, &[0; 11][..]),
+            (0x21, &[0; 35][..]),
+            // Unknown family and transport
+            (0x41, &[0; 12][..]),
+            (0x13, &[0; 12][..]),
+        ] {
+            assert!(matches!(
+                parse(&v2(0x21, family_transport, rest)).await,
+                Err(Error::InvalidProxyHeader)
+            ));
+        }
+        // Cut off before the end of the addresses
+        let header = v2(0x21, 0x11, &[0; 12]);
+        assert!(matches!(
+            read_header(&mut &header[..header.len() - 1]).await,
+            Err(Error::IO(_))
+        ));
+    }
+}
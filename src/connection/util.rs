@@ -24,6 +24,11 @@ impl<S> Limit<S> {
     pub fn get_ref(&self) -> &S {
         &self.stream
     }
+
+    // For when the bytes were moved without going through this reader
+    pub fn consume(&mut self, amt: usize) {
+        self.limit -= min(amt, self.limit);
+    }
 }
 
 impl<R: AsyncReadExt + Unpin> AsyncRead for Limit<R> {
@@ -0,0 +1,178 @@
+use super::{
+    codec::{IncomingInnerPacket, IncomingPacket, OutboundConnection},
+    error::Error,
+};
+use crate::protocol::types::{Encode, VarInt};
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+};
+
+// Forwards the rest of a packet with splice(2) so its body never gets copied
+// into userspace. Only possible when neither side is encrypted and the writer
+// isn't compressing; otherwise nothing is consumed or written and this returns
+// false so the caller can copy the packet as usual.
+pub async fn try_zero_copy_forward(
+    packet: &mut IncomingPacket<'_, OwnedReadHalf>,
+    writer: &mut OutboundConnection<OwnedWriteHalf>,
+) -> Result<bool, Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let reader = match &mut packet.content {
+            IncomingInnerPacket::Normal(reader) => reader,
+            IncomingInnerPacket::Decompressed(_) => return Ok(false),
+        };
+        if reader.get_ref().is_encrypted()
+            || writer.conn.is_encrypted()
+            || writer.compress_threshold().is_some()
+        {
+            return Ok(false);
+        }
+        let len = reader.remaining();
+        let id = VarInt(packet.id);
+        VarInt((len + id.len()) as i32)
+            .encode(&mut writer.conn, writer.version)
+            .await?;
+        id.encode(&mut writer.conn, writer.version).await?;
+        writer.conn.flush().await?;
+        linux::splice_exact(reader.get_ref().get_ref(), writer.conn.get_ref(), len).await?;
+        reader.consume(len);
+        Ok(true)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (packet, writer);
+        Ok(false)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use nix::{
+        fcntl::{splice, OFlag, SpliceFFlags},
+        unistd::{close, pipe2},
+    };
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use tokio::{
+        io::{self, Interest},
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpStream,
+        },
+    };
+
+    struct Pipe {
+        read: RawFd,
+        write: RawFd,
+    }
+
+    impl Pipe {
+        fn new() -> io::Result<Pipe> {
+            let (read, write) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+            Ok(Pipe { read, write })
+        }
+    }
+
+    impl Drop for Pipe {
+        fn drop(&mut self) {
+            let _ = close(self.read);
+            let _ = close(self.write);
+        }
+    }
+
+    // Sockets can't be spliced into each other directly, so everything goes
+    // through a pipe, which is drained before reading more
+    pub async fn splice_exact(
+        src: &OwnedReadHalf,
+        tgt: &OwnedWriteHalf,
+        len: usize,
+    ) -> io::Result<()> {
+        let src: &TcpStream = src.as_ref();
+        let tgt: &TcpStream = tgt.as_ref();
+        let pipe = Pipe::new()?;
+        let flags = SpliceFFlags::SPLICE_F_MOVE | SpliceFFlags::SPLICE_F_NONBLOCK;
+        let mut remaining = len;
+        while remaining > 0 {
+            src.readable().await?;
+            let read = match src.try_io(Interest::READABLE, || {
+                splice(src.as_raw_fd(), None, pipe.write, None, remaining, flags)
+                    .map_err(io::Error::from)
+            }) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(err),
+            };
+            let mut in_pipe = read;
+            while in_pipe > 0 {
+                tgt.writable().await?;
+                match tgt.try_io(Interest::WRITABLE, || {
+                    splice(pipe.read, None, tgt.as_raw_fd(), None, in_pipe, flags)
+                        .map_err(io::Error::from)
+                }) {
+                    Ok(written) => in_pipe -= written,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            remaining -= read;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::try_zero_copy_forward;
+    use crate::{
+        connection::{
+            codec::{InboundConnection, OutboundConnection},
+            packets::play::KeepAlive,
+            socket_pair,
+        },
+        protocol::{
+            types::{Decode, Encode},
+            version::ProtocolVersion,
+        },
+    };
+    use tokio::{io::AsyncWriteExt, test};
+
+    #[test]
+    async fn forwards_unencrypted() {
+        let version = ProtocolVersion::V1_16;
+        let (src_write, src_read) = socket_pair().await;
+        let (tgt_write, tgt_read) = socket_pair().await;
+        let mut sender = OutboundConnection::new(src_write.into_split().1, version);
+        let mut forwarder_in = InboundConnection::new(src_read.into_split().0, version);
+        let mut forwarder_out = OutboundConnection::new(tgt_write.into_split().1, version);
+        let mut receiver = InboundConnection::new(tgt_read.into_split().0, version);
+
+        let keep_alive = KeepAlive(300);
+        let mut packet = sender
+            .create_packet(0x1F, Some(keep_alive.len(version)))
+            .await
+            .unwrap();
+        keep_alive.encode(&mut packet, version).await.unwrap();
+        packet.shutdown().await.unwrap();
+
+        let mut packet = forwarder_in.next_packet().await.unwrap();
+        let forwarded = try_zero_copy_forward(&mut packet, &mut forwarder_out)
+            .await
+            .unwrap();
+        packet.content.finished().unwrap();
+        if !forwarded {
+            // Nothing to check off Linux
+            return;
+        }
+
+        let mut packet = receiver.next_packet().await.unwrap();
+        assert_eq!(packet.id, 0x1F);
+        assert_eq!(
+            KeepAlive::decode(&mut packet.content, version)
+                .await
+                .unwrap(),
+            keep_alive
+        );
+        packet.content.finished().unwrap();
+    }
+}
@@ -2,39 +2,80 @@
 #![feature(generic_associated_types)]
 #![feature(poll_ready)]
 #![feature(iter_intersperse)]
+#![cfg_attr(test, feature(test))]
 #![allow(clippy::upper_case_acronyms)]
 
+mod config;
 mod connection;
+mod persistence;
 mod protocol;
+mod transform;
 mod web;
 
-use futures::future::join_all;
+pub use config::Config;
+pub use connection::dump::{dump_packets, export_pcap};
+pub use connection::error::Error as ConnectionError;
+pub use connection::packets::status::{Status, StatusTransform};
 
-use bimap::BiHashMap;
+use futures::{future::try_join_all, stream::FuturesUnordered, StreamExt};
+
+use config::{load_watchlist, DynamicConfig, Error as ConfigError, ProxyMessagePosition};
 use connection::{
-    packets::login::{Player, ServerLoginCredentials},
+    packets::{
+        login::{Player, ServerLoginCredentials},
+        play::{
+            clientbound_keep_alive_id, serverbound_keep_alive_id, KeepAlive, PlayerInfoAction,
+            PlayerInfoPacket, CHAT_POSITION_ACTION_BAR,
+        },
+    },
+    zero_copy::try_zero_copy_forward,
     Client, ServerConnection, State,
 };
-use protocol::error::Error as ProtocolError;
+use persistence::StatsDb;
 use reqwest::Client as HTTPClient;
-use std::{borrow::Cow, error::Error, io::Cursor, sync::Mutex, collections::HashMap};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
 use tokio::{
-    io::{copy, AsyncReadExt, AsyncWriteExt},
+    io::AsyncWriteExt,
     net::TcpListener,
+    select,
+    sync::{mpsc::unbounded_channel, Mutex as AsyncMutex, Semaphore},
+    task::JoinHandle,
     try_join,
 };
-use unicase::Ascii;
-use web::microsoft;
+use tokio_util::sync::CancellationToken;
+use transform::{
+    AchievementsCommand, BansCommand, Direction, ExportCommand, FriendsCommand, GuildStatsCommand,
+    HistoryCommand, NickCommand, NickSubstitution, PacketAction, PacketTransformChain,
+    PartyChatStats, PartyStatsCommand, PingCommand, PlayerList, PlayerListTracker,
+    ProxyStatsCommand, RecentCommand, ReloadCommand, ReportCommand, SessionContext,
+    SkyBlockCommand, StatsCommand, TopCommand, WatchCommand, WhereCommand, WhoisCommand,
+    SERVERBOUND_CHAT_ID,
+};
+use web::microsoft::{self, Error as MicrosoftError};
+use web::skin;
 use web::yggdrasil;
 
 use crate::{
-    protocol::types::{
-        Chat, ChatObject, ChatValue, Color, Decode, Encode, LengthCappedString, VarInt, UUID,
+    protocol::types::{Chat, ChatObject, Color, Decode, Encode, UUID},
+    web::{
+        error::Error as WebError,
+        hypixel::{Hypixel, HypixelCacheConfig, PlayerInfo},
+        mojang::{Mojang, RateLimitedMojang},
     },
-    web::{hypixel::Hypixel, mojang::Mojang},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum StartConfig {
     Yggdrasil { username: String, password: String },
     Microsoft { access_token: String },
@@ -47,20 +88,504 @@ enum AuthConfig<'a> {
 }
 
 const CLIENT_NAME: &str = "mc-proxy";
+const LOCAL_PORT: u16 = 25565;
+// Only suggested to the user when LOCAL_PORT is taken
+const ALTERNATIVE_LOCAL_PORT: u16 = 25566;
+// How often vanilla servers send keep alives
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+// How long the client shows an action bar message for
+const ACTION_BAR_DISPLAY_TIME: Duration = Duration::from_secs(3);
+// Longest chat message the server accepts from the client, in characters
+const MAX_CHAT_MESSAGE_LEN: usize = 256;
+
+pub const DEFAULT_UPSTREAM: &str = "mc.hypixel.net";
 
+// Maps the hostname the client connected to onto the server to proxy it to
+pub type Router = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+pub fn default_router(_hostname: &str) -> String {
+    DEFAULT_UPSTREAM.to_string()
+}
+
+// Rewrites the status of the server a hostname is routed to, so that each
+// hostname can have its own MOTD
+type HostStatusTransform = Arc<dyn Fn(&str, Status<'static>) -> Status<'static> + Send + Sync>;
+
+// What the proxy is doing, for frontends to show
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ProxyStatus {
+    // Logged in and waiting for the local client
+    Ready,
+    // The local client is playing on the server
+    Connected { server: String },
+    // The local client or the server closed the connection
+    Disconnected,
+    // The proxy stopped
+    Error { message: String },
+}
+
+// Called with every status change, from whichever task caused it
+pub type StatusListener = Arc<dyn Fn(ProxyStatus) + Send + Sync>;
+
+// Stops a proxy started with start_oneshot from accepting connections.
+// Connections that are already open are left to finish.
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownTrigger(CancellationToken);
+
+impl ShutdownTrigger {
+    pub fn shutdown(&self) {
+        self.0.cancel();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+// Invalidates the access token the proxy logged in with, or every token of
+// the account if it wasn't saved
+pub async fn logout(
+    config: StartConfig,
+    access_token: Option<String>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    match (config, access_token) {
+        (StartConfig::Yggdrasil { .. }, Some(access_token)) => {
+            yggdrasil::Authentication::new(
+                Some(CLIENT_NAME),
+                Some(Cow::Owned(access_token)),
+                None,
+                None,
+            )
+            .invalidate()
+            .await?;
+        }
+        (StartConfig::Yggdrasil { username, password }, None) => {
+            yggdrasil::Authentication::signout(&username, &password, None, None).await?;
+        }
+        // Microsoft tokens can't be revoked, they just expire
+        (StartConfig::Microsoft { .. }, _) => {}
+    };
+    Ok(())
+}
+
+pub async fn player_face(uuid: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync + 'static>> {
+    Ok(skin::face_png_bytes(uuid.parse()?, &HTTPClient::new()).await?)
+}
+
+fn reload_config(
+    path: Option<&Path>,
+    dynamic_config: &RwLock<DynamicConfig>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let path = path.ok_or("no configuration file")?;
+    *dynamic_config.write().unwrap() = DynamicConfig::from_file(path)?;
+    Ok(())
+}
+
+// The reply to /reload
+fn reload_message(res: Result<(), Box<dyn Error + Send + Sync + 'static>>) -> Chat<'static> {
+    Chat::Raw(match res {
+        Ok(()) => "§aReloaded configuration".into(),
+        Err(err) => format!("§cFailed to reload configuration: {}", err).into(),
+    })
+}
+
+// Checks the tab list before asking Mojang, so nicked players in the lobby
+// still resolve
+async fn lookup_player(
+    username: &str,
+    all_local_players: &Mutex<PlayerList>,
+    mojang: &RateLimitedMojang<'_>,
+) -> Option<(UUID, String)> {
+    let known_uuid = all_local_players.lock().unwrap().get_uuid_by_name(username);
+    match known_uuid {
+        Some(uuid) => Some((uuid, username.to_string())),
+        None => mojang.get_uuid(username).await.ok(),
+    }
+}
+
+// The reply to /stats for a player Hypixel knows
+fn stats_chat(info: &PlayerInfo, settings: &DynamicConfig) -> Chat<'static> {
+    let mut display = Vec::new();
+    if let Some(bw_stats) = &info.stats.bedwars {
+        let fkdr = bw_stats.fkdr();
+        // Left to the client's default colour when no tier matches
+        display.push(Chat::Object(ChatObject {
+            color: settings.fkdr_color(fkdr),
+            ..ChatObject::text(format!("{:.2} FKDR", fkdr))
+        }));
+    }
+    Chat::Array(vec![
+        info.full_badge(),
+        Chat::Object(
+            ChatObject::text(": ").color(Color::Reset).extra(
+                display
+                    .into_iter()
+                    .intersperse(Chat::Raw(", ".into()))
+                    .collect(),
+            ),
+        ),
+    ])
+}
+
+// Breaks at spaces where possible, and within words longer than max_len
+fn split_message(s: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_len = 0;
+    for word in s.split_whitespace() {
+        if chunk_len != 0 && chunk_len + 1 + word.chars().count() > max_len {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_len = 0;
+        }
+        if chunk_len != 0 {
+            chunk.push(' ');
+            chunk_len += 1;
+        }
+        for c in word.chars() {
+            if chunk_len == max_len {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_len = 0;
+            }
+            chunk.push(c);
+            chunk_len += 1;
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// Action bar messages replace each other, so they are shown one by one
+enum ActionBarState {
+    Idle,
+    Showing(Instant),
+}
+
+impl ActionBarState {
+    // The message to show now, if the previous one has been up long enough
+    fn next<T>(&mut self, queue: &mut VecDeque<T>, now: Instant) -> Option<T> {
+        if let ActionBarState::Showing(since) = *self {
+            if now.duration_since(since) < ACTION_BAR_DISPLAY_TIME {
+                return None;
+            }
+            *self = ActionBarState::Idle;
+        }
+        let next = queue.pop_front()?;
+        *self = ActionBarState::Showing(now);
+        Some(next)
+    }
+}
+
+fn throttled_message(delay: Duration) -> Chat<'static> {
+    Chat::Raw(
+        format!(
+            "§cThe Hypixel API is throttling requests, try again in {} seconds",
+            delay.as_secs()
+        )
+        .into(),
+    )
+}
+
+// Everything about running the proxy other than the account. By default it
+// listens on the local port, proxies to Hypixel and keeps nothing on disk.
+#[derive(Clone)]
+pub struct ProxyOptions {
+    listen_addr: SocketAddr,
+    config_path: Option<PathBuf>,
+    stats_db_path: Option<PathBuf>,
+    watchlist_path: Option<PathBuf>,
+    session_server: Option<String>,
+    max_connections: Option<usize>,
+    proxy_protocol: bool,
+    router: Router,
+    status_transform: Option<HostStatusTransform>,
+    on_status: StatusListener,
+    on_access_token: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl Default for ProxyOptions {
+    fn default() -> Self {
+        ProxyOptions {
+            listen_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, LOCAL_PORT)),
+            config_path: None,
+            stats_db_path: None,
+            watchlist_path: None,
+            session_server: None,
+            max_connections: None,
+            proxy_protocol: false,
+            router: Arc::new(default_router),
+            status_transform: None,
+            on_status: Arc::new(|_| {}),
+            on_access_token: Arc::new(|_| {}),
+        }
+    }
+}
+
+impl ProxyOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn listen_addr(mut self, addr: SocketAddr) -> Self {
+        self.listen_addr = addr;
+        self
+    }
+
+    // The settings file, read on startup and again by /reload
+    pub fn config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    // Enables /history
+    pub fn stats_db_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stats_db_path = Some(path.into());
+        self
+    }
+
+    // Keeps /watch across restarts
+    pub fn watchlist_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.watchlist_path = Some(path.into());
+        self
+    }
+
+    // For accounts from launchers with their own session server, e.g.
+    // https://authserver.ely.by
+    pub fn session_server(mut self, base_url: impl Into<String>) -> Self {
+        self.session_server = Some(base_url.into());
+        self
+    }
+
+    // Connections past the limit are closed straight away
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    // Connections start with a PROXY protocol header from a load balancer,
+    // and are closed without one
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    pub fn router(mut self, router: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.router = Arc::new(router);
+        self
+    }
+
+    // Changes the status the local client sees, given the hostname it
+    // connected with. Statuses that don't fit Status are passed on as they are.
+    pub fn status_transform(
+        mut self,
+        transform: impl Fn(&str, Status<'static>) -> Status<'static> + Send + Sync + 'static,
+    ) -> Self {
+        self.status_transform = Some(Arc::new(transform));
+        self
+    }
+
+    pub fn on_status(mut self, on_status: impl Fn(ProxyStatus) + Send + Sync + 'static) -> Self {
+        self.on_status = Arc::new(on_status);
+        self
+    }
+
+    // Called with the Yggdrasil access token after logging in, so that it can
+    // be saved and passed to logout later
+    pub fn on_access_token(
+        mut self,
+        on_access_token: impl Fn(&str) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_access_token = Arc::new(on_access_token);
+        self
+    }
+}
+
+#[deprecated(note = "use start_oneshot, which can be stopped")]
 pub async fn start(
     config: StartConfig,
     api_key: String,
+    config_path: Option<PathBuf>,
+    stats_db_path: Option<PathBuf>,
+    watchlist_path: Option<PathBuf>,
+    router: impl Fn(&str) -> String + Send + Sync + 'static,
+    on_status: impl Fn(ProxyStatus) + Send + Sync + 'static,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let options = ProxyOptions {
+        config_path,
+        stats_db_path,
+        watchlist_path,
+        ..Default::default()
+    }
+    .router(router)
+    .on_status(on_status);
+    serve(
+        config,
+        api_key,
+        bind(options.listen_addr).await?,
+        options,
+        CancellationToken::new(),
+    )
+    .await
+}
+
+// Runs the proxy in the background, for embedding it in other applications.
+// Errors binding the port are returned straight away, and the handle
+// resolves once the proxy stops.
+pub async fn start_oneshot(
+    config: StartConfig,
+    api_key: String,
+    options: ProxyOptions,
+) -> Result<
+    (
+        JoinHandle<Result<(), Box<dyn Error + Send + Sync + 'static>>>,
+        ShutdownTrigger,
+    ),
+    Box<dyn Error + Send + Sync + 'static>,
+> {
+    let listener = bind(options.listen_addr).await?;
+    let shutdown = ShutdownTrigger::default();
+    let handle = tokio::spawn(serve(
+        config,
+        api_key,
+        listener,
+        options,
+        shutdown.0.clone(),
+    ));
+    Ok((handle, shutdown))
+}
+
+impl Config {
+    // The account, API key and options to start the proxy with
+    pub fn into_parts(self) -> (StartConfig, String, ProxyOptions) {
+        let upstream = self
+            .upstream
+            .unwrap_or_else(|| DEFAULT_UPSTREAM.to_string());
+        let options = ProxyOptions {
+            listen_addr: self.listen_addr,
+            config_path: self.stat_config,
+            stats_db_path: self.stats_db_path,
+            watchlist_path: self.watchlist_path,
+            session_server: self.session_server,
+            max_connections: self.max_connections,
+            proxy_protocol: self.proxy_protocol,
+            ..Default::default()
+        }
+        .router(move |_| upstream.clone());
+        (self.credentials, self.api_key, options)
+    }
+}
+
+// Everything comes from the config, including the listen address and the
+// server to proxy to
+pub async fn start_with_config(
+    config: Config,
 ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    let listener = TcpListener::bind("localhost:25565").await?;
+    let (config, api_key, options) = config.into_parts();
+    serve(
+        config,
+        api_key,
+        bind(options.listen_addr).await?,
+        options,
+        CancellationToken::new(),
+    )
+    .await
+}
+
+// Runs one proxy per account, each authenticated separately and listening on
+// its own address instead of the one in the options. Stops at the first
+// error.
+pub async fn start_multi(
+    configs: Vec<(StartConfig, String, SocketAddr)>,
+    options: ProxyOptions,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    try_join_all(configs.into_iter().map(|(config, api_key, addr)| {
+        let options = options.clone().listen_addr(addr);
+        async move {
+            serve(
+                config,
+                api_key,
+                bind(options.listen_addr).await?,
+                options,
+                CancellationToken::new(),
+            )
+            .await
+        }
+    }))
+    .await?;
+    Ok(())
+}
+
+// Suggests another port when the one asked for is taken
+async fn bind(addr: SocketAddr) -> Result<TcpListener, Box<dyn Error + Send + Sync + 'static>> {
+    match TcpListener::bind(addr).await {
+        Err(err) if err.kind() == ErrorKind::AddrInUse => {
+            let free_port = if addr.port() == LOCAL_PORT {
+                TcpListener::bind((addr.ip(), ALTERNATIVE_LOCAL_PORT))
+                    .await
+                    .ok()
+                    .map(|_| ALTERNATIVE_LOCAL_PORT)
+            } else {
+                None
+            };
+            Err(ConnectionError::PortInUse {
+                port: addr.port(),
+                free_port,
+            }
+            .into())
+        }
+        res => Ok(res?),
+    }
+}
+
+// Stops accepting connections once shutdown is cancelled
+async fn serve(
+    config: StartConfig,
+    api_key: String,
+    listener: TcpListener,
+    options: ProxyOptions,
+    shutdown: CancellationToken,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let ProxyOptions {
+        config_path,
+        stats_db_path,
+        watchlist_path,
+        session_server,
+        max_connections,
+        proxy_protocol,
+        router,
+        status_transform,
+        on_status,
+        on_access_token,
+        ..
+    } = options;
+    let dynamic_config = Arc::new(RwLock::new(match config_path {
+        Some(ref path) => match DynamicConfig::from_file(path) {
+            Err(ConfigError::IO(err)) if err.kind() == ErrorKind::NotFound => Default::default(),
+            res => res?,
+        },
+        None => Default::default(),
+    }));
+    // /history is disabled without a database
+    let stats_db = match stats_db_path {
+        Some(ref path) => Some(StatsDb::open(path).await?),
+        None => None,
+    };
     let web_client = HTTPClient::new();
     let mut auth_config: AuthConfig<'_>;
     let config = dbg!(config);
     match config {
         StartConfig::Yggdrasil { username, password } => {
-            let mut auth =
-                yggdrasil::Authentication::new(Some(CLIENT_NAME), None, Some(web_client.clone()));
-            let info = auth.authenticate(&username, &password).await?.user_info;
+            let mut auth = yggdrasil::Authentication::new(
+                Some(CLIENT_NAME),
+                None,
+                Some(web_client.clone()),
+                None,
+            );
+            let res = auth.authenticate(&username, &password).await?;
+            on_access_token(res.access_token);
+            let info = res.user_info;
             auth_config = AuthConfig::Yggdrasil(auth, info);
         }
         StartConfig::Microsoft { access_token } => {
@@ -69,522 +594,648 @@ pub async fn start(
                 None,
                 Some(web_client.clone()),
             );
-            let info = auth.get_info().await?;
+            let info = match auth.get_info().await {
+                Err(WebError::Microsoft(MicrosoftError::MinecraftNotOwned)) => {
+                    return Err("this Microsoft account does not own Minecraft: \
+                        you need to buy the game before you can use the proxy"
+                        .into())
+                }
+                res => res?,
+            };
             auth_config = AuthConfig::Microsoft(auth, info);
         }
     };
     auth_config = dbg!(auth_config);
+    on_status(ProxyStatus::Ready);
+    let hypixel = Hypixel::new(
+        api_key,
+        Some(web_client.clone()),
+        HypixelCacheConfig::default(),
+    );
+    if let Some(ref path) = watchlist_path {
+        for uuid in load_watchlist(path)? {
+            hypixel.add_to_watchlist(uuid);
+        }
+    }
+    let mut mojang = Mojang::new(None, Some(web_client.clone()), None);
+    if let Some(ref base_url) = session_server {
+        mojang.set_session_server_url(base_url);
+    }
+    // Shared so the rate limit applies across all connections
+    let mojang = Arc::new(RateLimitedMojang::new(mojang));
+    // Set by /nick, and only shown to the local client from its next login
+    let nick = Arc::new(Mutex::new(None::<String>));
+    let connection_permits = max_connections.map(|max| Arc::new(Semaphore::new(max)));
     loop {
-        let conn = listener.accept().await?.0;
-        let api_key = api_key.clone();
+        let conn = select! {
+            res = listener.accept() => res?.0,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        // Held until the connection closes
+        let permit = match connection_permits {
+            Some(ref permits) => match permits.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    println!("Refused a connection: too many clients are connected");
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let hypixel = hypixel.clone();
+        let mojang = mojang.clone();
+        let nick = nick.clone();
+        let stats_db = stats_db.clone();
+        let dynamic_config = dynamic_config.clone();
+        let config_path = config_path.clone();
+        let watchlist_path = watchlist_path.clone();
         let web_client = web_client.clone();
         let auth_config = auth_config.clone();
+        let router = router.clone();
+        let status_transform = status_transform.clone();
+        let on_status = on_status.clone();
+        // Tags the log messages of this connection
+        let conn_id = UUID::new_v4();
         tokio::spawn(async move {
+            let _permit = permit;
+            // Status pings come and go all the time, so only connections
+            // that got as far as logging in are reported
+            let mut logged_in = false;
             if let Err(err) = async {
-                let mut conn = ServerConnection::new(conn).await;
+                let mut conn = if proxy_protocol {
+                    ServerConnection::new_behind_proxy(conn).await?
+                } else {
+                    ServerConnection::new(conn).await
+                };
+                let write_timeout = dynamic_config.read().unwrap().client_write_timeout;
+                conn.outbound
+                    .set_write_timeout(write_timeout.map(Duration::from_secs));
                 conn.accept_handshake().await?;
-                let mut client = Client::connect("mc.hypixel.net", conn.version).await?;
-                client.handshake(conn.state).await?;
-                if conn.state == State::Status {
-                    let packet = conn.inbound.next_packet().await?;
-                    if packet.id != 0 || packet.len != 0 {
-                        return Err(ProtocolError::Malformed.into());
-                    }
-                    client.outbound.create_packet(0, Some(0)).await?;
-                    loop {
-                        let mut packet = client.inbound.next_packet().await?;
-                        let mut out_packet = conn
-                            .outbound
-                            .create_packet(packet.id, Some(packet.len))
-                            .await?;
-                        copy(&mut packet.content, &mut out_packet).await?;
-                        packet.content.finished()?;
-                        out_packet.shutdown().await?;
-                    }
+                let target = router(conn.hostname());
+                let mut client = Client::connect(&target, conn.version()).await?;
+                client.handshake(conn.state()).await?;
+                if conn.state() == State::Status {
+                    let hostname = conn.hostname().to_string();
+                    let transform = status_transform.map(|transform| {
+                        Box::new(move |status| transform(&hostname, status)) as Box<StatusTransform>
+                    });
+                    conn.status_proxy(&mut client, transform.as_deref()).await?;
                 } else {
                     let (name, id) = match auth_config {
-                        AuthConfig::Yggdrasil(_, ref info) => (info.name.as_ref(), info.id),
-                        AuthConfig::Microsoft(_, ref info) => (info.name.as_ref(), info.id),
+                        AuthConfig::Yggdrasil(_, ref info) => (info.name.to_string(), info.id),
+                        AuthConfig::Microsoft(_, ref info) => (info.name.to_string(), info.id),
                     };
+                    // Not borrowed from auth_config, which logging in uses up
+                    let name = name.as_str();
+                    // The server still gets the real name, since logging in
+                    // needs the account's own credentials
+                    let display_name = nick
+                        .lock()
+                        .unwrap()
+                        .clone()
+                        .unwrap_or_else(|| name.to_string());
                     conn.accept_login(|_| async {
                         Ok(ServerLoginCredentials::OfflineMode(Player {
-                            username: Cow::Borrowed(&name),
+                            username: Cow::Borrowed(&display_name),
                             uuid: id,
                         }))
                     })
                     .await?;
-                    match auth_config {
-                        AuthConfig::Yggdrasil(auth, _) => {
-                            client
-                                .login(
-                                    Some(web_client.clone()),
-                                    yggdrasil::OnlineMode::new(
-                                        yggdrasil::UserInfo {
-                                            name: Cow::Borrowed(name),
-                                            id,
-                                        },
-                                        auth,
-                                    ),
-                                    Client::NO_LOGIN_PLUGIN_HANDLER,
-                                )
-                                .await?;
+                    // The local client is already playing by now, so it is
+                    // told why instead of just losing the connection
+                    let res = async {
+                        match auth_config {
+                            AuthConfig::Yggdrasil(auth, _) => {
+                                client
+                                    .login(
+                                        Some(web_client.clone()),
+                                        mojang.session_server(),
+                                        yggdrasil::OnlineMode::new(
+                                            yggdrasil::UserInfo {
+                                                name: Cow::Borrowed(name),
+                                                id,
+                                            },
+                                            auth,
+                                        ),
+                                        Client::NO_LOGIN_PLUGIN_HANDLER,
+                                        Client::NO_COOKIE_HANDLER,
+                                    )
+                                    .await?;
+                            }
+                            AuthConfig::Microsoft(auth, _) => {
+                                client
+                                    .login(
+                                        Some(web_client.clone()),
+                                        mojang.session_server(),
+                                        microsoft::OnlineMode::new(
+                                            microsoft::UserInfo {
+                                                name: Cow::Borrowed(name),
+                                                id,
+                                            },
+                                            auth,
+                                        ),
+                                        Client::NO_LOGIN_PLUGIN_HANDLER,
+                                        Client::NO_COOKIE_HANDLER,
+                                    )
+                                    .await?;
+                            }
+                        };
+                        logged_in = true;
+                        on_status(ProxyStatus::Connected { server: target });
+
+                        let version = client.version();
+                        // Shared with the tasks answering party joins
+                        let send_to_client = Arc::new(Mutex::new(VecDeque::new()));
+                        let all_local_players = Mutex::new(PlayerList::new());
+                        let pings = Mutex::new(HashMap::<UUID, i32>::new());
+                        let gamemodes = Mutex::new(HashMap::<UUID, u8>::new());
+                        let pending_stats = Mutex::new(HashSet::new());
+                        let session_stats = Mutex::new(HashMap::new());
+                        let party_list_requested = Mutex::new(None);
+                        let ctx = SessionContext {
+                            conn_id,
+                            version,
+                            username: name,
+                            uuid: id,
+                            display_name: &display_name,
+                            nick: &nick,
+                            hypixel: &hypixel,
+                            mojang: &mojang,
+                            stats_db: stats_db.as_ref(),
+                            dynamic_config: &dynamic_config,
+                            config_path: config_path.as_deref(),
+                            watchlist_path: watchlist_path.as_deref(),
+                            all_local_players: &all_local_players,
+                            pings: &pings,
+                            gamemodes: &gamemodes,
+                            pending_stats: &pending_stats,
+                            session_stats: &session_stats,
+                            party_list_requested: &party_list_requested,
+                            send_to_client: &send_to_client,
+                        };
+                        proxy_play(&mut conn, &mut client, &ctx).await
+                    }
+                    .await;
+                    if let Err(ref err) = res {
+                        let reason = Chat::Raw(format!("§cLost connection: {}", err).into());
+                        let _ = conn.outbound.write_disconnect(reason).await;
+                    }
+                    res?;
+                }
+                Ok::<(), Box<dyn Error + Send + Sync + 'static>>(())
+            }
+            .await
+            {
+                println!("[{}] Connection closed: {}", conn_id, err);
+            }
+            if logged_in {
+                on_status(ProxyStatus::Disconnected);
+            }
+        });
+    }
+}
+
+// Relays packets both ways until either side closes the connection, running
+// them through the transform chains on the way
+async fn proxy_play(
+    conn: &mut ServerConnection,
+    client: &mut Client,
+    ctx: &SessionContext<'_>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let version = ctx.version;
+    let server_version = conn.version();
+    let serverbound = PacketTransformChain::new()
+        .then(NickSubstitution)
+        .then(StatsCommand)
+        .then(PingCommand)
+        .then(GuildStatsCommand)
+        .then(FriendsCommand)
+        .then(BansCommand)
+        .then(ProxyStatsCommand)
+        .then(AchievementsCommand)
+        .then(PartyStatsCommand)
+        .then(ExportCommand)
+        .then(ReportCommand)
+        .then(NickCommand)
+        .then(WatchCommand)
+        .then(RecentCommand)
+        .then(HistoryCommand)
+        .then(SkyBlockCommand)
+        .then(WhoisCommand)
+        .then(WhereCommand)
+        .then(TopCommand)
+        .then(ReloadCommand);
+    let clientbound = PacketTransformChain::new()
+        .then(PlayerListTracker)
+        .then(PartyChatStats::new(version));
+    let Client {
+        inbound, outbound, ..
+    } = client;
+    let upload_bandwidth_kbps = ctx.settings().upload_bandwidth_kbps;
+    if upload_bandwidth_kbps.is_some() {
+        outbound.enable_adaptive_compression();
+    }
+    // Also written to by the keep alive handler, which must not wait on the
+    // local client
+    let outbound = AsyncMutex::new(outbound);
+    let pending_keep_alive = Mutex::new(None::<i64>);
+    // Chat goes through the transforms away from the relay loop, so that
+    // commands waiting on the web don't hold up movement and the like
+    let (chat_tx, mut chat_rx) = unbounded_channel();
+    let ServerConnection {
+        inbound: server_inbound,
+        outbound: server_outbound,
+        ..
+    } = conn;
+    try_join!(
+        async {
+            loop {
+                let mut packet = server_inbound.next_packet().await?;
+                match packet.id {
+                    SERVERBOUND_CHAT_ID => {
+                        // Only fails once the chat loop has stopped, which
+                        // ends the session anyway
+                        let _ = chat_tx.send(packet.into_owned().await?);
+                    }
+                    id if id == serverbound_keep_alive_id(server_version) => {
+                        // Already answered when it was sent, so don't
+                        // forward the local client's response
+                        let keep_alive =
+                            KeepAlive::decode(&mut packet.content, server_version).await?;
+                        packet.content.finished()?;
+                        let mut pending = pending_keep_alive.lock().unwrap();
+                        if *pending == Some(keep_alive.0) {
+                            *pending = None;
+                        }
+                    }
+                    _ => {
+                        let mut outbound = outbound.lock().await;
+                        if !try_zero_copy_forward(&mut packet, &mut outbound).await? {
+                            outbound.forward_packet(&mut packet).await?;
+                        }
+                        packet.content.finished()?;
+                    }
+                }
+            }
+            #[allow(unreachable_code)]
+            Ok::<(), Box<dyn Error + Send + Sync + 'static>>(())
+        },
+        async {
+            // Every command runs at once, and each result is sent on as soon
+            // as it is ready
+            let mut running = FuturesUnordered::new();
+            loop {
+                let action = select! {
+                    packet = chat_rx.recv() => match packet {
+                        Some(packet) => {
+                            running.push(serverbound.apply(packet, Direction::Serverbound, ctx));
+                            continue;
+                        }
+                        None => return Ok(()),
+                    },
+                    Some(action) = running.next(), if !running.is_empty() => action?,
+                };
+                let packet = match action {
+                    PacketAction::Pass(packet) | PacketAction::Replace(packet) => packet,
+                    PacketAction::Discard => continue,
+                    PacketAction::Inject(packets) => {
+                        let mut outbound = outbound.lock().await;
+                        for packet in &packets {
+                            outbound.emit_owned(packet).await?;
+                        }
+                        continue;
+                    }
+                };
+                outbound.lock().await.emit_owned(&packet).await?;
+            }
+        },
+        async {
+            let mut last_keep_alive_at = Instant::now();
+            let mut action_bar = ActionBarState::Idle;
+            loop {
+                // Only checked between packets, but the server sends
+                // plenty of them
+                let position = ctx.dynamic_config.read().unwrap().message_position;
+                if position == ProxyMessagePosition::ActionBar {
+                    let next =
+                        action_bar.next(&mut ctx.send_to_client.lock().unwrap(), Instant::now());
+                    if let Some(out_chat) = next {
+                        server_outbound
+                            .write_chat(out_chat, CHAT_POSITION_ACTION_BAR)
+                            .await?;
+                    }
+                } else {
+                    while let Some(out_chat) = {
+                        let chat = ctx.send_to_client.lock().unwrap().pop_front();
+                        // TODO: figure out why this is needed???
+                        chat
+                    } {
+                        server_outbound.write_chat(out_chat, position as u8).await?;
+                    }
+                }
+                let mut packet = inbound.next_packet().await?;
+                match packet.id {
+                    id if clientbound.wants(id, Direction::Clientbound) => {
+                        let packet = packet.into_owned().await?;
+                        let action = clientbound
+                            .apply(packet, Direction::Clientbound, ctx)
+                            .await?;
+                        for packet in action.into_packets() {
+                            server_outbound.emit_owned(&packet).await?;
+                        }
+                    }
+                    id if id == clientbound_keep_alive_id(version) => {
+                        let keep_alive = KeepAlive::decode(&mut packet.content, version).await?;
+                        packet.content.finished()?;
+                        let since_last = last_keep_alive_at.elapsed();
+                        last_keep_alive_at = Instant::now();
+                        if since_last > KEEP_ALIVE_INTERVAL * 2 {
+                            println!(
+                                "[{}] Warning: no keep alive from the server for {} seconds",
+                                ctx.conn_id,
+                                since_last.as_secs()
+                            );
+                        }
+                        if let Some(prev_id) =
+                            pending_keep_alive.lock().unwrap().replace(keep_alive.0)
+                        {
+                            println!(
+                                "[{}] Warning: local client never answered keep alive {}",
+                                ctx.conn_id, prev_id
+                            );
                         }
-                        AuthConfig::Microsoft(auth, _) => {
-                            client
-                                .login(
-                                    Some(web_client.clone()),
-                                    microsoft::OnlineMode::new(
-                                        microsoft::UserInfo {
-                                            name: Cow::Borrowed(name),
-                                            id,
-                                        },
-                                        auth,
-                                    ),
-                                    Client::NO_LOGIN_PLUGIN_HANDLER,
+                        {
+                            let mut outbound = outbound.lock().await;
+                            let mut out_packet = outbound
+                                .create_packet(
+                                    serverbound_keep_alive_id(version),
+                                    Some(keep_alive.len(version)),
                                 )
                                 .await?;
-                        }
-                    };
-
-                    let Client {
-                        inbound,
-                        outbound,
-                        version,
-                        ..
-                    } = &mut client;
-                    let version = *version;
-                    let send_to_client = Mutex::new(Vec::new());
-                    let all_local_players =
-                        Mutex::new(BiHashMap::<UUID, Ascii<Cow<'_, str>>>::new());
-                    let pings = Mutex::new(HashMap::<UUID, i32>::new());
-                    let ServerConnection {
-                        inbound: server_inbound,
-                        outbound: server_outbound,
-                        version: server_version,
-                        ..
-                    } = &mut conn;
-                    let server_version = *server_version;
-                    let client = web_client.clone();
-                    let mojang = Mojang::new(None, Some(client.clone()));
-                    let hypixel = Hypixel::new(&api_key, Some(client));
-                    try_join!(
-                        async {
-                            loop {
-                                let mut packet = server_inbound.next_packet().await?;
-                                match packet.id {
-                                    1 => {
-                                        let orig_msg = LengthCappedString::<256>::decode(
-                                            &mut packet.content,
-                                            server_version,
-                                        )
-                                        .await?;
-                                        let msg = &orig_msg.0;
-                                        packet.content.finished()?;
-                                        if let Some(unames) = msg.strip_prefix("/stats ") {
-                                            let players = if unames == "*" {
-                                                all_local_players
-                                                    .lock()
-                                                    .unwrap()
-                                                    .iter()
-                                                    .map(|(&uuid, v)| {
-                                                        (Some(uuid), Cow::Owned(v.as_ref().into()))
-                                                    })
-                                                    .collect::<Vec<_>>()
-                                            } else {
-                                                unames
-                                                    .split(' ')
-                                                    .map(|uname| {
-                                                        (
-                                                            all_local_players
-                                                                .lock()
-                                                                .unwrap()
-                                                                .get_by_right(&Ascii::new(
-                                                                    uname.into(),
-                                                                ))
-                                                                .copied(),
-                                                            Cow::Borrowed(uname),
-                                                        )
-                                                    })
-                                                    .collect()
-                                            };
-                                            let good_players =
-                                                join_all(players.into_iter().map(|player| {
-                                                    let mojang = &mojang;
-                                                    let hypixel = &hypixel;
-                                                    let send_to_client = &send_to_client;
-                                                    let mut uuid = player.0;
-                                                    let mut player = player.1.into_owned();
-                                                    async move {
-                                                        let mut out = Vec::<Cow<'_, str>>::new();
-                                                        let mut display = Vec::<Chat<'_>>::new();
-                                                        let uuid_lookup = uuid.is_none();
-                                                        if uuid_lookup {
-                                                            uuid = mojang
-                                                                .get_uuid(&player)
-                                                                .await
-                                                                .ok()
-                                                                .map(|(uuid, name)| {
-                                                                    player = name;
-                                                                    uuid
-                                                                });
-                                                        }
-                                                        let mut player_info = None;
-                                                        let mut nicked = true;
-                                                        if let Some(uuid) = uuid {
-                                                            if let Some(info) =
-                                                                hypixel.info(uuid).await?
-                                                            {
-                                                                nicked = false;
-                                                                let stats = &info.stats;
-                                                                if let Some(bw_stats) =
-                                                                    &stats.bedwars
-                                                                {
-                                                                    let fkdr = bw_stats
-                                                                        .final_kills
-                                                                        .map_or(0.0, |v| v as f64)
-                                                                        / bw_stats
-                                                                            .final_deaths
-                                                                            .map_or(1.0, |v| {
-                                                                                v as f64
-                                                                            });
-                                                                    if fkdr > 2.0 {
-                                                                        out.push(
-                                                                            format!(
-                                                                                "has {:.2} FKDR",
-                                                                                fkdr,
-                                                                            )
-                                                                            .into(),
-                                                                        );
-                                                                    }
-                                                                    display.push(Chat::Raw(
-                                                                        format!("{:.2} FKDR", fkdr)
-                                                                            .into(),
-                                                                    ));
-                                                                }
-                                                                player_info = Some(info);
-                                                            } else if uuid_lookup
-                                                                || mojang
-                                                                    .get_uuid(&player)
-                                                                    .await
-                                                                    .is_ok()
-                                                            {
-                                                                nicked = false;
-                                                            }
-                                                        }
-                                                        if nicked {
-                                                            out.push("is nicked".into());
-                                                        }
-                                                        let out = if out.is_empty() {
-                                                            None
-                                                        } else {
-                                                            Some(format!(
-                                                                "{} {}",
-                                                                &player,
-                                                                out.join(", ")
-                                                            ))
-                                                        };
-                                                        send_to_client.lock().unwrap().push(
-                                                            if let Some(ref player_info) =
-                                                                player_info
-                                                            {
-                                                                Chat::Array(vec![
-                                                                    player_info.into(),
-                                                                    Chat::Object(ChatObject {
-                                                                        color: Some(Color::Reset),
-                                                                        value: ChatValue::Text {
-                                                                            text: ": ".into(),
-                                                                        },
-                                                                        extra: Some(
-                                                                            display
-                                                                                .into_iter()
-                                                                                .intersperse(
-                                                                                    Chat::Raw(
-                                                                                        ", ".into(),
-                                                                                    ),
-                                                                                )
-                                                                                .collect(),
-                                                                        ),
-                                                                        ..Default::default()
-                                                                    }),
-                                                                ])
-                                                            } else {
-                                                                Chat::Object(ChatObject {
-                                                                    color: Some(if nicked {
-                                                                        Color::DarkRed
-                                                                    } else {
-                                                                        Color::Gray
-                                                                    }),
-                                                                    value: ChatValue::Text {
-                                                                        text: (if nicked {
-                                                                            "[NICKED] "
-                                                                        } else {
-                                                                            ""
-                                                                        })
-                                                                        .into(),
-                                                                    },
-                                                                    extra: Some(vec![
-                                                                        Chat::Raw(player.into()),
-                                                                        Chat::Raw(
-                                                                            "§r: Unknown".into(),
-                                                                        ),
-                                                                    ]),
-                                                                    ..Default::default()
-                                                                })
-                                                            },
-                                                        );
-                                                        Ok::<
-                                                            Option<String>,
-                                                            Box<dyn Error + Send + Sync + 'static>,
-                                                        >(
-                                                            out
-                                                        )
-                                                    }
-                                                }))
-                                                .await;
-                                            if unames == "*" {
-                                                for good_player in good_players {
-                                                    if let Some(msg) = good_player? {
-                                                        let mut out_packet =
-                                                            outbound.create_packet(1, None).await?;
-                                                        LengthCappedString::<256>(
-                                                            format!("/pc {}", msg).into(),
-                                                        )
-                                                        .encode(&mut out_packet, version)
-                                                        .await?;
-                                                        out_packet.shutdown().await?;
-                                                    }
-                                                }
-                                            }
-                                        } else if msg == "/ping" || msg.starts_with("/ping ") {
-                                            let unames = &msg[5..];
-                                            let players = if unames == "" {
-                                                vec![(Some(id), Cow::Borrowed(name))]
-                                            } else if unames == " *" {
-                                                all_local_players
-                                                    .lock()
-                                                    .unwrap()
-                                                    .iter()
-                                                    .map(|(&uuid, v)| {
-                                                        (Some(uuid), Cow::Owned(v.as_ref().into()))
-                                                    })
-                                                    .collect::<Vec<_>>()
-                                            } else {
-                                                unames
-                                                    .split(' ')
-                                                    .skip(1)
-                                                    .map(|uname| {
-                                                        (all_local_players
-                                                            .lock()
-                                                            .unwrap()
-                                                            .get_by_right(&Ascii::new(
-                                                                uname.into(),
-                                                            ))
-                                                            .copied(),
-                                                        Cow::Borrowed(uname))
-                                                    })
-                                                    .collect()
-                                            };
-                                            let results = join_all(players.into_iter().map(|player| {
-                                                let hypixel = &hypixel;
-                                                let send_to_client = &send_to_client;
-                                                let pings = &pings;
-                                                let mojang = &mojang;
-                                                let mut uuid = player.0;
-                                                let mut player = player.1.into_owned();
-                                                async move {
-                                                    if uuid.is_none() {
-                                                        uuid = mojang
-                                                            .get_uuid(&player)
-                                                            .await
-                                                            .ok()
-                                                            .map(|(uuid, name)| {
-                                                                player = name;
-                                                                uuid
-                                                            });
-                                                    }
-                                                    let mut ping = None;
-                                                    let mut player_info = None;
-                                                    if let Some(uuid) = uuid {
-                                                        ping = pings.lock().unwrap().get(&uuid).copied();
-                                                        player_info = hypixel.info(uuid).await?;
-                                                    }
-                                                    send_to_client.lock().unwrap().push(
-                                                        Chat::Array(vec![
-                                                            if let Some(ref player_info) = player_info {
-                                                                player_info.into()
-                                                            } else {
-                                                                Chat::Raw(format!("§4[NICKED] {}", player).into())
-                                                            },
-                                                            Chat::Object(ChatObject {
-                                                                color: Some(Color::Reset),
-                                                                value: ChatValue::Text {
-                                                                    text: ": ".into(),
-                                                                },
-                                                                ..Default::default()
-                                                            }),
-                                                            if let Some(ping) = ping {
-                                                                Chat::Object(ChatObject {
-                                                                    color: Some(if ping < 50 {
-                                                                        Color::DarkGreen
-                                                                    } else if ping < 100 {
-                                                                        Color::Green
-                                                                    } else if ping < 200 {
-                                                                        Color::Yellow
-                                                                    } else {
-                                                                        Color::Red
-                                                                    }),
-                                                                    value: ChatValue::Text {
-                                                                        text: format!("{}ms", ping).into(),
-                                                                    },
-                                                                    ..Default::default()
-                                                                })
-                                                            } else {
-                                                                Chat::Raw("Unknown".into())
-                                                            }
-                                                        ])
-                                                    );
-                                                    Ok::<
-                                                        (),
-                                                        Box<dyn Error + Send + Sync + 'static>,
-                                                    >(())
-                                                }
-                                            })).await;
-                                            for result in results {
-                                                result?;
-                                            }
-                                        } else {
-                                            let mut out_packet = outbound
-                                                .create_packet(packet.id, Some(packet.len))
-                                                .await?;
-                                            orig_msg.encode(&mut out_packet, version).await?;
-                                            out_packet.shutdown().await?;
-                                        }
-                                    }
-                                    _ => {
-                                        let mut out_packet = outbound
-                                            .create_packet(packet.id, Some(packet.len))
-                                            .await?;
-                                        copy(&mut packet.content, &mut out_packet).await?;
-                                        packet.content.finished()?;
-                                        out_packet.shutdown().await?;
-                                    }
-                                }
-                            }
-                            #[allow(unreachable_code)]
-                            Ok::<(), Box<dyn Error + Send + Sync + 'static>>(())
-                        },
-                        async {
-                            loop {
-                                while let Some(out_chat) = {
-                                    let chat = send_to_client.lock().unwrap().pop();
-                                    // TODO: figure out why this is needed???
-                                    chat
-                                } {
-                                    let mut out_packet =
-                                        server_outbound.create_packet(2, None).await?;
-                                    out_chat.encode(&mut out_packet, server_version).await?;
-                                    1u8.encode(&mut out_packet, server_version).await?;
-                                    out_packet.shutdown().await?;
-                                }
-                                let mut packet = inbound.next_packet().await?;
-                                match packet.id {
-                                    0x38 => {
-                                        let mut vec = Vec::with_capacity(packet.len);
-                                        packet.content.read_to_end(&mut vec).await?;
-                                        packet.content.finished()?;
-                                        let mut content = Cursor::new(&vec);
-                                        let action = VarInt::decode(&mut content, version).await?.0;
-                                        let num_players =
-                                            VarInt::decode(&mut content, version).await?.0;
-                                        for _ in 0..num_players {
-                                            let uuid = UUID::decode(&mut content, version).await?;
-                                            match action {
-                                                0 => {
-                                                    let name = LengthCappedString::<16>::decode(
-                                                        &mut content,
-                                                        version,
-                                                    )
-                                                    .await?
-                                                    .0;
-                                                    for _ in
-                                                        0..VarInt::decode(&mut content, version)
-                                                            .await?
-                                                            .0
-                                                    {
-                                                        LengthCappedString::<32767>::decode(
-                                                            &mut content,
-                                                            version,
-                                                        )
-                                                        .await?;
-                                                        LengthCappedString::<32767>::decode(
-                                                            &mut content,
-                                                            version,
-                                                        )
-                                                        .await?;
-                                                        if bool::decode(&mut content, version)
-                                                            .await?
-                                                        {
-                                                            LengthCappedString::<32767>::decode(
-                                                                &mut content,
-                                                                version,
-                                                            )
-                                                            .await?;
-                                                        }
-                                                    }
-                                                    VarInt::decode(&mut content, version).await?;
-                                                    let ping = VarInt::decode(&mut content, version).await?.0;
-                                                    if bool::decode(&mut content, version).await? {
-                                                        Chat::decode(&mut content, version).await?;
-                                                    }
-                                                    pings.lock().unwrap().insert(uuid, ping);
-                                                    all_local_players
-                                                        .lock()
-                                                        .unwrap()
-                                                        .insert(uuid, Ascii::new(name));
-                                                }
-                                                2 => {
-                                                    let ping = VarInt::decode(&mut content, version).await?.0;
-                                                    pings.lock().unwrap().insert(uuid, ping);
-                                                }
-                                                4 => {
-                                                    all_local_players
-                                                        .lock()
-                                                        .unwrap()
-                                                        .remove_by_left(&uuid);
-                                                    pings.lock().unwrap().remove(&uuid);
-                                                }
-                                                _ => {}
-                                            }
-                                        }
-                                        content.set_position(0);
-                                        let mut out_packet = server_outbound
-                                            .create_packet(packet.id, Some(packet.len))
-                                            .await?;
-                                        copy(&mut content, &mut out_packet).await?;
-                                        packet.content.finished()?;
-                                        out_packet.shutdown().await?;
-                                    }
-                                    _ => {
-                                        let mut out_packet = server_outbound
-                                            .create_packet(packet.id, Some(packet.len))
-                                            .await?;
-                                        copy(&mut packet.content, &mut out_packet).await?;
-                                        packet.content.finished()?;
-                                        out_packet.shutdown().await?;
-                                    }
-                                }
+                            keep_alive.encode(&mut out_packet, version).await?;
+                            out_packet.shutdown().await?;
+                            // The server measures our ping from the keep
+                            // alives answered here, so it is the round trip
+                            // to the server
+                            let latency = ctx.pings.lock().unwrap().get(&ctx.uuid).copied();
+                            if let (Some(bandwidth_kbps), Some(latency)) =
+                                (upload_bandwidth_kbps, latency)
+                            {
+                                outbound.adaptive_threshold(
+                                    latency.max(0) as u32,
+                                    bandwidth_kbps.saturating_mul(1000),
+                                );
                             }
-                            #[allow(unreachable_code)]
-                            Ok::<(), Box<dyn Error + Send + Sync + 'static>>(())
                         }
-                    )?;
+                        let mut out_packet = server_outbound
+                            .create_packet(packet.id, Some(keep_alive.len(server_version)))
+                            .await?;
+                        keep_alive.encode(&mut out_packet, server_version).await?;
+                        out_packet.shutdown().await?;
+                    }
+                    _ => {
+                        if !try_zero_copy_forward(&mut packet, server_outbound).await? {
+                            server_outbound.forward_packet(&mut packet).await?;
+                        }
+                        packet.content.finished()?;
+                    }
                 }
-                Ok::<(), Box<dyn Error + Send + Sync + 'static>>(())
             }
+            #[allow(unreachable_code)]
+            Ok::<(), Box<dyn Error + Send + Sync + 'static>>(())
+        },
+    )?;
+    Ok(())
+}
+
+// Returns the players that were added to the tab list
+fn track_player_info(
+    info: PlayerInfoPacket,
+    all_local_players: &Mutex<PlayerList>,
+    pings: &Mutex<HashMap<UUID, i32>>,
+    gamemodes: &Mutex<HashMap<UUID, u8>>,
+) -> Vec<(UUID, String)> {
+    let mut added = Vec::new();
+    for (uuid, action) in info.players {
+        match action {
+            PlayerInfoAction::Add {
+                name,
+                gamemode,
+                ping,
+            } => {
+                pings.lock().unwrap().insert(uuid, ping);
+                gamemodes.lock().unwrap().insert(uuid, gamemode);
+                added.push((uuid, name.clone()));
+                match all_local_players.lock().unwrap().add(uuid, name.clone()) {
+                    (None, None) => {}
+                    (Some(old_name), None) => {
+                        println!(
+                            "Warning: {} ({}) was added to the player list twice",
+                            old_name, uuid
+                        );
+                    }
+                    (None, Some(old_uuid)) => {
+                        println!(
+                            "Warning: {} was added as {} but was still listed as {}",
+                            name, uuid, old_uuid
+                        );
+                    }
+                    (Some(old_name), Some(old_uuid)) => {
+                        println!(
+                            "Warning: {} ({}) was added to the player list twice, \
+                            and {} was still listed as {}",
+                            old_name, uuid, name, old_uuid
+                        );
+                    }
+                }
+            }
+            PlayerInfoAction::UpdateGamemode(gamemode) => {
+                gamemodes.lock().unwrap().insert(uuid, gamemode);
+            }
+            PlayerInfoAction::UpdateLatency(ping) => {
+                pings.lock().unwrap().insert(uuid, ping);
+            }
+            PlayerInfoAction::UpdateDisplayName => {}
+            PlayerInfoAction::Remove => {
+                if all_local_players
+                    .lock()
+                    .unwrap()
+                    .remove_by_uuid(uuid)
+                    .is_none()
+                {
+                    println!(
+                        "Warning: {} was removed from the player list but never added",
+                        uuid
+                    );
+                }
+                pings.lock().unwrap().remove(&uuid);
+                gamemodes.lock().unwrap().remove(&uuid);
+            }
+        }
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connection::codec::OwnedPacket;
+    use protocol::{
+        types::{LengthCappedString, VarInt},
+        version::ProtocolVersion,
+    };
+    use tokio::test;
+
+    const VERSION: ProtocolVersion = ProtocolVersion::V1_8_9;
+
+    async fn player_info(action: i32, players: &[(UUID, &str)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        VarInt(action).encode(&mut buf, VERSION).await.unwrap();
+        VarInt(players.len() as i32)
+            .encode(&mut buf, VERSION)
             .await
-            {
-                println!("Connection closed: {}", err);
+            .unwrap();
+        for &(uuid, name) in players {
+            uuid.encode(&mut buf, VERSION).await.unwrap();
+            if action == 1 {
+                // spectator
+                VarInt(3).encode(&mut buf, VERSION).await.unwrap();
+            } else if action == 0 {
+                LengthCappedString::<16>(name.into())
+                    .encode(&mut buf, VERSION)
+                    .await
+                    .unwrap();
+                // properties, gamemode, ping, has display name
+                VarInt(0).encode(&mut buf, VERSION).await.unwrap();
+                VarInt(0).encode(&mut buf, VERSION).await.unwrap();
+                VarInt(42).encode(&mut buf, VERSION).await.unwrap();
+                false.encode(&mut buf, VERSION).await.unwrap();
             }
+        }
+        buf
+    }
+
+    async fn apply(packets: &[Vec<u8>]) -> (PlayerList, HashMap<UUID, i32>, HashMap<UUID, u8>) {
+        let all_local_players = Mutex::new(PlayerList::new());
+        let pings = Mutex::new(HashMap::new());
+        let gamemodes = Mutex::new(HashMap::new());
+        for packet in packets {
+            let info = OwnedPacket::new(0x38, packet.clone())
+                .parse::<PlayerInfoPacket>(VERSION)
+                .await
+                .unwrap()
+                .packet;
+            track_player_info(info, &all_local_players, &pings, &gamemodes);
+        }
+        (
+            all_local_players.into_inner().unwrap(),
+            pings.into_inner().unwrap(),
+            gamemodes.into_inner().unwrap(),
+        )
+    }
+
+    fn uuid(last: u8) -> UUID {
+        let mut uuid = [0; 16];
+        uuid[15] = last;
+        UUID(uuid)
+    }
+
+    #[test]
+    async fn remove_before_add() {
+        let (players, pings, _) = apply(&[
+            player_info(4, &[(uuid(1), "")]).await,
+            player_info(0, &[(uuid(1), "Steve"), (uuid(2), "Alex")]).await,
+            player_info(4, &[(uuid(3), "")]).await,
+        ])
+        .await;
+        assert_eq!(players.len(), 2);
+        assert_eq!(players.get_uuid_by_name("steve"), Some(uuid(1)));
+        assert_eq!(pings.get(&uuid(2)), Some(&42));
+    }
+
+    #[test]
+    async fn readd_replaces_stale_entry() {
+        let (players, pings, _) = apply(&[
+            player_info(0, &[(uuid(1), "Steve")]).await,
+            player_info(0, &[(uuid(1), "Alex")]).await,
+            player_info(0, &[(uuid(2), "Alex")]).await,
+            player_info(4, &[(uuid(1), "")]).await,
+        ])
+        .await;
+        assert_eq!(players.len(), 1);
+        assert_eq!(players.get_name_by_uuid(uuid(2)), Some("Alex"));
+        assert!(players.get_uuid_by_name("steve").is_none());
+        assert!(!pings.contains_key(&uuid(1)));
+    }
+
+    #[test]
+    async fn gamemode_updates() {
+        let (_, _, gamemodes) = apply(&[
+            player_info(0, &[(uuid(1), "Steve"), (uuid(2), "Alex")]).await,
+            player_info(1, &[(uuid(1), ""), (uuid(2), "")]).await,
+            player_info(4, &[(uuid(2), "")]).await,
+        ])
+        .await;
+        assert_eq!(gamemodes.get(&uuid(1)), Some(&3));
+        assert!(!gamemodes.contains_key(&uuid(2)));
+    }
+
+    #[test]
+    async fn stats_chat_colors() {
+        let info: PlayerInfo = serde_json::from_str(
+            r#"{"stats":{"Bedwars":{"final_kills_bedwars":3}},"rank":"Default","name":"Steve"}"#,
+        )
+        .unwrap();
+        let fkdr_color = |settings| match stats_chat(&info, &settings) {
+            Chat::Array(parts) => match &parts[1] {
+                Chat::Object(ChatObject {
+                    extra: Some(extra), ..
+                }) => match &extra[0] {
+                    Chat::Object(fkdr) => fkdr.color,
+                    other => panic!("unexpected FKDR {:?}", other),
+                },
+                other => panic!("unexpected stats {:?}", other),
+            },
+            other => panic!("unexpected message {:?}", other),
+        };
+        let mut settings = DynamicConfig::default();
+        assert_eq!(fkdr_color(settings.clone()), None);
+        settings.stat_tiers.push(config::StatTier {
+            min_fkdr: 2.0,
+            color: Color::Gold,
         });
+        assert_eq!(fkdr_color(settings), Some(Color::Gold));
+    }
+
+    #[test]
+    async fn split_long_message() {
+        let msg = (0..43)
+            .map(|i| format!("word{:02}", i))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(msg.len(), 300);
+        let chunks = split_message(&msg, MAX_CHAT_MESSAGE_LEN - "/pc ".len());
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].len() <= MAX_CHAT_MESSAGE_LEN - "/pc ".len());
+        assert_eq!(chunks.join(" "), msg);
+
+        assert_eq!(split_message("abcdefg hi", 3), ["abc", "def", "g", "hi"]);
+        assert!(split_message("   ", 3).is_empty());
+    }
+
+    #[test]
+    async fn action_bar_cycles() {
+        let start = Instant::now();
+        let mut queue = VecDeque::from(["a", "b"]);
+        let mut state = ActionBarState::Idle;
+        assert_eq!(state.next(&mut queue, start), Some("a"));
+        assert_eq!(state.next(&mut queue, start + Duration::from_secs(1)), None);
+        let later = start + ACTION_BAR_DISPLAY_TIME;
+        assert_eq!(state.next(&mut queue, later), Some("b"));
+        let end = later + ACTION_BAR_DISPLAY_TIME;
+        assert_eq!(state.next(&mut queue, end), None);
+        assert!(matches!(state, ActionBarState::Idle));
+        queue.push_back("c");
+        assert_eq!(state.next(&mut queue, end), Some("c"));
     }
 }
@@ -0,0 +1,36 @@
+use mc_proxy::{start_with_config, Config, DEFAULT_UPSTREAM};
+use std::{env, path::PathBuf, process::exit};
+
+const USAGE: &str = "usage: mc-proxy [--config <path>]";
+const DEFAULT_CONFIG_PATH: &str = "mc-proxy.toml";
+
+#[tokio::main]
+async fn main() {
+    let mut config_path = PathBuf::from(DEFAULT_CONFIG_PATH);
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--config", Some(path)) => config_path = PathBuf::from(path),
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(2);
+            }
+        }
+    }
+    let config = match Config::from_file(&config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}: {}", config_path.display(), err);
+            exit(1);
+        }
+    };
+    println!(
+        "Proxying {} to {}",
+        config.listen_addr,
+        config.upstream.as_deref().unwrap_or(DEFAULT_UPSTREAM)
+    );
+    if let Err(err) = start_with_config(config).await {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
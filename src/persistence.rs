@@ -0,0 +1,186 @@
+use crate::{protocol::types::UUID, web::hypixel::PlayerInfo};
+use serde::Serialize;
+use sqlx::{
+    migrate::MigrateError,
+    sqlite::{SqliteConnectOptions, SqlitePool},
+    Error as SQLError, Row,
+};
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("database error")]
+    SQL(#[from] SQLError),
+    #[error("failed to set up the database")]
+    Migrate(#[from] MigrateError),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatsSnapshot {
+    // Seconds since the Unix epoch
+    pub timestamp: i64,
+    pub fkdr: Option<f64>,
+    pub wlr: Option<f64>,
+    // Network level, with progress towards the next as the fraction
+    pub level: Option<f64>,
+}
+
+impl StatsSnapshot {
+    // None for players who have never played Bedwars
+    pub fn from_info(info: &PlayerInfo) -> Option<StatsSnapshot> {
+        let bedwars = info.stats.bedwars.as_ref()?;
+        Some(StatsSnapshot {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs() as i64),
+            fkdr: Some(bedwars.fkdr()),
+            wlr: Some(bedwars.wlr()),
+            level: Some(info.network_level_exact()),
+        })
+    }
+
+    // UTC, as YYYY-MM-DD
+    pub fn date(&self) -> String {
+        // Howard Hinnant's days_from_civil, inverted
+        let days = self.timestamp.div_euclid(86400) + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+// Clones share the same connection pool
+#[derive(Clone, Debug)]
+pub struct StatsDb {
+    pool: SqlitePool,
+}
+
+impl StatsDb {
+    pub async fn open(path: &Path) -> Result<StatsDb, Error> {
+        let pool = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true),
+        )
+        .await?;
+        sqlx::migrate!().run(&pool).await?;
+        Ok(StatsDb { pool })
+    }
+
+    // Skipped if nothing changed since the last snapshot, so repeated lookups
+    // don't flood the history
+    pub async fn record(&self, uuid: UUID, snapshot: &StatsSnapshot) -> Result<(), Error> {
+        let latest = self.history(uuid, 1).await?;
+        if let Some(latest) = latest.first() {
+            if (latest.fkdr, latest.wlr, latest.level)
+                == (snapshot.fkdr, snapshot.wlr, snapshot.level)
+            {
+                return Ok(());
+            }
+        }
+        sqlx::query(
+            "INSERT OR REPLACE INTO stats (uuid, timestamp, fkdr, wlr, level) \
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(uuid.to_string())
+        .bind(snapshot.timestamp)
+        .bind(snapshot.fkdr)
+        .bind(snapshot.wlr)
+        .bind(snapshot.level)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Newest first
+    pub async fn history(&self, uuid: UUID, limit: u32) -> Result<Vec<StatsSnapshot>, Error> {
+        Ok(sqlx::query(
+            "SELECT timestamp, fkdr, wlr, level FROM stats WHERE uuid = ? \
+            ORDER BY timestamp DESC LIMIT ?",
+        )
+        .bind(uuid.to_string())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| StatsSnapshot {
+            timestamp: row.get("timestamp"),
+            fkdr: row.get("fkdr"),
+            wlr: row.get("wlr"),
+            level: row.get("level"),
+        })
+        .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatsDb, StatsSnapshot};
+    use crate::web::hypixel::PlayerInfo;
+    use std::{env::temp_dir, fs::remove_file, process};
+    use tokio::test;
+
+    fn snapshot(timestamp: i64, fkdr: f64) -> StatsSnapshot {
+        StatsSnapshot {
+            timestamp,
+            fkdr: Some(fkdr),
+            wlr: Some(1.0),
+            level: None,
+        }
+    }
+
+    #[test]
+    async fn history() {
+        let path = temp_dir().join(format!("mc-proxy-stats-{}.db", process::id()));
+        let db = StatsDb::open(&path).await.unwrap();
+        let uuid = "069a79f4-44e9-4726-a5be-fca90e38aaf5".parse().unwrap();
+        db.record(uuid, &snapshot(100, 2.0)).await.unwrap();
+        // Unchanged, so not recorded
+        db.record(uuid, &snapshot(200, 2.0)).await.unwrap();
+        db.record(uuid, &snapshot(300, 2.5)).await.unwrap();
+        assert_eq!(
+            db.history(uuid, 5).await.unwrap(),
+            [snapshot(300, 2.5), snapshot(100, 2.0)]
+        );
+        drop(db);
+        let _ = remove_file(path);
+    }
+
+    #[test]
+    async fn from_info() {
+        let info: PlayerInfo =
+            serde_json::from_str(r#"{"stats":{"Bedwars":null},"rank":"Default","name":"Steve"}"#)
+                .unwrap();
+        assert_eq!(StatsSnapshot::from_info(&info), None);
+        let info: PlayerInfo = serde_json::from_str(
+            r#"{"stats":{"Bedwars":{"final_kills_bedwars":4,"final_deaths_bedwars":2}},
+            "rank":"Default","name":"Steve","network_exp":10000.0}"#,
+        )
+        .unwrap();
+        let snapshot = StatsSnapshot::from_info(&info).unwrap();
+        assert_eq!(snapshot.fkdr, Some(2.0));
+        assert_eq!(snapshot.level, Some(2.0));
+    }
+
+    #[test]
+    async fn dates() {
+        assert_eq!(snapshot(0, 0.0).date(), "1970-01-01");
+        assert_eq!(snapshot(1705276800, 0.0).date(), "2024-01-15");
+        assert_eq!(snapshot(951782400, 0.0).date(), "2000-02-29");
+    }
+}
@@ -8,6 +8,9 @@ pub enum Error {
     NeedMore,
     #[error("malformed data")]
     Malformed,
+    // serde_json::Error isn't Clone, so only its message is kept
+    #[error("invalid JSON: {0}")]
+    InvalidJson(String),
     #[error("unknown error")]
     Unknown,
 }
@@ -1,3 +1,4 @@
 pub mod error;
+pub mod nbt;
 pub mod types;
 pub mod version;
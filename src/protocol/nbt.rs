@@ -0,0 +1,303 @@
+use super::error::Error;
+use std::{collections::HashMap, iter::Peekable, str::CharIndices};
+
+// Minecraft's own limit. Nesting past it comes from a hostile server trying
+// to overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+// Only the stringified (SNBT, a.k.a. JSON-NBT) form is supported, as used by
+// hover events. Both the modern syntax and the laxer pre-1.13 one (unquoted
+// strings with colons, indexed lists) are accepted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    List(Vec<Nbt>),
+    Compound(HashMap<String, Nbt>),
+    ByteArray(Vec<i8>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Nbt {
+    pub fn parse(src: &str) -> Result<Nbt, Error> {
+        let mut parser = Parser {
+            src,
+            chars: src.char_indices().peekable(),
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            Some(_) => Err(Error::Malformed),
+            None => Ok(value),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Nbt> {
+        match self {
+            Nbt::Compound(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Nbt::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Nbt::Byte(val) => Some(val.into()),
+            Nbt::Short(val) => Some(val.into()),
+            Nbt::Int(val) => Some(val.into()),
+            Nbt::Long(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    // Compounds and lists currently open
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            Some(_) => Err(Error::Malformed),
+            None => Err(Error::UnexpectedEOF),
+        }
+    }
+
+    fn value(&mut self) -> Result<Nbt, Error> {
+        match self.peek().ok_or(Error::UnexpectedEOF)? {
+            c @ ('{' | '[') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(Error::Malformed);
+                }
+                self.depth += 1;
+                let value = if c == '{' {
+                    self.compound()
+                } else {
+                    self.list()
+                };
+                self.depth -= 1;
+                value
+            }
+            '"' | '\'' => Ok(Nbt::String(self.quoted()?)),
+            _ => Ok(scalar(self.unquoted(&[',', '}', ']']))),
+        }
+    }
+
+    fn compound(&mut self) -> Result<Nbt, Error> {
+        self.expect('{')?;
+        let mut map = HashMap::new();
+        if self.peek() == Some('}') {
+            self.chars.next();
+            return Ok(Nbt::Compound(map));
+        }
+        loop {
+            let key = match self.peek() {
+                Some('"') | Some('\'') => self.quoted()?,
+                _ => self.unquoted(&[':']).to_string(),
+            };
+            self.expect(':')?;
+            map.insert(key, self.value()?);
+            match self.peek() {
+                Some(',') => {
+                    self.chars.next();
+                }
+                Some('}') => {
+                    self.chars.next();
+                    return Ok(Nbt::Compound(map));
+                }
+                Some(_) => return Err(Error::Malformed),
+                None => return Err(Error::UnexpectedEOF),
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<Nbt, Error> {
+        self.expect('[')?;
+        let start = self.chars.peek().map_or(self.src.len(), |&(i, _)| i);
+        let array_kind = match self.src[start..].get(..2) {
+            Some(prefix @ ("B;" | "I;" | "L;")) => {
+                self.chars.nth(1);
+                prefix.chars().next()
+            }
+            _ => None,
+        };
+        let mut values = Vec::new();
+        if self.peek() == Some(']') {
+            self.chars.next();
+        } else {
+            loop {
+                // Lists before 1.13 could have indices, e.g. [0:"a",1:"b"]
+                let rest = &self.src[self.chars.peek().map_or(self.src.len(), |&(i, _)| i)..];
+                let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+                if digits != 0 && rest.as_bytes().get(digits) == Some(&b':') {
+                    self.chars.nth(digits);
+                }
+                values.push(self.value()?);
+                match self.peek() {
+                    Some(',') => {
+                        self.chars.next();
+                    }
+                    Some(']') => {
+                        self.chars.next();
+                        break;
+                    }
+                    Some(_) => return Err(Error::Malformed),
+                    None => return Err(Error::UnexpectedEOF),
+                }
+            }
+        }
+        let integers = || {
+            values
+                .iter()
+                .map(|value| value.as_i64().ok_or(Error::Malformed))
+        };
+        Ok(match array_kind {
+            Some('B') => Nbt::ByteArray(
+                integers()
+                    .map(|val| val.map(|val| val as i8))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Some('I') => Nbt::IntArray(
+                integers()
+                    .map(|val| val.map(|val| val as i32))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Some(_) => Nbt::LongArray(integers().collect::<Result<_, _>>()?),
+            None => Nbt::List(values),
+        })
+    }
+
+    fn quoted(&mut self) -> Result<String, Error> {
+        let quote = self.chars.next().ok_or(Error::UnexpectedEOF)?.1;
+        let mut out = String::new();
+        loop {
+            match self.chars.next().ok_or(Error::UnexpectedEOF)?.1 {
+                '\\' => out.push(self.chars.next().ok_or(Error::UnexpectedEOF)?.1),
+                c if c == quote => return Ok(out),
+                c => out.push(c),
+            }
+        }
+    }
+
+    // Reads up to (but not including) the first terminator
+    fn unquoted(&mut self, terminators: &[char]) -> &str {
+        self.skip_whitespace();
+        let start = self.chars.peek().map_or(self.src.len(), |&(i, _)| i);
+        while self
+            .chars
+            .next_if(|(_, c)| !terminators.contains(c))
+            .is_some()
+        {}
+        let end = self.chars.peek().map_or(self.src.len(), |&(i, _)| i);
+        self.src[start..end].trim()
+    }
+}
+
+fn scalar(token: &str) -> Nbt {
+    match token {
+        "true" => return Nbt::Byte(1),
+        "false" => return Nbt::Byte(0),
+        _ => {}
+    }
+    let (number, suffix) = match token.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&token[..i], Some(c.to_ascii_lowercase())),
+        _ => (token, None),
+    };
+    let parsed = match suffix {
+        Some('b') => number.parse().ok().map(Nbt::Byte),
+        Some('s') => number.parse().ok().map(Nbt::Short),
+        Some('l') => number.parse().ok().map(Nbt::Long),
+        Some('f') => number.parse().ok().map(Nbt::Float),
+        Some('d') => number.parse().ok().map(Nbt::Double),
+        Some(_) => None,
+        None => number
+            .parse()
+            .ok()
+            .map(Nbt::Int)
+            .or_else(|| number.parse().ok().map(Nbt::Double)),
+    };
+    parsed.unwrap_or_else(|| Nbt::String(token.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Nbt;
+
+    #[test]
+    fn modern() {
+        let nbt = Nbt::parse(
+            r#"{id:"minecraft:diamond_sword",Count:1b,tag:{display:{Name:'{"text":"Sword"}'},Damage:3s,ids:[I;1,-2]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            nbt.get("id").and_then(Nbt::as_str),
+            Some("minecraft:diamond_sword")
+        );
+        assert_eq!(nbt.get("Count"), Some(&Nbt::Byte(1)));
+        let tag = nbt.get("tag").unwrap();
+        assert_eq!(
+            tag.get("display").and_then(|display| display.get("Name")),
+            Some(&Nbt::String(r#"{"text":"Sword"}"#.into()))
+        );
+        assert_eq!(tag.get("Damage"), Some(&Nbt::Short(3)));
+        assert_eq!(tag.get("ids"), Some(&Nbt::IntArray(vec![1, -2])));
+    }
+
+    #[test]
+    fn legacy() {
+        let nbt =
+            Nbt::parse(r#"{id:minecraft:stone,Count:64b,tag:{ench:[0:{id:16s,lvl:5s}],x:1.5}}"#)
+                .unwrap();
+        assert_eq!(nbt.get("id").and_then(Nbt::as_str), Some("minecraft:stone"));
+        let tag = nbt.get("tag").unwrap();
+        match tag.get("ench") {
+            Some(Nbt::List(list)) => assert_eq!(list[0].get("lvl"), Some(&Nbt::Short(5))),
+            ench => panic!("unexpected enchantments {:?}", ench),
+        }
+        assert_eq!(tag.get("x"), Some(&Nbt::Double(1.5)));
+    }
+
+    #[test]
+    fn invalid() {
+        for src in ["", "{", "{a:1", "{a:1}}", "[1,2", r#"{a:"b}"#] {
+            assert!(Nbt::parse(src).is_err(), "{:?} was accepted", src);
+        }
+    }
+
+    #[test]
+    fn depth_limit() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Nbt::parse(&nested(512)).is_ok());
+        assert!(Nbt::parse(&nested(513)).is_err());
+        assert!(Nbt::parse(&"{a:".repeat(100000)).is_err());
+    }
+}
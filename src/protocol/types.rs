@@ -1,8 +1,9 @@
-use super::{error::Error, version::ProtocolVersion};
+use super::{error::Error, nbt::Nbt, version::ProtocolVersion};
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde_with::{skip_serializing_none, DeserializeFromStr};
 use std::{
     borrow::Cow,
+    collections::VecDeque,
     convert::{TryFrom, TryInto},
     fmt::{self, Display},
     future::Future,
@@ -10,7 +11,7 @@ use std::{
 };
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-fn handle_io_err(err: std::io::Error) -> Error {
+pub(crate) fn handle_io_err(err: std::io::Error) -> Error {
     match err.kind() {
         std::io::ErrorKind::UnexpectedEof => Error::UnexpectedEOF,
         std::io::ErrorKind::WriteZero => Error::NeedMore,
@@ -131,7 +132,7 @@ decode_impl!(VarInt, src, {
     let mut bit_offset = 0;
     let mut result = 0;
     loop {
-        if bit_offset == 35 {
+        if bit_offset == VarInt::MAX_ENCODED_LEN * 7 {
             return Err(Error::Malformed);
         }
         match src.read_u8().await {
@@ -148,7 +149,25 @@ decode_impl!(VarInt, src, {
     }
 });
 
-// TODO: get same performance with better code
+// Little-endian groups of 7 bits, with the top bit set on all but the last.
+// For code that can't await, e.g. inside poll_write.
+#[inline(always)]
+pub fn varint_to_bytes(value: i32) -> ([u8; VarInt::MAX_ENCODED_LEN], usize) {
+    let mut buf = [0; VarInt::MAX_ENCODED_LEN];
+    // Unsigned so negative values take all 5 bytes instead of looping forever
+    let mut value = value as u32;
+    let mut len = 0;
+    while value >= 128 {
+        buf[len] = value as u8 | 128;
+        value >>= 7;
+        len += 1;
+    }
+    buf[len] = value as u8;
+    (buf, len + 1)
+}
+
+// Writing a fixed-size array per length class benchmarks ~15% faster than
+// a single write of varint_to_bytes' slice, so async encoding keeps this
 encode_impl!(VarInt, self, tgt, {
     match self.0 {
         0..=127 => tgt.write_all(&[self.0 as u8]).await,
@@ -189,13 +208,15 @@ encode_impl!(VarInt, self, tgt, {
 });
 
 impl VarInt {
-    pub fn len(&self) -> usize {
+    pub const MAX_ENCODED_LEN: usize = 5;
+
+    pub const fn len(&self) -> usize {
         match self.0 {
             0..=127 => 1,
             128..=16383 => 2,
             16384..=2097151 => 3,
             2097152..=268435455 => 4,
-            _ => 5,
+            _ => Self::MAX_ENCODED_LEN,
         }
     }
 }
@@ -219,7 +240,7 @@ decode_impl!(VarLong, src, {
     let mut bit_offset = 0;
     let mut result = 0;
     loop {
-        if bit_offset == 70 {
+        if bit_offset == VarLong::MAX_ENCODED_LEN * 7 {
             return Err(Error::Malformed);
         }
         match src.read_u8().await {
@@ -236,124 +257,36 @@ decode_impl!(VarLong, src, {
     }
 });
 
-// TODO: get same performance with better code
-encode_impl!(VarLong, self, tgt, {
-    match self.0 {
-        0..=127 => tgt.write_all(&[self.0 as u8]).await,
-        128..=16383 => {
-            tgt.write_all(&[self.0 as u8 | 128, (self.0 >> 7) as u8])
-                .await
-        }
-        16384..=2097151 => {
-            tgt.write_all(&[
-                self.0 as u8 | 128,
-                (self.0 >> 7) as u8 | 128,
-                (self.0 >> 14) as u8,
-            ])
-            .await
-        }
-        2097152..=268435455 => {
-            tgt.write_all(&[
-                self.0 as u8 | 128,
-                (self.0 >> 7) as u8 | 128,
-                (self.0 >> 14) as u8 | 128,
-                (self.0 >> 21) as u8,
-            ])
-            .await
-        }
-        268435456..=34359738367 => {
-            tgt.write_all(&[
-                self.0 as u8 | 128,
-                (self.0 >> 7) as u8 | 128,
-                (self.0 >> 14) as u8 | 128,
-                (self.0 >> 21) as u8 | 128,
-                (self.0 >> 28) as u8,
-            ])
-            .await
-        }
-        34359738368..=4398046511103 => {
-            tgt.write_all(&[
-                self.0 as u8 | 128,
-                (self.0 >> 7) as u8 | 128,
-                (self.0 >> 14) as u8 | 128,
-                (self.0 >> 21) as u8 | 128,
-                (self.0 >> 28) as u8 | 128,
-                (self.0 >> 35) as u8,
-            ])
-            .await
-        }
-        4398046511104..=562949953421311 => {
-            tgt.write_all(&[
-                self.0 as u8 | 128,
-                (self.0 >> 7) as u8 | 128,
-                (self.0 >> 14) as u8 | 128,
-                (self.0 >> 21) as u8 | 128,
-                (self.0 >> 28) as u8 | 128,
-                (self.0 >> 35) as u8 | 128,
-                (self.0 >> 42) as u8,
-            ])
-            .await
-        }
-        562949953421312..=72057594037927935 => {
-            tgt.write_all(&[
-                self.0 as u8 | 128,
-                (self.0 >> 7) as u8 | 128,
-                (self.0 >> 14) as u8 | 128,
-                (self.0 >> 21) as u8 | 128,
-                (self.0 >> 28) as u8 | 128,
-                (self.0 >> 35) as u8 | 128,
-                (self.0 >> 42) as u8 | 128,
-                (self.0 >> 49) as u8,
-            ])
-            .await
-        }
-        72057594037927936.. => {
-            tgt.write_all(&[
-                self.0 as u8 | 128,
-                (self.0 >> 7) as u8 | 128,
-                (self.0 >> 14) as u8 | 128,
-                (self.0 >> 21) as u8 | 128,
-                (self.0 >> 28) as u8 | 128,
-                (self.0 >> 35) as u8 | 128,
-                (self.0 >> 42) as u8 | 128,
-                (self.0 >> 49) as u8 | 128,
-                (self.0 >> 56) as u8,
-            ])
-            .await
-        }
-        _ => {
-            tgt.write_all(&[
-                self.0 as u8 | 128,
-                (self.0 >> 7) as u8 | 128,
-                (self.0 >> 14) as u8 | 128,
-                (self.0 >> 21) as u8 | 128,
-                (self.0 >> 28) as u8 | 128,
-                (self.0 >> 35) as u8 | 128,
-                (self.0 >> 42) as u8 | 128,
-                (self.0 >> 49) as u8 | 128,
-                (self.0 >> 56) as u8 | 128,
-                // No unsigned cast because always negative here
-                1, // this byte is useless but needed
-            ])
-            .await
-        }
+// As varint_to_bytes, for up to 64 bits
+#[inline(always)]
+pub fn varlong_to_bytes(value: i64) -> ([u8; VarLong::MAX_ENCODED_LEN], usize) {
+    let mut buf = [0; VarLong::MAX_ENCODED_LEN];
+    let mut value = value as u64;
+    let mut len = 0;
+    while value >= 128 {
+        buf[len] = value as u8 | 128;
+        value >>= 7;
+        len += 1;
     }
-    .map_err(handle_io_err)
+    buf[len] = value as u8;
+    (buf, len + 1)
+}
+
+encode_impl!(VarLong, self, tgt, {
+    let (buf, len) = varlong_to_bytes(self.0);
+    tgt.write_all(&buf[..len]).await.map_err(handle_io_err)
 });
 
 impl VarLong {
-    pub fn len(&self) -> usize {
-        match self.0 {
-            0..=127 => 1,
-            128..=16383 => 2,
-            16384..=2097151 => 3,
-            2097152..=268435455 => 4,
-            268435456..=34359738367 => 5,
-            34359738368..=4398046511103 => 6,
-            4398046511104..=562949953421311 => 7,
-            562949953421312..=72057594037927935 => 8,
-            72057594037927936.. => 9,
-            _ => 10,
+    pub const MAX_ENCODED_LEN: usize = 10;
+
+    // 7 bits per byte, and at least one byte even for 0
+    pub const fn len(&self) -> usize {
+        let bits = 64 - (self.0 as u64).leading_zeros() as usize;
+        if bits == 0 {
+            1
+        } else {
+            (bits + 6) / 7
         }
     }
 }
@@ -372,6 +305,21 @@ impl From<VarLong> for i64 {
 #[derive(Clone, Debug)]
 pub struct LengthCappedString<'a, const L: usize>(pub Cow<'a, str>);
 
+// Minecraft counts characters the way Java does, in UTF-16 code units. Each
+// takes at most 3 bytes in UTF-8 (4 for a surrogate pair), so the byte length
+// rules out most strings before anything is counted.
+fn check_string_len<const L: usize>(str: &str) -> Result<(), Error> {
+    if str.len() <= L
+        || (str.len() <= (L << 2)
+            && str.len() <= i32::MAX as usize
+            && str.encode_utf16().count() <= L)
+    {
+        Ok(())
+    } else {
+        Err(Error::Malformed)
+    }
+}
+
 impl<'a, R: AsyncReadExt + Unpin + 'a, const L: usize> Decode<'a, R>
     for LengthCappedString<'static, L>
 {
@@ -383,7 +331,10 @@ impl<'a, R: AsyncReadExt + Unpin + 'a, const L: usize> Decode<'a, R>
         let mut buf = vec![0; str_len];
         match src.read_exact(&mut buf).await {
             Ok(_) => match String::from_utf8(buf) {
-                Ok(str) => Ok(LengthCappedString(Cow::Owned(str))),
+                Ok(str) => {
+                    check_string_len::<L>(&str)?;
+                    Ok(LengthCappedString(Cow::Owned(str)))
+                }
                 Err(_) => todo!(),
             },
             Err(err) => Err(handle_io_err(err)),
@@ -396,26 +347,27 @@ impl<'a, W: AsyncWriteExt + Unpin + 'a, const L: usize> Encode<'a, W>
 {
     encode_inner_impl!('a, W, self, tgt, version, {
         let str = self.0;
-        if str.len() > (L << 2) || str.len() > i32::MAX as usize {
-            Err(Error::Malformed)
-        } else {
-            VarInt(str.len() as i32).encode(tgt, version).await?;
-            tgt.write_all(str.as_bytes())
-                .await
-                .map_err(handle_io_err)
-        }
+        check_string_len::<L>(&str)?;
+        VarInt(str.len() as i32).encode(tgt, version).await?;
+        tgt.write_all(str.as_bytes())
+            .await
+            .map_err(handle_io_err)
     });
 }
 
+impl<const L: usize> LengthCappedString<'_, L> {
+    // L is in characters, each of which is up to 4 bytes in UTF-8
+    pub const fn max_bytes() -> usize {
+        (L << 2) + VarInt((L << 2) as i32).len()
+    }
+}
+
 impl<const L: usize> TryFrom<String> for LengthCappedString<'_, L> {
     type Error = Error;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        if value.len() > (L << 2) || value.len() > i32::MAX as usize {
-            Err(Error::Malformed)
-        } else {
-            Ok(LengthCappedString(Cow::Owned(value)))
-        }
+        check_string_len::<L>(&value)?;
+        Ok(LengthCappedString(Cow::Owned(value)))
     }
 }
 
@@ -423,11 +375,8 @@ impl<'a, const L: usize> TryFrom<&'a str> for LengthCappedString<'a, L> {
     type Error = Error;
 
     fn try_from(value: &'a str) -> Result<Self, Self::Error> {
-        if value.len() > (L << 2) || value.len() > i32::MAX as usize {
-            Err(Error::Malformed)
-        } else {
-            Ok(LengthCappedString(Cow::Borrowed(value)))
-        }
+        check_string_len::<L>(value)?;
+        Ok(LengthCappedString(Cow::Borrowed(value)))
     }
 }
 
@@ -522,6 +471,57 @@ impl FromStr for Color {
     }
 }
 
+// The RGB values the client renders the named colors with
+const NAMED_COLORS: [(Color, u32); 16] = [
+    (Color::Black, 0x000000),
+    (Color::DarkBlue, 0x0000AA),
+    (Color::DarkGreen, 0x00AA00),
+    (Color::DarkAqua, 0x00AAAA),
+    (Color::DarkRed, 0xAA0000),
+    (Color::DarkPurple, 0xAA00AA),
+    (Color::Gold, 0xFFAA00),
+    (Color::Gray, 0xAAAAAA),
+    (Color::DarkGray, 0x555555),
+    (Color::Blue, 0x5555FF),
+    (Color::Green, 0x55FF55),
+    (Color::Aqua, 0x55FFFF),
+    (Color::Red, 0xFF5555),
+    (Color::LightPurple, 0xFF55FF),
+    (Color::Yellow, 0xFFFF55),
+    (Color::White, 0xFFFFFF),
+];
+
+impl Color {
+    // The named color closest to the given one, by squared distance between
+    // the RGB components. Never Reset or Hex.
+    pub fn nearest_named(r: u8, g: u8, b: u8) -> Color {
+        let distance = |rgb: u32| {
+            [(r, 16), (g, 8), (b, 0)]
+                .iter()
+                .map(|&(component, shift)| {
+                    let diff = component as i32 - ((rgb >> shift) & 0xFF) as i32;
+                    diff * diff
+                })
+                .sum::<i32>()
+        };
+        NAMED_COLORS
+            .iter()
+            .min_by_key(|&&(_, rgb)| distance(rgb))
+            .unwrap()
+            .0
+    }
+}
+
+// Hex colors are 1.16+, so older versions get the closest named color
+pub fn downgrade_hex_color(color: Color, version: ProtocolVersion) -> Color {
+    match color {
+        Color::Hex(hex) if version.is_before(ProtocolVersion::V1_16) => {
+            Color::nearest_named((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+        }
+        color => color,
+    }
+}
+
 impl TryFrom<&str> for Color {
     type Error = Error;
 
@@ -535,7 +535,8 @@ impl From<&Color> for Cow<'_, str> {
         if let Color::Hex(hex) = *value {
             let mut buf = Vec::with_capacity(7);
             buf.push(b'#');
-            for i in 2..8 {
+            // Most significant digit first
+            for i in (0..6).rev() {
                 let value = ((hex >> (i << 2)) & 15) as u8;
                 buf.push(value + (if value < 10 { b'0' } else { b'A' - 10 }));
             }
@@ -579,7 +580,7 @@ impl Display for Color {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "snake_case", tag = "action", content = "value")]
 pub enum ChatClickEvent<'a> {
     OpenUrl(Cow<'a, str>),
@@ -611,13 +612,13 @@ impl ChatClickEvent<'_> {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "snake_case", tag = "action", content = "value")]
 #[allow(clippy::enum_variant_names)]
 pub enum ChatHoverEvent<'a> {
     ShowText(Box<Chat<'a>>),
-    // TODO: Serialize and deserialize JSON-NBT
-    // JSON-NBT (a.k.a. SNBT)
+    // JSON-NBT (a.k.a. SNBT), kept as-is so that re-encoding is lossless. See
+    // ChatHoverEvent::item and ChatHoverEvent::entity for the parsed forms.
     ShowItem(Cow<'a, str>),
     // JSON-NBT (a.k.a. SNBT)
     ShowEntity(Cow<'a, str>),
@@ -641,8 +642,84 @@ impl ChatHoverEvent<'_> {
     }
 }
 
+impl ChatHoverEvent<'_> {
+    // None if this isn't a ShowItem event
+    pub fn item(&self) -> Option<Result<HoverItem, Error>> {
+        match self {
+            ChatHoverEvent::ShowItem(item) => Some(HoverItem::parse(item)),
+            _ => None,
+        }
+    }
+
+    // None if this isn't a ShowEntity event
+    pub fn entity(&self) -> Option<Result<HoverEntity, Error>> {
+        match self {
+            ChatHoverEvent::ShowEntity(entity) => Some(HoverEntity::parse(entity)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HoverItem {
+    pub id: Identifier<'static>,
+    pub count: u8,
+    pub tag: Option<Nbt>,
+}
+
+impl HoverItem {
+    fn parse(src: &str) -> Result<HoverItem, Error> {
+        let nbt = Nbt::parse(src)?;
+        let id = match nbt.get("id").ok_or(Error::Malformed)? {
+            Nbt::String(id) => id.clone(),
+            // Numeric IDs from before 1.8
+            id => id.as_i64().ok_or(Error::Malformed)?.to_string(),
+        };
+        Ok(HoverItem {
+            id: id.try_into()?,
+            count: match nbt.get("Count") {
+                Some(count) => u8::try_from(count.as_i64().ok_or(Error::Malformed)?)
+                    .map_err(|_| Error::Malformed)?,
+                None => 1,
+            },
+            tag: nbt.get("tag").cloned(),
+        })
+    }
+
+    // The custom name, e.g. from an anvil, as a chat component on 1.13+ and
+    // a legacy formatted string before that
+    pub fn display_name(&self) -> Option<&str> {
+        self.tag.as_ref()?.get("display")?.get("Name")?.as_str()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HoverEntity {
+    pub kind: Identifier<'static>,
+    pub name: Option<Chat<'static>>,
+}
+
+impl HoverEntity {
+    fn parse(src: &str) -> Result<HoverEntity, Error> {
+        let nbt = Nbt::parse(src)?;
+        let kind = nbt
+            .get("type")
+            .and_then(Nbt::as_str)
+            .ok_or(Error::Malformed)?;
+        Ok(HoverEntity {
+            kind: kind.to_string().try_into()?,
+            // A JSON chat component since 1.12, the plain name before
+            name: nbt.get("name").and_then(Nbt::as_str).map(|name| {
+                serde_json::from_str::<Chat<'_>>(name)
+                    .map(Chat::into_owned)
+                    .unwrap_or_else(|_| Chat::Raw(Cow::Owned(name.to_string())))
+            }),
+        })
+    }
+}
+
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ChatScore<'a> {
     pub name: Cow<'a, str>,
     pub objective: Cow<'a, str>,
@@ -659,7 +736,7 @@ impl ChatScore<'_> {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
 pub enum ChatValue<'a> {
     Text {
@@ -710,7 +787,7 @@ impl ChatValue<'_> {
 }
 
 #[serde_with::skip_serializing_none]
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct ChatObject<'a> {
     pub bold: Option<bool>,
@@ -749,7 +826,46 @@ impl ChatObject<'_> {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl<'a> ChatObject<'a> {
+    pub fn text(text: impl Into<Cow<'a, str>>) -> Self {
+        ChatObject {
+            value: ChatValue::Text { text: text.into() },
+            ..Default::default()
+        }
+    }
+
+    pub fn bold(mut self, bold: bool) -> Self {
+        self.bold = Some(bold);
+        self
+    }
+
+    pub fn italic(mut self, italic: bool) -> Self {
+        self.italic = Some(italic);
+        self
+    }
+
+    pub fn color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn click_open_url(mut self, url: impl Into<Cow<'a, str>>) -> Self {
+        self.click_event = Some(ChatClickEvent::OpenUrl(url.into()));
+        self
+    }
+
+    pub fn hover_show_text(mut self, text: Chat<'a>) -> Self {
+        self.hover_event = Some(ChatHoverEvent::ShowText(Box::new(text)));
+        self
+    }
+
+    pub fn extra(mut self, extra: Vec<Chat<'a>>) -> Self {
+        self.extra = Some(extra);
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
 #[allow(clippy::large_enum_variant)]
 pub enum Chat<'a> {
@@ -758,7 +874,35 @@ pub enum Chat<'a> {
     Object(ChatObject<'a>),
 }
 
+// More than any real server sends; anything bigger is likely an attempt to
+// exhaust the stack in the recursive methods below
+const MAX_CHAT_COMPONENTS: usize = 1000;
+
 impl Chat<'_> {
+    // Every component including this one, counted without recursion so that
+    // it is safe to call on untrusted chat
+    pub fn count_components(&self) -> usize {
+        let mut count = 0;
+        let mut stack = VecDeque::from([self]);
+        while let Some(chat) = stack.pop_back() {
+            count += 1;
+            match chat {
+                Chat::Raw(_) => {}
+                Chat::Array(array) => stack.extend(array),
+                Chat::Object(object) => {
+                    stack.extend(object.extra.iter().flatten());
+                    if let ChatValue::Translate { with, .. } = &object.value {
+                        stack.extend(with);
+                    }
+                    if let Some(ChatHoverEvent::ShowText(text)) = &object.hover_event {
+                        stack.push_back(text);
+                    }
+                }
+            }
+        }
+        count
+    }
+
     // Note: version fix cannot be undone, i.e. roundtrip is lossy
     fn fix_version(&mut self, version: ProtocolVersion) {
         match self {
@@ -780,7 +924,7 @@ impl Chat<'_> {
                 if let Some(ChatHoverEvent::ShowText(chat)) = &mut object.hover_event {
                     chat.fix_version(version);
                 }
-                if version >= ProtocolVersion::V1_12 {
+                if version.is_at_least(ProtocolVersion::V1_12) {
                     if let Some(ChatHoverEvent::ShowAchievement(achievement)) =
                         object.hover_event.take()
                     {
@@ -798,10 +942,8 @@ impl Chat<'_> {
                         object.click_event = Some(ChatClickEvent::OpenUrl(Cow::Owned(url)));
                     }
                 }
-                if version < ProtocolVersion::V1_16 {
-                    if let Some(Color::Hex(_)) = object.color {
-                        object.color = Some(Color::Reset);
-                    }
+                if let Some(color) = object.color {
+                    object.color = Some(downgrade_hex_color(color, version));
                 }
             }
             Chat::Array(array) => {
@@ -824,11 +966,96 @@ impl Chat<'_> {
             Chat::Object(object) => Chat::Object(object.into_owned()),
         }
     }
+
+    // The text as displayed, without colors, styles or legacy § codes.
+    // Translations are left as their key since the client's language files
+    // aren't available, with the arguments filled in.
+    pub fn to_plain_text(&self) -> String {
+        let mut out = String::new();
+        self.push_plain_text(&mut out);
+        strip_formatting_codes(&out)
+    }
+
+    fn push_plain_text(&self, out: &mut String) {
+        match self {
+            Chat::Raw(text) => out.push_str(text),
+            Chat::Array(array) => {
+                for chat in array {
+                    chat.push_plain_text(out);
+                }
+            }
+            Chat::Object(object) => {
+                match &object.value {
+                    ChatValue::Text { text } => out.push_str(text),
+                    ChatValue::Translate { translate, with } => {
+                        let mut args = with.iter();
+                        let mut parts = translate.split('%');
+                        out.push_str(parts.next().unwrap_or_default());
+                        let mut escaped = false;
+                        for part in parts {
+                            if escaped {
+                                out.push_str(part);
+                                escaped = false;
+                            } else if part.is_empty() {
+                                // %% is a literal percent sign
+                                out.push('%');
+                                escaped = true;
+                            } else if let Some(rest) = part.strip_prefix('s') {
+                                if let Some(arg) = args.next() {
+                                    arg.push_plain_text(out);
+                                }
+                                out.push_str(rest);
+                            } else if let Some((index, rest)) = part.split_once("$s") {
+                                if let Some(arg) = index
+                                    .parse::<usize>()
+                                    .ok()
+                                    .and_then(|index| with.get(index.wrapping_sub(1)))
+                                {
+                                    arg.push_plain_text(out);
+                                }
+                                out.push_str(rest);
+                            } else {
+                                out.push('%');
+                                out.push_str(part);
+                            }
+                        }
+                    }
+                    ChatValue::Score { score } => {
+                        out.push_str(score.value.as_deref().unwrap_or_default())
+                    }
+                    ChatValue::Keybind { keybind } => out.push_str(keybind),
+                    ChatValue::Selector { selector } => out.push_str(selector),
+                }
+                for chat in object.extra.iter().flatten() {
+                    chat.push_plain_text(out);
+                }
+            }
+        }
+    }
+}
+
+// Removes legacy formatting codes, i.e. § followed by any character
+pub fn strip_formatting_codes(text: &str) -> String {
+    let mut chars = text.chars();
+    let mut out = String::with_capacity(text.len());
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
 }
 
 decode_impl!(Chat<'a>, src, version, {
-    serde_json::from_str(&LengthCappedString::<262144>::decode(src, version).await?.0)
-        .map_err(|_| Error::Malformed)
+    let chat: Chat<'_> =
+        serde_json::from_str(&LengthCappedString::<262144>::decode(src, version).await?.0)
+            .map_err(|err| Error::InvalidJson(err.to_string()))?;
+    if chat.count_components() > MAX_CHAT_COMPONENTS {
+        return Err(Error::Malformed);
+    }
+    Ok(chat)
 });
 
 encode_impl!(Chat<'a>, self, tgt, version, {
@@ -839,10 +1066,21 @@ encode_impl!(Chat<'a>, self, tgt, version, {
     chat.encode(tgt, version).await
 });
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+// Ordered byte-by-byte, i.e. as unsigned 128-bit integers. Java's
+// UUID.compareTo compares the two halves as *signed* longs instead, so the
+// orders differ when the first byte of either half is 0x80 or above.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct UUID(pub [u8; 16]);
 
 impl UUID {
+    // Random, as in version 4 of RFC 4122
+    pub fn new_v4() -> UUID {
+        let mut bytes: [u8; 16] = rand::random();
+        bytes[6] = (bytes[6] & 0x0F) | 0x40;
+        bytes[8] = (bytes[8] & 0x3F) | 0x80;
+        UUID(bytes)
+    }
+
     pub fn to_ascii_bytes(self) -> [u8; 32] {
         let mut buf = [0; 32];
         for i in 0..16 {
@@ -894,17 +1132,18 @@ impl UUID {
         if s.len() == 36 {
             let mut res = [0; 16];
             let mut i = 0;
-            for byte in s.bytes() {
+            // Hyphens are checked by position in the string, since counting
+            // digits lets doubled or missing hyphens through
+            for (pos, byte) in s.bytes().enumerate() {
+                if let 8 | 13 | 18 | 23 = pos {
+                    if byte != b'-' {
+                        return Err(Error::Malformed);
+                    }
+                    continue;
+                }
                 res[i >> 1] |= match byte {
                     b'0'..=b'9' => byte - b'0',
                     b'a'..=b'f' => byte - b'a' + 10,
-                    b'-' => {
-                        if i == 8 || i == 12 || i == 16 || i == 20 {
-                            continue;
-                        } else {
-                            return Err(Error::Malformed);
-                        }
-                    }
                     _ => return Err(Error::Malformed),
                 } << (4 - ((i & 1) << 2));
                 i += 1;
@@ -1017,9 +1256,37 @@ pub struct Position {
     pub z: i32,
 }
 
+// Arithmetic shifts and masks round towards negative infinity, which is what
+// chunk coordinates need
+impl Position {
+    pub fn chunk_x(&self) -> i32 {
+        self.x >> 4
+    }
+
+    pub fn chunk_z(&self) -> i32 {
+        self.z >> 4
+    }
+
+    pub fn section_y(&self) -> i32 {
+        self.y >> 4
+    }
+
+    pub fn chunk_local_x(&self) -> u8 {
+        (self.x & 15) as u8
+    }
+
+    pub fn chunk_local_y(&self) -> u8 {
+        (self.y & 15) as u8
+    }
+
+    pub fn chunk_local_z(&self) -> u8 {
+        (self.z & 15) as u8
+    }
+}
+
 decode_impl!(Position, src, version, {
     i64::decode(src, version).await.map(|num| {
-        let (x, y, z) = if version >= ProtocolVersion::V1_14_4 {
+        let (x, y, z) = if version.is_at_least(ProtocolVersion::V1_14_4) {
             (
                 (num >> 38) as i32,
                 (num << 52 >> 52) as i32,
@@ -1042,28 +1309,100 @@ encode_impl!(Position, self, tgt, version, {
         || self.y > 2047
         || self.y < -2048
         || self.z > 33554431
-        || self.z < -33554431
+        || self.z < -33554432
     {
         Err(Error::Malformed)
     } else {
-        let num = if version >= ProtocolVersion::V1_14_4 {
-            ((self.x as u64) << 38) | ((self.z as u64) << 12) | (self.y as u64)
+        // Negative coordinates would otherwise sign-extend into the other fields
+        let (x, y, z) = (
+            self.x as u64 & 0x3FFFFFF,
+            self.y as u64 & 0xFFF,
+            self.z as u64 & 0x3FFFFFF,
+        );
+        let num = if version.is_at_least(ProtocolVersion::V1_14_4) {
+            (x << 38) | (z << 12) | y
         } else {
-            ((self.x as u64) << 38) | ((self.y as u64) << 26) | (self.z as u64)
+            (x << 38) | (y << 26) | z
         };
         tgt.write_u64(num).await.map_err(handle_io_err)
     }
 });
 
+// Before 1.9, entity coordinates were sent as integers with a fixed number of
+// fractional bits rather than as doubles. These keep the scaling in one place.
+macro_rules! fixed_point_impl {
+    ($name:ident, $bits:literal) => {
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub struct $name(pub i32);
+
+        impl $name {
+            const SCALE: f64 = (1 << $bits) as f64;
+
+            pub fn to_f64(self) -> f64 {
+                self.0 as f64 / Self::SCALE
+            }
+
+            // Rounds down like the vanilla server
+            pub fn from_f64(value: f64) -> $name {
+                $name((value * Self::SCALE).floor() as i32)
+            }
+        }
+
+        decode_impl!($name, src, version, {
+            i32::decode(src, version).await.map($name)
+        });
+
+        encode_impl!($name, self, tgt, version, {
+            self.0.encode(tgt, version).await
+        });
+    };
+}
+
+// Positions, in 1/32 blocks
+fixed_point_impl!(FixedPoint5, 5);
+// Velocities, in 1/8 blocks
+fixed_point_impl!(FixedPoint3, 3);
+
 mod tests {
     use tokio::test;
 
     #[cfg(test)]
     use {
-        super::{super::version::ProtocolVersion, Decode, Encode, VarInt, VarLong},
-        std::io::Cursor,
+        super::{
+            super::{error::Error, version::ProtocolVersion},
+            downgrade_hex_color, varint_to_bytes, Chat, ChatHoverEvent, ChatObject, ChatValue,
+            Color, Decode, Encode, FixedPoint3, FixedPoint5, LengthCappedString, Position, VarInt,
+            VarLong, UUID,
+        },
+        std::{convert::TryFrom, io::Cursor},
     };
 
+    #[test]
+    async fn varint_to_bytes_matches_encode() {
+        for value in [
+            0,
+            127,
+            128,
+            16383,
+            16384,
+            2097151,
+            2097152,
+            268435455,
+            268435456,
+            -1,
+            i32::MIN,
+        ] {
+            let (buf, len) = varint_to_bytes(value);
+            let mut encoded = Vec::new();
+            VarInt(value)
+                .encode(&mut encoded, ProtocolVersion::V1_8_9)
+                .await
+                .unwrap();
+            assert_eq!(&buf[..len], &encoded[..]);
+            assert_eq!(len, VarInt(value).len());
+        }
+    }
+
     #[test]
     async fn varint() {
         let test_cases: [(i32, &[u8]); 10] = [
@@ -1138,6 +1477,551 @@ mod tests {
                 .await
                 .unwrap();
             assert_eq!(&out, bytes);
+            assert_eq!(VarLong(value).len(), bytes.len());
+        }
+    }
+
+    #[test]
+    async fn uuid_v4() {
+        let uuid = UUID::new_v4();
+        let hyphenated = uuid.to_string();
+        assert_eq!(&hyphenated[14..15], "4");
+        assert!("89ab".contains(&hyphenated[19..20]));
+        assert_ne!(uuid, UUID::new_v4());
+    }
+
+    #[test]
+    async fn uuid_order() {
+        let mut uuids: Vec<UUID> = [
+            "ffffffff-0000-0000-0000-000000000000",
+            "00000000-0000-0000-0000-000000000001",
+            "7fffffff-ffff-ffff-ffff-ffffffffffff",
+            "00000000-0000-0000-ffff-ffffffffffff",
+        ]
+        .iter()
+        .map(|uuid| uuid.parse().unwrap())
+        .collect();
+        uuids.sort();
+        assert_eq!(
+            uuids.iter().map(UUID::to_string).collect::<Vec<_>>(),
+            [
+                "00000000-0000-0000-0000-000000000001",
+                "00000000-0000-0000-ffff-ffffffffffff",
+                "7fffffff-ffff-ffff-ffff-ffffffffffff",
+                "ffffffff-0000-0000-0000-000000000000",
+            ]
+        );
+    }
+
+    #[test]
+    async fn position_chunk_coords() {
+        let pos = Position {
+            x: 33,
+            y: 70,
+            z: -1,
+        };
+        assert_eq!((pos.chunk_x(), pos.section_y(), pos.chunk_z()), (2, 4, -1));
+        assert_eq!(
+            (
+                pos.chunk_local_x(),
+                pos.chunk_local_y(),
+                pos.chunk_local_z()
+            ),
+            (1, 6, 15)
+        );
+        let pos = Position {
+            x: -16,
+            y: -1,
+            z: -17,
+        };
+        assert_eq!(
+            (pos.chunk_x(), pos.section_y(), pos.chunk_z()),
+            (-1, -1, -2)
+        );
+        assert_eq!(
+            (
+                pos.chunk_local_x(),
+                pos.chunk_local_y(),
+                pos.chunk_local_z()
+            ),
+            (0, 15, 15)
+        );
+    }
+
+    #[test]
+    async fn fixed_point() {
+        assert_eq!(FixedPoint5(-48).to_f64(), -1.5);
+        assert_eq!(FixedPoint5::from_f64(100.03125), FixedPoint5(3201));
+        // Towards negative infinity, not zero
+        assert_eq!(FixedPoint5::from_f64(-0.01), FixedPoint5(-1));
+        assert_eq!(FixedPoint3::from_f64(2.25), FixedPoint3(18));
+        assert_eq!(FixedPoint3(18).to_f64(), 2.25);
+
+        let mut out = Vec::new();
+        FixedPoint5(-48)
+            .encode(&mut out, ProtocolVersion::V1_8_9)
+            .await
+            .unwrap();
+        assert_eq!(out, (-48i32).to_be_bytes());
+        assert_eq!(
+            FixedPoint5::decode(&mut Cursor::new(out), ProtocolVersion::V1_8_9)
+                .await
+                .unwrap(),
+            FixedPoint5(-48)
+        );
+    }
+
+    #[test]
+    async fn position_bounds() {
+        let extremes = [
+            Position {
+                x: 0,
+                y: 0,
+                z: -33554432,
+            },
+            Position {
+                x: -33554432,
+                y: 0,
+                z: 0,
+            },
+            Position {
+                x: 0,
+                y: 0,
+                z: 33554431,
+            },
+            Position {
+                x: 0,
+                y: -2048,
+                z: 0,
+            },
+        ];
+        for version in [ProtocolVersion::V1_8_9, ProtocolVersion::V1_16] {
+            for pos in extremes {
+                let mut out = Vec::new();
+                assert!(pos.encode(&mut out, version).await.is_ok());
+                assert_eq!(
+                    Position::decode(&mut Cursor::new(out), version)
+                        .await
+                        .unwrap(),
+                    pos
+                );
+            }
+        }
+        let mut out = Vec::new();
+        assert!(Position {
+            x: 0,
+            y: 0,
+            z: -33554433
         }
+        .encode(&mut out, ProtocolVersion::V1_16)
+        .await
+        .is_err());
+    }
+
+    #[test]
+    async fn position_layout() {
+        let pos = Position {
+            x: 18357644,
+            y: 831,
+            z: -20882616,
+        };
+        // x, y, z before 1.14.4 and x, z, y after, at 26, 12 and 26 bits
+        for (version, expected) in [
+            (ProtocolVersion::V1_8_9, 0x4607630cfec15b48u64),
+            (ProtocolVersion::V1_14_4, 0x4607632c15b4833fu64),
+        ] {
+            let mut out = Vec::new();
+            pos.encode(&mut out, version).await.unwrap();
+            assert_eq!(out, expected.to_be_bytes());
+            assert_eq!(
+                Position::decode(&mut Cursor::new(out), version)
+                    .await
+                    .unwrap(),
+                pos
+            );
+        }
+    }
+
+    #[test]
+    async fn chat_plain_text() {
+        let chat = Chat::Object(ChatObject::text("§cYou are ").extra(vec![
+            Chat::Raw("§lbanned".into()),
+            Chat::Array(vec![Chat::Raw("!".into()), Chat::Raw(" §7(30d)".into())]),
+        ]));
+        assert_eq!(chat.to_plain_text(), "You are banned! (30d)");
+
+        let chat = Chat::Object(ChatObject {
+            value: ChatValue::Translate {
+                translate: "%2$s kicked %1$s: %s at 100%%".into(),
+                with: vec![Chat::Raw("Steve".into()), Chat::Raw("Alex".into())],
+            },
+            ..Default::default()
+        });
+        assert_eq!(chat.to_plain_text(), "Alex kicked Steve: Steve at 100%");
+    }
+
+    #[test]
+    async fn string_char_limit() {
+        let version = ProtocolVersion::V1_16;
+        let exact = "é".repeat(16);
+        let over = "é".repeat(17);
+        assert!(LengthCappedString::<16>::try_from(exact.as_str()).is_ok());
+        assert!(LengthCappedString::<16>::try_from(over.as_str()).is_err());
+        // Surrogate pairs count twice, as in Java
+        assert!(LengthCappedString::<16>::try_from("😀".repeat(9)).is_err());
+
+        let mut buf = Vec::new();
+        LengthCappedString::<16>(exact.as_str().into())
+            .encode(&mut buf, version)
+            .await
+            .unwrap();
+        assert_eq!(
+            LengthCappedString::<16>::decode(&mut Cursor::new(&buf), version)
+                .await
+                .unwrap()
+                .0,
+            exact
+        );
+        assert!(LengthCappedString::<16>(over.as_str().into())
+            .encode(&mut Vec::new(), version)
+            .await
+            .is_err());
+
+        let mut buf = Vec::new();
+        LengthCappedString::<17>(over.as_str().into())
+            .encode(&mut buf, version)
+            .await
+            .unwrap();
+        assert!(
+            LengthCappedString::<16>::decode(&mut Cursor::new(&buf), version)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    async fn chat_invalid_json() {
+        let mut buf = Vec::new();
+        LengthCappedString::<262144>("invalid json".into())
+            .encode(&mut buf, ProtocolVersion::V1_16)
+            .await
+            .unwrap();
+        let err = Chat::decode(&mut Cursor::new(buf), ProtocolVersion::V1_16)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("expected value"),
+            "unexpected error {}",
+            err
+        );
+    }
+
+    #[test]
+    async fn chat_component_limit() {
+        let translate = Chat::Object(ChatObject {
+            value: ChatValue::Translate {
+                translate: "chat.type.text".into(),
+                with: vec![Chat::Raw("a".into()), Chat::Raw("b".into())],
+            },
+            ..ChatObject::text("").extra(vec![Chat::Raw("c".into())])
+        });
+        assert_eq!(translate.count_components(), 4);
+
+        for (len, ok) in [(998, true), (1000, false)] {
+            let chat = Chat::Array(vec![Chat::Raw("a".into()); len]);
+            assert_eq!(chat.count_components(), len + 1);
+            let mut buf = Vec::new();
+            LengthCappedString::<262144>(serde_json::to_string(&chat).unwrap().into())
+                .encode(&mut buf, ProtocolVersion::V1_16)
+                .await
+                .unwrap();
+            match Chat::decode(&mut Cursor::new(buf), ProtocolVersion::V1_16).await {
+                Ok(decoded) if ok => assert_eq!(decoded, chat),
+                Err(Error::Malformed) if !ok => {}
+                res => panic!("unexpected result for {} components: {:?}", len + 1, res),
+            }
+        }
+    }
+
+    #[test]
+    async fn nearest_named_color() {
+        assert_eq!(Color::nearest_named(0, 170, 170), Color::DarkAqua);
+        assert_eq!(Color::nearest_named(255, 255, 255), Color::White);
+        assert_eq!(Color::nearest_named(80, 80, 240), Color::Blue);
+    }
+
+    #[test]
+    async fn hex_color_downgrade() {
+        for (hex, named) in [
+            (0x000000, Color::Black),
+            (0xFFAA00, Color::Gold),
+            (0xFF4040, Color::Red),
+            (0xFF0000, Color::DarkRed),
+            (0x101010, Color::Black),
+            (0x9A9AA8, Color::Gray),
+            (0xFFFFF0, Color::White),
+        ] {
+            assert_eq!(
+                downgrade_hex_color(Color::Hex(hex), ProtocolVersion::V1_8_9),
+                named
+            );
+        }
+        assert_eq!(
+            downgrade_hex_color(Color::Hex(0xFF0000), ProtocolVersion::V1_16),
+            Color::Hex(0xFF0000)
+        );
+        assert_eq!(
+            downgrade_hex_color(Color::Reset, ProtocolVersion::V1_8_9),
+            Color::Reset
+        );
+    }
+
+    #[test]
+    async fn chat_across_1_16_versions() {
+        // Only packet IDs changed in 1.16.2, so chat passes between the two
+        // unchanged, hex colors included
+        let chat = Chat::Object(
+            ChatObject::text("hi")
+                .color(Color::Hex(0x55AAFF))
+                .extra(vec![Chat::Raw("!".into())]),
+        );
+        let mut buf = Vec::new();
+        chat.clone()
+            .encode(&mut buf, ProtocolVersion::V1_16)
+            .await
+            .unwrap();
+        assert_eq!(
+            Chat::decode(&mut Cursor::new(buf), ProtocolVersion::V1_16_4)
+                .await
+                .unwrap(),
+            chat
+        );
+    }
+
+    #[test]
+    async fn chat_eq() {
+        let borrowed = "hello".to_string();
+        assert_eq!(
+            Chat::Raw(borrowed.as_str().into()),
+            Chat::Raw(String::from("hello").into())
+        );
+        assert_ne!(Chat::Raw("hello".into()), Chat::Raw("hello!".into()));
+        assert_ne!(
+            Chat::Raw("hello".into()),
+            Chat::Array(vec![Chat::Raw("hello".into())])
+        );
+
+        let object = ChatObject::text("hi")
+            .color(Color::Red)
+            .extra(vec![Chat::Raw("!".into())]);
+        assert_eq!(Chat::Object(object.clone()), Chat::Object(object.clone()));
+        assert_ne!(object.clone(), object.clone().color(Color::Blue));
+        assert_ne!(object.clone(), object.clone().bold(false));
+        assert_ne!(object.clone(), object.extra(vec![Chat::Raw("?".into())]));
+    }
+
+    #[test]
+    async fn hover_events() {
+        let item = ChatHoverEvent::ShowItem(
+            r#"{id:"minecraft:bow",Count:2b,tag:{display:{Name:'{"text":"Bow"}'}}}"#.into(),
+        )
+        .item()
+        .unwrap()
+        .unwrap();
+        assert_eq!(item.id.0, "minecraft:bow");
+        assert_eq!(item.count, 2);
+        assert_eq!(item.display_name(), Some(r#"{"text":"Bow"}"#));
+        // Rather than wrapping around to 1
+        assert!(
+            ChatHoverEvent::ShowItem(r#"{id:"minecraft:bow",Count:257}"#.into())
+                .item()
+                .unwrap()
+                .is_err()
+        );
+
+        let entity = ChatHoverEvent::ShowEntity(
+            r#"{type:"minecraft:player",id:"069a79f4-44e9-4726-a5be-fca90e38aaf5",name:"{\"text\":\"Notch\"}"}"#
+                .into(),
+        )
+        .entity()
+        .unwrap()
+        .unwrap();
+        assert_eq!(entity.kind.0, "minecraft:player");
+        assert_eq!(entity.name.unwrap().to_plain_text(), "Notch");
+
+        assert!(
+            ChatHoverEvent::ShowAchievement("achievement.openInventory".into())
+                .item()
+                .is_none()
+        );
+    }
+}
+
+// Kept apart from the tests above, whose #[test] is tokio's
+#[cfg(test)]
+mod proptests {
+    use super::{super::version::ProtocolVersion, Decode, Encode, Error, Position, VarLong, UUID};
+    use futures::executor::block_on;
+    use proptest::prelude::*;
+    use std::io::Cursor;
+
+    const COORD_MIN: i32 = -33554432;
+    const COORD_MAX: i32 = 33554431;
+    const Y_MIN: i32 = -2048;
+    const Y_MAX: i32 = 2047;
+
+    // Plain ranges rarely hit the extremes, which are the interesting part
+    fn coordinate(min: i32, max: i32) -> impl Strategy<Value = i32> {
+        prop_oneof![Just(min), Just(max), Just(0), Just(-1), min..=max]
+    }
+
+    proptest! {
+        #[test]
+        fn uuid_round_trip(bytes in any::<[u8; 16]>()) {
+            let uuid = UUID(bytes);
+            let hyphenated = uuid.to_string();
+            prop_assert_eq!(UUID::from_ascii_bytes_hyphenated(&hyphenated), Ok(uuid));
+            let plain = hyphenated.replace('-', "");
+            prop_assert_eq!(UUID::from_ascii_bytes(&plain), Ok(uuid));
+            prop_assert_eq!(plain.parse::<UUID>(), hyphenated.parse::<UUID>());
+        }
+
+        #[test]
+        fn uuid_rejects_non_hex(
+            bytes in any::<[u8; 16]>(),
+            index in 0..36usize,
+            invalid in "[g-zG-Z!#_ ]",
+        ) {
+            let mut hyphenated = UUID(bytes).to_string();
+            // Hyphens in the wrong place are covered by uuid_misplaced_hyphens
+            prop_assume!(![8, 13, 18, 23].contains(&index));
+            hyphenated.replace_range(index..index + 1, &invalid);
+            prop_assert_eq!(hyphenated.parse::<UUID>(), Err(Error::Malformed));
+        }
+
+        #[test]
+        fn uuid_misplaced_hyphens(bytes in any::<[u8; 16]>(), index in 0..36usize) {
+            let mut hyphenated = UUID(bytes).to_string();
+            prop_assume!(![8, 13, 18, 23].contains(&index));
+            hyphenated.replace_range(index..index + 1, "-");
+            prop_assert_eq!(hyphenated.parse::<UUID>(), Err(Error::Malformed));
+        }
+
+        #[test]
+        fn uuid_missing_hyphens(bytes in any::<[u8; 16]>(), hyphen in 0..4usize, digit in "[0-9a-f]") {
+            let mut hyphenated = UUID(bytes).to_string();
+            let index = [8, 13, 18, 23][hyphen];
+            hyphenated.replace_range(index..index + 1, &digit);
+            prop_assert_eq!(hyphenated.parse::<UUID>(), Err(Error::Malformed));
+        }
+
+        #[test]
+        fn varlong_round_trip(value in any::<i64>()) {
+            let mut out = Vec::new();
+            block_on(VarLong(value).encode(&mut out, ProtocolVersion::V1_8_9)).unwrap();
+            prop_assert_eq!(out.len(), VarLong(value).len());
+            let decoded = block_on(VarLong::decode(&mut Cursor::new(out), ProtocolVersion::V1_8_9));
+            prop_assert_eq!(decoded.map(|varlong| varlong.0), Ok(value));
+        }
+
+        #[test]
+        fn position_round_trip(
+            x in coordinate(COORD_MIN, COORD_MAX),
+            y in coordinate(Y_MIN, Y_MAX),
+            z in coordinate(COORD_MIN, COORD_MAX),
+        ) {
+            let pos = Position { x, y, z };
+            for version in [
+                ProtocolVersion::V1_8_9,
+                ProtocolVersion::V1_12,
+                ProtocolVersion::V1_14_4,
+                ProtocolVersion::V1_16,
+            ] {
+                let mut out = Vec::new();
+                block_on(pos.encode(&mut out, version)).unwrap();
+                prop_assert_eq!(block_on(Position::decode(&mut Cursor::new(out), version)), Ok(pos));
+            }
+        }
+    }
+}
+
+// cargo bench --lib varlong
+#[cfg(test)]
+mod benches {
+    extern crate test;
+
+    use super::{super::version::ProtocolVersion, Encode, VarLong};
+    use futures::executor::block_on;
+    use test::{black_box, Bencher};
+
+    // One of each length, and a negative one
+    const VALUES: [i64; 11] = [
+        0,
+        1 << 7,
+        1 << 14,
+        1 << 21,
+        1 << 28,
+        1 << 35,
+        1 << 42,
+        1 << 49,
+        1 << 56,
+        1 << 63,
+        -1,
+    ];
+
+    // VarLong::len as it was before it worked the length out from the bits
+    const fn len_by_range(value: i64) -> usize {
+        match value {
+            0..=127 => 1,
+            128..=16383 => 2,
+            16384..=2097151 => 3,
+            2097152..=268435455 => 4,
+            268435456..=34359738367 => 5,
+            34359738368..=4398046511103 => 6,
+            4398046511104..=562949953421311 => 7,
+            562949953421312..=72057594037927935 => 8,
+            72057594037927936.. => 9,
+            _ => 10,
+        }
+    }
+
+    #[test]
+    fn len_matches_ranges() {
+        for value in VALUES {
+            for value in [value.wrapping_sub(1), value, value.wrapping_add(1)] {
+                assert_eq!(VarLong(value).len(), len_by_range(value), "{}", value);
+            }
+        }
+    }
+
+    #[bench]
+    fn varlong_len(b: &mut Bencher) {
+        b.iter(|| {
+            black_box(VALUES)
+                .iter()
+                .map(|&value| VarLong(value).len())
+                .sum::<usize>()
+        });
+    }
+
+    #[bench]
+    fn varlong_len_by_range(b: &mut Bencher) {
+        b.iter(|| {
+            black_box(VALUES)
+                .iter()
+                .map(|&value| len_by_range(value))
+                .sum::<usize>()
+        });
+    }
+
+    #[bench]
+    fn varlong_encode(b: &mut Bencher) {
+        let mut out = Vec::with_capacity(VALUES.len() * VarLong::MAX_ENCODED_LEN);
+        b.iter(|| {
+            out.clear();
+            for value in black_box(VALUES) {
+                block_on(VarLong(value).encode(&mut out, ProtocolVersion::V1_8_9)).unwrap();
+            }
+        });
     }
 }
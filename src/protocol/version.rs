@@ -1,5 +1,10 @@
 use super::error::Error;
-use std::convert::TryFrom;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    str::FromStr,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ProtocolVersion {
@@ -7,6 +12,67 @@ pub enum ProtocolVersion {
     V1_12 = 335,
     V1_14_4 = 498,
     V1_16 = 735,
+    // 1.16.2 moved the multi block change packet, shifting the IDs after it
+    V1_16_4 = 754,
+}
+
+impl ProtocolVersion {
+    // Clients newer than this are treated as it while pinging, since the
+    // status packets haven't changed
+    pub const LATEST: ProtocolVersion = ProtocolVersion::V1_16_4;
+
+    pub fn is_at_least(&self, other: ProtocolVersion) -> bool {
+        *self >= other
+    }
+
+    pub fn is_before(&self, other: ProtocolVersion) -> bool {
+        *self < other
+    }
+
+    // Both have been around since 1.7, before any supported version
+    pub fn supports_encryption(&self) -> bool {
+        true
+    }
+
+    pub fn supports_compression(&self) -> bool {
+        true
+    }
+
+    // Earlier versions send it as a hyphenated string instead
+    pub fn has_uuid_in_login_success(&self) -> bool {
+        self.is_at_least(ProtocolVersion::V1_16)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProtocolVersion::V1_8_9 => "1.8.9",
+            ProtocolVersion::V1_12 => "1.12",
+            ProtocolVersion::V1_14_4 => "1.14.4",
+            ProtocolVersion::V1_16 => "1.16",
+            ProtocolVersion::V1_16_4 => "1.16.4",
+        }
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ProtocolVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "1.8.9" => ProtocolVersion::V1_8_9,
+            "1.12" => ProtocolVersion::V1_12,
+            "1.14.4" => ProtocolVersion::V1_14_4,
+            "1.16" => ProtocolVersion::V1_16,
+            "1.16.4" => ProtocolVersion::V1_16_4,
+            _ => return Err(Error::Malformed),
+        })
+    }
 }
 
 impl TryFrom<i32> for ProtocolVersion {
@@ -17,8 +83,80 @@ impl TryFrom<i32> for ProtocolVersion {
             0..=334 => ProtocolVersion::V1_8_9,
             335..=497 => ProtocolVersion::V1_12,
             498..=734 => ProtocolVersion::V1_14_4,
-            735.. => ProtocolVersion::V1_16,
+            735..=750 => ProtocolVersion::V1_16,
+            751..=754 => ProtocolVersion::V1_16_4,
+            // 1.17 and later, including 1.19+ with signed chat, which the
+            // proxy can't relay
             _ => return Err(Error::Malformed),
         })
     }
 }
+
+impl Serialize for ProtocolVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+struct ProtocolVersionVisitor;
+
+impl<'de> de::Visitor<'de> for ProtocolVersionVisitor {
+    type Value = ProtocolVersion;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a protocol number or a version name like \"1.16\"")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        i32::try_from(value)
+            .map_err(E::custom)
+            .and_then(|value| ProtocolVersion::try_from(value).map_err(E::custom))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        self.visit_i64(i64::try_from(value).map_err(E::custom)?)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value
+            .parse()
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+// Either the protocol number, as in the handshake, or the version name
+impl<'de> Deserialize<'de> for ProtocolVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ProtocolVersionVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProtocolVersion;
+
+    #[test]
+    fn serde() {
+        for (json, version) in [
+            ("47", ProtocolVersion::V1_8_9),
+            ("340", ProtocolVersion::V1_12),
+            ("\"1.14.4\"", ProtocolVersion::V1_14_4),
+            ("\"1.16\"", ProtocolVersion::V1_16),
+            ("736", ProtocolVersion::V1_16),
+            ("751", ProtocolVersion::V1_16_4),
+            ("\"1.16.4\"", ProtocolVersion::V1_16_4),
+        ] {
+            assert_eq!(
+                serde_json::from_str::<ProtocolVersion>(json).unwrap(),
+                version
+            );
+        }
+        for json in ["-1", "755", "759", "\"1.17\"", "true"] {
+            assert!(serde_json::from_str::<ProtocolVersion>(json).is_err());
+        }
+        assert_eq!(
+            serde_json::to_string(&ProtocolVersion::V1_8_9).unwrap(),
+            "\"1.8.9\""
+        );
+    }
+}
@@ -0,0 +1,797 @@
+use super::{
+    decode_chat_message, Direction, PacketAction, PacketTransform, SessionContext, TransformError,
+    SERVERBOUND_CHAT_ID,
+};
+use crate::{
+    connection::codec::OwnedPacket,
+    lookup_player,
+    persistence::StatsSnapshot,
+    protocol::types::{Chat, ChatObject, Color, Encode, LengthCappedString, UUID},
+    reload_config, reload_message, split_message, stats_chat, throttled_message,
+    web::{error::Error as WebError, hypixel::Rank},
+    MAX_CHAT_MESSAGE_LEN,
+};
+use futures::future::{join_all, BoxFuture};
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::HashSet,
+    sync::{atomic::Ordering, Mutex},
+    time::Instant,
+};
+
+// Each online status is a request of its own, so /friends only checks the
+// most recent friends
+const MAX_FRIEND_STATUS_CHECKS: usize = 10;
+// Shown by /friends
+const MAX_ONLINE_FRIENDS: usize = 5;
+// Shown by /achievements when the player has made progress on them
+const NOTABLE_ACHIEVEMENTS: [(&str, &str); 5] = [
+    ("bedwars_level", "Bed Wars level"),
+    ("bedwars_wins", "Bed Wars wins"),
+    ("bedwars_bedwars_killer", "Bed Wars kills"),
+    ("bedwars_beds", "Beds broken"),
+    ("general_wins", "Total wins"),
+];
+
+// Players in the tab list resolve without asking Mojang, which also works for
+// nicked players
+fn players_by_name<'a>(
+    unames: impl Iterator<Item = &'a str>,
+    ctx: &SessionContext<'_>,
+) -> Vec<(Option<UUID>, Cow<'a, str>)> {
+    let all_local_players = ctx.all_local_players.lock().unwrap();
+    unames
+        .map(|uname| {
+            (
+                all_local_players.get_uuid_by_name(uname),
+                Cow::Borrowed(uname),
+            )
+        })
+        .collect()
+}
+
+// Everyone in the tab list but spectators
+fn all_players<'a>(ctx: &SessionContext<'_>) -> Vec<(Option<UUID>, Cow<'a, str>)> {
+    ctx.all_local_players
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|&(uuid, _)| !ctx.is_spectator(uuid))
+        .map(|(uuid, name)| (Some(uuid), Cow::Owned(name.into())))
+        .collect()
+}
+
+// Marks a name as being looked up until dropped, so the same player isn't
+// looked up and shown twice at once. Commands run concurrently, so this
+// covers /stats Player typed again before the first reply as well as
+// /stats Player player.
+struct PendingLookup<'a> {
+    pending: &'a Mutex<HashSet<String>>,
+    name: String,
+}
+
+impl<'a> PendingLookup<'a> {
+    // None if the name is already being looked up
+    fn start(pending: &'a Mutex<HashSet<String>>, name: &str) -> Option<PendingLookup<'a>> {
+        let name = name.to_ascii_lowercase();
+        if pending.lock().unwrap().insert(name.clone()) {
+            Some(PendingLookup { pending, name })
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for PendingLookup<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.name);
+    }
+}
+
+// /stats <players...> shows their stats, and /stats * shows everyone's in
+// the lobby and reports the notable ones to party chat
+pub struct StatsCommand;
+
+impl PacketTransform for StatsCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let unames = match msg.strip_prefix("/stats ") {
+                Some(unames) => unames,
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let settings = ctx.settings();
+            let hypixel = ctx.hypixel(&settings);
+            let players = if unames == "*" {
+                all_players(ctx)
+            } else {
+                players_by_name(unames.split(' '), ctx)
+            };
+            let known_uuids = players
+                .iter()
+                .filter_map(|player| player.0)
+                .collect::<Vec<_>>();
+            // Marked in the output of /stats *, since that's the one run on
+            // whole lobbies. Not worth failing the lookup over.
+            let friends = if unames == "*" {
+                hypixel
+                    .friends(ctx.uuid)
+                    .await
+                    .map(|records| {
+                        records
+                            .iter()
+                            .map(|record| record.friend_of(ctx.uuid))
+                            .collect::<HashSet<_>>()
+                    })
+                    .unwrap_or_default()
+            } else {
+                HashSet::new()
+            };
+            let mut known_infos = hypixel.info_bulk(&known_uuids).await.into_iter();
+            let throttled = Mutex::new(None);
+            let good_players = join_all(players.into_iter().map(|player| {
+                let hypixel = &hypixel;
+                let settings = &settings;
+                let throttled = &throttled;
+                let friends = &friends;
+                let known_info = player.0.map(|_| known_infos.next().unwrap());
+                let mut uuid = player.0;
+                let mut player = player.1.into_owned();
+                async move {
+                    let _pending = match PendingLookup::start(ctx.pending_stats, &player) {
+                        Some(pending) => pending,
+                        None => return None,
+                    };
+                    let mut out = Vec::<Cow<'_, str>>::new();
+                    let uuid_lookup = uuid.is_none();
+                    if uuid_lookup {
+                        uuid = ctx.mojang.get_uuid(&player).await.ok().map(|(uuid, name)| {
+                            player = name;
+                            uuid
+                        });
+                    }
+                    let mut player_info = None;
+                    let mut nicked = true;
+                    if let Some(uuid) = uuid {
+                        let info = match known_info {
+                            Some(info) => info,
+                            None => hypixel.info(uuid).await,
+                        };
+                        let info = match info {
+                            Err(WebError::Throttled(delay)) => {
+                                *throttled.lock().unwrap() = Some(delay);
+                                // No way to tell
+                                nicked = false;
+                                None
+                            }
+                            Err(err) => {
+                                ctx.reply(Chat::Raw(
+                                    format!("§cFailed to get the stats of {}: {}", player, err)
+                                        .into(),
+                                ));
+                                return None;
+                            }
+                            Ok(info) => info,
+                        };
+                        if let Some(info) = info {
+                            nicked = false;
+                            if let (Some(db), Some(snapshot)) =
+                                (ctx.stats_db.cloned(), StatsSnapshot::from_info(&info))
+                            {
+                                let conn_id = ctx.conn_id;
+                                // Not worth holding up the reply for
+                                tokio::spawn(async move {
+                                    if let Err(err) = db.record(uuid, &snapshot).await {
+                                        println!("[{}] Failed to record stats: {}", conn_id, err);
+                                    }
+                                });
+                            }
+                            if let Some(bw_stats) = &info.stats.bedwars {
+                                let fkdr = bw_stats.fkdr();
+                                if fkdr > settings.fkdr_threshold {
+                                    out.push(format!("has {:.2} FKDR", fkdr).into());
+                                }
+                            }
+                            player_info = Some(info);
+                        } else if uuid_lookup || ctx.mojang.get_uuid(&player).await.is_ok() {
+                            nicked = false;
+                        }
+                    }
+                    if let Some(uuid) = uuid {
+                        if player_info.is_some() || nicked {
+                            ctx.session_stats
+                                .lock()
+                                .unwrap()
+                                .insert(uuid, player_info.clone());
+                        }
+                    }
+                    if nicked {
+                        out.push("is nicked".into());
+                    }
+                    if settings.is_blocked(&player) {
+                        out.push("is blocked".into());
+                    }
+                    let out = if out.is_empty() {
+                        None
+                    } else {
+                        Some(format!("{} {}", player, out.join(", ")))
+                    };
+                    let chat = if let Some(ref player_info) = player_info {
+                        stats_chat(player_info, settings)
+                    } else {
+                        Chat::Object(
+                            ChatObject::text(if nicked { "[NICKED] " } else { "" })
+                                .color(if nicked { Color::DarkRed } else { Color::Gray })
+                                .extra(vec![
+                                    Chat::Raw(player.into()),
+                                    Chat::Raw("§r: Unknown".into()),
+                                ]),
+                        )
+                    };
+                    ctx.reply(match uuid {
+                        Some(uuid) if friends.contains(&uuid) => Chat::Array(vec![
+                            Chat::Object(ChatObject::text("\u{2665} ").color(Color::LightPurple)),
+                            chat,
+                        ]),
+                        _ => chat,
+                    });
+                    out
+                }
+            }))
+            .await;
+            if let Some(delay) = *throttled.lock().unwrap() {
+                ctx.reply(throttled_message(delay));
+            }
+            if unames != "*" {
+                return Ok(PacketAction::Discard);
+            }
+            let mut party_messages = Vec::new();
+            for good_player in good_players {
+                if let Some(msg) = good_player {
+                    for chunk in split_message(&msg, MAX_CHAT_MESSAGE_LEN - "/pc ".len()) {
+                        let mut content = Vec::new();
+                        LengthCappedString::<MAX_CHAT_MESSAGE_LEN>(format!("/pc {}", chunk).into())
+                            .encode(&mut content, ctx.version)
+                            .await?;
+                        party_messages.push(OwnedPacket::new(SERVERBOUND_CHAT_ID, content));
+                    }
+                }
+            }
+            Ok(PacketAction::Inject(party_messages))
+        })
+    }
+}
+
+// /gstats * lists the lobby with anyone in the player's own guild highlighted,
+// since guildmates queueing together are often teaming
+pub struct GuildStatsCommand;
+
+impl PacketTransform for GuildStatsCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            if msg != "/gstats *" {
+                return Ok(PacketAction::Pass(packet));
+            }
+            let hypixel = ctx.hypixel(&ctx.settings());
+            // Cached along with its member list, so running this again in
+            // the next lobby doesn't ask Hypixel again
+            let guild = match hypixel.guild(ctx.uuid).await {
+                Ok(Some(guild)) => guild,
+                Ok(None) => {
+                    ctx.reply(Chat::Raw("§cYou aren't in a guild".into()));
+                    return Ok(PacketAction::Discard);
+                }
+                Err(WebError::Throttled(delay)) => {
+                    ctx.reply(throttled_message(delay));
+                    return Ok(PacketAction::Discard);
+                }
+                Err(err) => {
+                    ctx.reply(Chat::Raw(
+                        format!("§cFailed to get your guild: {}", err).into(),
+                    ));
+                    return Ok(PacketAction::Discard);
+                }
+            };
+            let (mut guildmates, others): (Vec<_>, Vec<_>) = all_players(ctx)
+                .into_iter()
+                .filter_map(|(uuid, name)| Some((uuid?, name)))
+                .filter(|&(uuid, _)| uuid != ctx.uuid)
+                .partition(|&(uuid, _)| guild.has_member(uuid));
+            guildmates.sort_by(|a, b| a.1.cmp(&b.1));
+            let mut names = guildmates
+                .iter()
+                .map(|(_, name)| ChatObject::text(name.to_string()).color(Color::DarkGreen))
+                .chain(
+                    others
+                        .iter()
+                        .map(|(_, name)| ChatObject::text(name.to_string()).color(Color::Gray)),
+                )
+                .flat_map(|name| vec![Chat::Raw(", ".into()), Chat::Object(name)])
+                .skip(1)
+                .collect::<Vec<_>>();
+            names.insert(
+                0,
+                Chat::Raw(
+                    format!(
+                        "§a{} of {} players in this lobby are in {}:\n",
+                        guildmates.len(),
+                        guildmates.len() + others.len(),
+                        guild.name
+                    )
+                    .into(),
+                ),
+            );
+            ctx.reply(Chat::Array(names));
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /friends <player> shows how many friends the player has and which of their
+// latest ones are online
+pub struct FriendsCommand;
+
+impl PacketTransform for FriendsCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let uname = match msg.strip_prefix("/friends ") {
+                Some(uname) if !uname.is_empty() && !uname.contains(' ') => uname,
+                _ => return Ok(PacketAction::Pass(packet)),
+            };
+            let hypixel = ctx.hypixel(&ctx.settings());
+            let (uuid, name) = match lookup_player(uname, ctx.all_local_players, ctx.mojang).await {
+                Some(player) => player,
+                None => {
+                    ctx.reply(Chat::Raw(format!("§c{} is not a player", uname).into()));
+                    return Ok(PacketAction::Discard);
+                }
+            };
+            let mut friends = match hypixel.friends(uuid).await {
+                Ok(friends) => friends,
+                Err(WebError::Throttled(delay)) => {
+                    ctx.reply(throttled_message(delay));
+                    return Ok(PacketAction::Discard);
+                }
+                Err(err) => {
+                    ctx.reply(Chat::Raw(
+                        format!("§cFailed to get the friends of {}: {}", name, err).into(),
+                    ));
+                    return Ok(PacketAction::Discard);
+                }
+            };
+            if friends.is_empty() {
+                ctx.reply(Chat::Raw(
+                    format!("§7{} has no friends or hides them", name).into(),
+                ));
+                return Ok(PacketAction::Discard);
+            }
+            friends.sort_by_key(|record| Reverse(record.started));
+            let online = join_all(friends.iter().take(MAX_FRIEND_STATUS_CHECKS).map(|record| {
+                let hypixel = &hypixel;
+                let friend = record.friend_of(uuid);
+                async move {
+                    let status = hypixel.online_status(friend).await.ok()??;
+                    let info = hypixel.info(friend).await.ok()??;
+                    Some(format!("\n§a{} §7in {}", info.name, status.game_type))
+                }
+            }))
+            .await;
+            let mut lines = vec![Chat::Raw(
+                format!("§a{} has {} friends", name, friends.len()).into(),
+            )];
+            lines.extend(
+                online
+                    .into_iter()
+                    .flatten()
+                    .take(MAX_ONLINE_FRIENDS)
+                    .map(|line| Chat::Raw(line.into())),
+            );
+            ctx.reply(Chat::Array(lines));
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /achievements <player> shows how many achievements the player has progress
+// on. Bought accounts tend to have plenty of these with poor stats.
+pub struct AchievementsCommand;
+
+impl PacketTransform for AchievementsCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let uname = match msg.strip_prefix("/achievements ") {
+                Some(uname) if !uname.is_empty() && !uname.contains(' ') => uname,
+                _ => return Ok(PacketAction::Pass(packet)),
+            };
+            let hypixel = ctx.hypixel(&ctx.settings());
+            let (uuid, name) = match lookup_player(uname, ctx.all_local_players, ctx.mojang).await {
+                Some(player) => player,
+                None => {
+                    ctx.reply(Chat::Raw(format!("§c{} is not a player", uname).into()));
+                    return Ok(PacketAction::Discard);
+                }
+            };
+            let achievements = match hypixel.achievements(uuid).await {
+                Ok(achievements) => achievements,
+                Err(WebError::Throttled(delay)) => {
+                    ctx.reply(throttled_message(delay));
+                    return Ok(PacketAction::Discard);
+                }
+                Err(err) => {
+                    ctx.reply(Chat::Raw(
+                        format!("§cFailed to get the achievements of {}: {}", name, err).into(),
+                    ));
+                    return Ok(PacketAction::Discard);
+                }
+            };
+            let mut out = format!(
+                "§a{} has progress on {} achievements",
+                name,
+                achievements.len()
+            );
+            for (key, label) in NOTABLE_ACHIEVEMENTS.iter() {
+                if let Some(progress) = achievements.get(*key) {
+                    out.push_str(&format!("\n§7{}: §f{}", label, progress));
+                }
+            }
+            ctx.reply(Chat::Raw(out.into()));
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /bans shows how many players Watchdog and staff banned in the last day
+pub struct BansCommand;
+
+impl PacketTransform for BansCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            if decode_chat_message(&packet, ctx.version).await? != "/bans" {
+                return Ok(PacketAction::Pass(packet));
+            }
+            let hypixel = ctx.hypixel(&ctx.settings());
+            ctx.reply(match hypixel.punishment_stats().await {
+                Err(WebError::Throttled(delay)) => throttled_message(delay),
+                Err(err) => Chat::Raw(format!("§cFailed to get the ban stats: {}", err).into()),
+                Ok(stats) => {
+                    Chat::Raw(
+                        format!(
+                            "§cWatchdog §7banned §c{} §7players in the last day (about {} an hour, {} in total)\n\
+                             §6Staff §7banned §6{} §7players in the last day ({} in total)",
+                            stats.watchdog_rolling_daily,
+                            stats.watchdog_rolling_daily / 24,
+                            stats.watchdog_total,
+                            stats.staff_rolling_daily,
+                            stats.staff_total
+                        )
+                        .into(),
+                    )
+                }
+            });
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /proxy stats shows how well the Hypixel API cache is doing
+pub struct ProxyStatsCommand;
+
+impl PacketTransform for ProxyStatsCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            if decode_chat_message(&packet, ctx.version).await? != "/proxy stats" {
+                return Ok(PacketAction::Pass(packet));
+            }
+            let stats = ctx.hypixel.cache_stats();
+            ctx.reply(Chat::Raw(
+                match stats.hit_rate() {
+                    Some(hit_rate) => format!(
+                        "§aHypixel API cache hit rate: §e{:.1}% §7({} hits, {} misses)",
+                        hit_rate * 100.0,
+                        stats.cache_hits.load(Ordering::Relaxed),
+                        stats.cache_misses.load(Ordering::Relaxed)
+                    ),
+                    None => "§7Nothing has been looked up from the Hypixel API yet".into(),
+                }
+                .into(),
+            ));
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /reload rereads the settings from the configuration file
+pub struct ReloadCommand;
+
+impl PacketTransform for ReloadCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            if decode_chat_message(&packet, ctx.version).await? != "/reload" {
+                return Ok(PacketAction::Pass(packet));
+            }
+            ctx.reply(reload_message(reload_config(
+                ctx.config_path,
+                ctx.dynamic_config,
+            )));
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /pstats or /party stats asks Hypixel for the party list, and
+// PartyChatStats shows the stats of everyone in the response
+pub struct PartyStatsCommand;
+
+impl PacketTransform for PartyStatsCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            if msg != "/pstats" && msg != "/party stats" {
+                return Ok(PacketAction::Pass(packet));
+            }
+            if !ctx.settings().auto_party_stats {
+                ctx.reply(Chat::Raw(
+                    "§cTurn on auto_party_stats in the settings to use /pstats".into(),
+                ));
+                return Ok(PacketAction::Discard);
+            }
+            *ctx.party_list_requested.lock().unwrap() = Some(Instant::now());
+            let mut content = Vec::new();
+            LengthCappedString::<MAX_CHAT_MESSAGE_LEN>("/party list".into())
+                .encode(&mut content, ctx.version)
+                .await?;
+            Ok(PacketAction::Replace(OwnedPacket::new(
+                SERVERBOUND_CHAT_ID,
+                content,
+            )))
+        })
+    }
+}
+
+// /ping [players...|*] shows the pings from the tab list, or the player's own
+pub struct PingCommand;
+
+impl PacketTransform for PingCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            if msg != "/ping" && !msg.starts_with("/ping ") {
+                return Ok(PacketAction::Pass(packet));
+            }
+            let hypixel = ctx.hypixel(&ctx.settings());
+            let unames = &msg[5..];
+            let players = if unames.is_empty() {
+                vec![(Some(ctx.uuid), Cow::Borrowed(ctx.username))]
+            } else if unames == " *" {
+                all_players(ctx)
+            } else {
+                players_by_name(unames.split(' ').skip(1), ctx)
+            };
+            join_all(players.into_iter().map(|player| {
+                let hypixel = &hypixel;
+                let mut uuid = player.0;
+                let mut player = player.1.into_owned();
+                async move {
+                    let _pending = match PendingLookup::start(ctx.pending_stats, &player) {
+                        Some(pending) => pending,
+                        None => return,
+                    };
+                    if uuid.is_none() {
+                        uuid = ctx.mojang.get_uuid(&player).await.ok().map(|(uuid, name)| {
+                            player = name;
+                            uuid
+                        });
+                    }
+                    let mut ping = None;
+                    let mut player_info = None;
+                    if let Some(uuid) = uuid {
+                        ping = ctx.pings.lock().unwrap().get(&uuid).copied();
+                        player_info = match hypixel.info(uuid).await {
+                            Ok(info) => info,
+                            Err(err) => {
+                                ctx.reply(Chat::Raw(
+                                    format!("§cFailed to get the rank of {}: {}", player, err)
+                                        .into(),
+                                ));
+                                return;
+                            }
+                        };
+                    }
+                    ctx.reply(Chat::Array(vec![
+                        Chat::Raw(match player_info {
+                            Some(ref player_info) if player_info.rank == Rank::Default => {
+                                player_info.name.clone().into()
+                            }
+                            Some(ref player_info) => format!(
+                                "{} {}",
+                                player_info.rank.to_prefix_string(),
+                                player_info.name
+                            )
+                            .into(),
+                            None => format!("§4[NICKED] {}", player).into(),
+                        }),
+                        Chat::Object(ChatObject::text(": ").color(Color::Reset)),
+                        if let Some(ping) = ping {
+                            Chat::Object(ChatObject::text(format!("{}ms", ping)).color(
+                                if ping < 50 {
+                                    Color::DarkGreen
+                                } else if ping < 100 {
+                                    Color::Green
+                                } else if ping < 200 {
+                                    Color::Yellow
+                                } else {
+                                    Color::Red
+                                },
+                            ))
+                        } else {
+                            Chat::Raw("Unknown".into())
+                        },
+                    ]));
+                }
+            }))
+            .await;
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PendingLookup, ReloadCommand, StatsCommand};
+    use crate::transform::{
+        chat, Direction, PacketAction, PacketTransform, SessionContext, TestSession,
+    };
+    use std::{collections::HashSet, env::temp_dir, fs, process, sync::Mutex};
+
+    #[test]
+    fn pending_lookup() {
+        let pending = Mutex::new(HashSet::new());
+        let first = PendingLookup::start(&pending, "Player");
+        assert!(first.is_some());
+        assert!(PendingLookup::start(&pending, "pLAYER").is_none());
+        assert!(PendingLookup::start(&pending, "Other").is_some());
+        drop(first);
+        assert!(PendingLookup::start(&pending, "player").is_some());
+        assert!(pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stats_skips_player_already_being_looked_up() {
+        // As if an earlier /stats player was still waiting on the API
+        let session = TestSession::new();
+        session
+            .pending_stats
+            .lock()
+            .unwrap()
+            .insert("player".to_string());
+        let action = StatsCommand
+            .transform(
+                chat("/stats Player").await,
+                Direction::Serverbound,
+                &session.ctx(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(action, PacketAction::Discard));
+        assert!(session.send_to_client.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        let session = TestSession::new();
+        let action = ReloadCommand
+            .transform(
+                chat("/reload").await,
+                Direction::Serverbound,
+                &session.ctx(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(action, PacketAction::Discard));
+        let reply = session.send_to_client.lock().unwrap().pop_front().unwrap();
+        assert_eq!(
+            reply.to_plain_text(),
+            "Failed to reload configuration: no configuration file"
+        );
+
+        let path = temp_dir().join(format!("mc-proxy-reload-{}.json", process::id()));
+        fs::write(&path, r#"{"upload_bandwidth_kbps": 500}"#).unwrap();
+        let ctx = SessionContext {
+            config_path: Some(&path),
+            ..session.ctx()
+        };
+        ReloadCommand
+            .transform(chat("/reload").await, Direction::Serverbound, &ctx)
+            .await
+            .unwrap();
+        let _ = fs::remove_file(&path);
+        let reply = session.send_to_client.lock().unwrap().pop_front().unwrap();
+        assert_eq!(reply.to_plain_text(), "Reloaded configuration");
+        assert_eq!(ctx.settings().upload_bandwidth_kbps, Some(500));
+    }
+}
@@ -0,0 +1,171 @@
+use super::{
+    decode_chat_message, Direction, PacketAction, PacketTransform, SessionContext, TransformError,
+    SERVERBOUND_CHAT_ID,
+};
+use crate::{
+    connection::codec::OwnedPacket,
+    protocol::types::{Chat, UUID},
+    web::hypixel::PlayerInfo,
+};
+use futures::future::BoxFuture;
+use std::{
+    env::var_os,
+    ffi::OsStr,
+    fs::write,
+    path::{Path, PathBuf},
+};
+
+const CSV_HEADER: &str = "uuid,name,rank,fkdr,wlr,network_level,nicked";
+
+// Quoted only when needed, e.g. for custom ranks with commas in them
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// One row per player, where None means the player was nicked. Stats the
+// player doesn't have are left empty.
+fn stats_csv<'a>(rows: impl Iterator<Item = (UUID, &'a str, Option<&'a PlayerInfo>)>) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for (uuid, name, info) in rows {
+        let fields = match info {
+            Some(info) => {
+                let bedwars = info.stats.bedwars.as_ref();
+                [
+                    uuid.to_string(),
+                    csv_field(&info.name),
+                    csv_field(&info.rank.to_prefix_string()),
+                    bedwars.map_or(String::new(), |stats| format!("{:.2}", stats.fkdr())),
+                    bedwars.map_or(String::new(), |stats| format!("{:.2}", stats.wlr())),
+                    info.network_level().to_string(),
+                    "false".into(),
+                ]
+            }
+            None => [
+                uuid.to_string(),
+                csv_field(name),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                "true".into(),
+            ],
+        };
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn home_dir() -> Option<PathBuf> {
+    var_os("HOME")
+        .or_else(|| var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+// /export <filename> saves the stats /stats loaded this session for everyone
+// still in the tab list, as a CSV file in the export directory from the
+// settings or the home directory
+pub struct ExportCommand;
+
+impl PacketTransform for ExportCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let filename = match msg.strip_prefix("/export ") {
+                Some(filename) => filename.trim(),
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            // Only a name, so the file can't end up outside the directory
+            if Path::new(filename).file_name() != Some(OsStr::new(filename)) {
+                ctx.reply(Chat::Raw(
+                    format!("§c{} is not a valid file name", filename).into(),
+                ));
+                return Ok(PacketAction::Discard);
+            }
+            let dir = match ctx.settings().export_dir.or_else(home_dir) {
+                Some(dir) => dir,
+                None => {
+                    ctx.reply(Chat::Raw(
+                        "§cNo export directory is set and there is no home directory".into(),
+                    ));
+                    return Ok(PacketAction::Discard);
+                }
+            };
+            let mut path = dir.join(filename);
+            if path.extension().is_none() {
+                path.set_extension("csv");
+            }
+            let (csv, count) = {
+                let all_local_players = ctx.all_local_players.lock().unwrap();
+                let session_stats = ctx.session_stats.lock().unwrap();
+                let mut rows = all_local_players
+                    .iter()
+                    .filter_map(|(uuid, name)| {
+                        Some((uuid, name, session_stats.get(&uuid)?.as_ref()))
+                    })
+                    .collect::<Vec<_>>();
+                // Tab list order changes between lookups
+                rows.sort_by_key(|&(_, name, _)| name.to_ascii_lowercase());
+                (stats_csv(rows.iter().copied()), rows.len())
+            };
+            ctx.reply(Chat::Raw(
+                match write(&path, csv) {
+                    Ok(()) => format!("§aExported {} players to {}", count, path.display()),
+                    Err(err) => format!("§cFailed to export to {}: {}", path.display(), err),
+                }
+                .into(),
+            ));
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stats_csv;
+    use crate::{
+        protocol::types::UUID,
+        web::hypixel::{PlayerBedwarsStats, PlayerInfo, PlayerStats, Rank},
+    };
+
+    #[test]
+    fn csv() {
+        let info = PlayerInfo {
+            stats: PlayerStats {
+                bedwars: Some(PlayerBedwarsStats {
+                    final_kills: Some(30),
+                    final_deaths: Some(8),
+                    wins: Some(5),
+                    losses: Some(2),
+                    winstreak: None,
+                }),
+            },
+            rank: Rank::Custom("[A, B]".into()),
+            name: "Player".into(),
+            network_exp: 0.0,
+            achievements: Default::default(),
+        };
+        let player = "069a79f4-44e9-4726-a5be-fca90e38aaf5".parse().unwrap();
+        let nick = UUID([0; 16]);
+        assert_eq!(
+            stats_csv(vec![(player, "Player", Some(&info)), (nick, "Nick", None)].into_iter()),
+            "uuid,name,rank,fkdr,wlr,network_level,nicked\n\
+             069a79f4-44e9-4726-a5be-fca90e38aaf5,Player,\"[A, B]\",3.75,2.50,1,false\n\
+             00000000-0000-0000-0000-000000000000,Nick,,,,,true\n"
+        );
+    }
+}
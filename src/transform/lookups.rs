@@ -0,0 +1,318 @@
+use super::{
+    decode_chat_message, Direction, PacketAction, PacketTransform, SessionContext, TransformError,
+    SERVERBOUND_CHAT_ID,
+};
+use crate::{
+    connection::codec::OwnedPacket,
+    lookup_player,
+    protocol::types::{Chat, ChatObject, Color},
+    throttled_message,
+    web::error::Error as WebError,
+};
+use futures::future::BoxFuture;
+
+// Shown by /recent
+const MAX_RECENT_GAMES: usize = 5;
+// Shown by /history
+const MAX_HISTORY_ENTRIES: usize = 5;
+// The UUID in /whois links here
+const NAMEMC_PROFILE_URL: &str = "https://namemc.com/profile/";
+
+fn not_found(uname: &str) -> Chat<'static> {
+    Chat::Raw(format!("§c{} is nicked or does not exist", uname).into())
+}
+
+// /recent <player> shows the last few games the player played
+pub struct RecentCommand;
+
+impl PacketTransform for RecentCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let uname = match msg.strip_prefix("/recent ") {
+                Some(uname) => uname,
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let hypixel = ctx.hypixel(&ctx.settings());
+            ctx.reply(
+                match lookup_player(uname, ctx.all_local_players, ctx.mojang).await {
+                    None => not_found(uname),
+                    Some((uuid, name)) => match hypixel.recent_games(uuid).await {
+                        Err(WebError::Throttled(delay)) => throttled_message(delay),
+                        Err(err) => Chat::Raw(
+                            format!("§cFailed to get the recent games of {}: {}", name, err).into(),
+                        ),
+                        Ok(games) if games.is_empty() => {
+                            Chat::Raw(format!("§7{} has no recent games", name).into())
+                        }
+                        Ok(games) => {
+                            let mut lines =
+                                vec![Chat::Raw(format!("§aRecent games of {}:", name).into())];
+                            for game in games.iter().take(MAX_RECENT_GAMES) {
+                                let mut line = format!("\n§e{}", game.game_type);
+                                if let Some(ref mode) = game.mode {
+                                    line.push_str(&format!(" {}", mode));
+                                }
+                                if let Some(ref map) = game.map {
+                                    line.push_str(&format!(" §7on §e{}", map));
+                                }
+                                line.push_str(&match game.ended {
+                                    Some(ended) => format!(
+                                        "§7: {} minutes",
+                                        ended.saturating_sub(game.date) / 60000
+                                    ),
+                                    None => "§7: §aIn progress".into(),
+                                });
+                                lines.push(Chat::Raw(line.into()));
+                            }
+                            Chat::Array(lines)
+                        }
+                    },
+                },
+            );
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /history <player> shows how the player's FKDR changed between the stats
+// snapshots /stats recorded
+pub struct HistoryCommand;
+
+impl PacketTransform for HistoryCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let uname = match msg.strip_prefix("/history ") {
+                Some(uname) => uname,
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let target = lookup_player(uname, ctx.all_local_players, ctx.mojang).await;
+            ctx.reply(match (ctx.stats_db, target) {
+                (None, _) => Chat::Raw("§cStats history is disabled".into()),
+                (_, None) => not_found(uname),
+                (Some(db), Some((uuid, name))) => {
+                    // One extra so the oldest shown snapshot has something to
+                    // compare to
+                    match db.history(uuid, MAX_HISTORY_ENTRIES as u32 + 1).await {
+                        Err(err) => Chat::Raw(
+                            format!("§cFailed to get the stats history of {}: {}", name, err)
+                                .into(),
+                        ),
+                        Ok(history) if history.is_empty() => Chat::Raw(
+                            format!("§7No stats recorded for {} yet, try /stats first", name)
+                                .into(),
+                        ),
+                        Ok(history) => {
+                            let mut lines =
+                                vec![Chat::Raw(format!("§aStats history of {}:", name).into())];
+                            for (i, snapshot) in
+                                history.iter().take(MAX_HISTORY_ENTRIES).enumerate()
+                            {
+                                let fkdr = snapshot.fkdr.unwrap_or(0.0);
+                                lines.push(Chat::Raw(
+                                    match history.get(i + 1).and_then(|prev| prev.fkdr) {
+                                        Some(prev_fkdr) => format!(
+                                            "\n§7[{} FKDR: {:.2} → {:.2}]",
+                                            snapshot.date(),
+                                            prev_fkdr,
+                                            fkdr
+                                        ),
+                                        None => {
+                                            format!("\n§7[{} FKDR: {:.2}]", snapshot.date(), fkdr)
+                                        }
+                                    }
+                                    .into(),
+                                ));
+                            }
+                            Chat::Array(lines)
+                        }
+                    }
+                }
+            });
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /sb <player> shows the player's selected SkyBlock profile
+pub struct SkyBlockCommand;
+
+impl PacketTransform for SkyBlockCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let uname = match msg.strip_prefix("/sb ") {
+                Some(uname) => uname,
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let hypixel = ctx.hypixel(&ctx.settings());
+            ctx.reply(
+                match lookup_player(uname, ctx.all_local_players, ctx.mojang).await {
+                    None => not_found(uname),
+                    Some((uuid, name)) => match hypixel.skyblock_profiles(uuid).await {
+                        Err(WebError::Throttled(delay)) => throttled_message(delay),
+                        Err(err) => Chat::Raw(
+                            format!("§cFailed to get the SkyBlock profiles of {}: {}", name, err)
+                                .into(),
+                        ),
+                        Ok(profiles) => match profiles
+                            .iter()
+                            .find(|profile| profile.selected)
+                            .or_else(|| profiles.first())
+                        {
+                            None => {
+                                Chat::Raw(format!("§7{} has never played SkyBlock", name).into())
+                            }
+                            Some(profile) => Chat::Raw(
+                                format!(
+                                    "§a{}§7 on §e{}§7: skill average §e{:.2}",
+                                    name,
+                                    profile.cute_name,
+                                    profile.skill_average()
+                                )
+                                .into(),
+                            ),
+                        },
+                    },
+                },
+            );
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /whois <player> shows the player's rank, network level and UUID
+pub struct WhoisCommand;
+
+impl PacketTransform for WhoisCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let uname = match msg.strip_prefix("/whois ") {
+                Some(uname) => uname,
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let hypixel = ctx.hypixel(&ctx.settings());
+            ctx.reply(
+                match lookup_player(uname, ctx.all_local_players, ctx.mojang).await {
+                    None => not_found(uname),
+                    Some((uuid, name)) => match hypixel.info(uuid).await {
+                        Err(WebError::Throttled(delay)) => throttled_message(delay),
+                        Err(err) => Chat::Raw(
+                            format!("§cFailed to get the Hypixel profile of {}: {}", name, err)
+                                .into(),
+                        ),
+                        Ok(None) => {
+                            Chat::Raw(format!("§7{} has never joined Hypixel", name).into())
+                        }
+                        Ok(Some(info)) => Chat::Array(vec![
+                            info.full_badge(),
+                            Chat::Raw(
+                                format!(
+                                    "§7: network level §e{:.2}§7, UUID ",
+                                    info.network_level_exact()
+                                )
+                                .into(),
+                            ),
+                            Chat::Object(
+                                ChatObject::text(uuid.to_string())
+                                    .color(Color::Yellow)
+                                    .click_open_url(format!("{}{}", NAMEMC_PROFILE_URL, uuid))
+                                    .hover_show_text(Chat::Raw("Open on NameMC".into())),
+                            ),
+                        ]),
+                    },
+                },
+            );
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+// /where <player> shows the game the player is in, if they let others see it
+pub struct WhereCommand;
+
+impl PacketTransform for WhereCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let uname = match msg.strip_prefix("/where ") {
+                Some(uname) => uname,
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let hypixel = ctx.hypixel(&ctx.settings());
+            ctx.reply(
+                match lookup_player(uname, ctx.all_local_players, ctx.mojang).await {
+                    None => not_found(uname),
+                    Some((uuid, name)) => match hypixel.online_status(uuid).await {
+                        Err(WebError::Throttled(delay)) => throttled_message(delay),
+                        Err(err) => Chat::Raw(
+                            format!("§cFailed to get the online status of {}: {}", name, err)
+                                .into(),
+                        ),
+                        Ok(None) => Chat::Raw(
+                            format!("§7{} is offline or hides their online status", name).into(),
+                        ),
+                        Ok(Some(status)) => {
+                            let mut line = format!("§a{}§7 is in §e{}", name, status.game_type);
+                            if let Some(ref mode) = status.mode {
+                                line.push_str(&format!(" {}", mode));
+                            }
+                            if let Some(ref map) = status.map {
+                                line.push_str(&format!(" §7on §e{}", map));
+                            }
+                            Chat::Raw(line.into())
+                        }
+                    },
+                },
+            );
+            Ok(PacketAction::Discard)
+        })
+    }
+}
@@ -0,0 +1,392 @@
+mod commands;
+mod export;
+mod lookups;
+mod nick;
+mod party;
+mod player_list;
+mod report;
+mod top;
+mod watchlist;
+
+pub use commands::{
+    AchievementsCommand, BansCommand, FriendsCommand, GuildStatsCommand, PartyStatsCommand,
+    PingCommand, ProxyStatsCommand, ReloadCommand, StatsCommand,
+};
+pub use export::ExportCommand;
+pub use lookups::{HistoryCommand, RecentCommand, SkyBlockCommand, WhereCommand, WhoisCommand};
+pub use nick::{NickCommand, NickSubstitution};
+pub use party::PartyChatStats;
+pub use player_list::{PlayerList, PlayerListTracker};
+pub use report::ReportCommand;
+pub use top::TopCommand;
+pub use watchlist::WatchCommand;
+
+use crate::{
+    config::DynamicConfig,
+    connection::codec::OwnedPacket,
+    persistence::StatsDb,
+    protocol::{
+        error::Error as ProtocolError,
+        types::{Chat, Decode, LengthCappedString, UUID},
+        version::ProtocolVersion,
+    },
+    web::{
+        hypixel::{Hypixel, PlayerInfo},
+        mojang::RateLimitedMojang,
+    },
+};
+use futures::future::BoxFuture;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    io::Cursor,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+const GAMEMODE_SPECTATOR: u8 = 3;
+// How long after /pstats a party list is taken as the response to it
+const PARTY_LIST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type TransformError = Box<dyn Error + Send + Sync + 'static>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Serverbound,
+    Clientbound,
+}
+
+#[derive(Debug)]
+pub enum PacketAction {
+    // Unchanged, on to the next transform
+    Pass(OwnedPacket),
+    // Modified, on to the next transform
+    Replace(OwnedPacket),
+    // Dropped; the remaining transforms don't see it
+    Discard,
+    // Sent instead of the packet; the remaining transforms don't see them
+    Inject(Vec<OwnedPacket>),
+}
+
+impl PacketAction {
+    // What to send on once the chain is done
+    pub fn into_packets(self) -> Vec<OwnedPacket> {
+        match self {
+            PacketAction::Pass(packet) | PacketAction::Replace(packet) => vec![packet],
+            PacketAction::Discard => Vec::new(),
+            PacketAction::Inject(packets) => packets,
+        }
+    }
+}
+
+// Everything a transform may need about the session it is running in
+pub struct SessionContext<'a> {
+    pub conn_id: UUID,
+    pub version: ProtocolVersion,
+    // The account the proxy logged in with
+    pub username: &'a str,
+    pub uuid: UUID,
+    // What the local client was told its name is, which differs from
+    // username while a nick is set
+    pub display_name: &'a str,
+    // Set by /nick for the next login
+    pub nick: &'a Mutex<Option<String>>,
+    pub hypixel: &'a Hypixel,
+    pub mojang: &'a Arc<RateLimitedMojang<'static>>,
+    pub stats_db: Option<&'a StatsDb>,
+    pub dynamic_config: &'a RwLock<DynamicConfig>,
+    // Where /reload reads dynamic_config from
+    pub config_path: Option<&'a Path>,
+    pub watchlist_path: Option<&'a Path>,
+    pub all_local_players: &'a Mutex<PlayerList>,
+    pub pings: &'a Mutex<HashMap<UUID, i32>>,
+    pub gamemodes: &'a Mutex<HashMap<UUID, u8>>,
+    // Lowercased names /stats and /ping are looking up right now
+    pub pending_stats: &'a Mutex<HashSet<String>>,
+    // Everyone /stats has looked up this session, with None for nicked players
+    pub session_stats: &'a Mutex<HashMap<UUID, Option<PlayerInfo>>>,
+    // When /pstats last asked for the party list
+    pub party_list_requested: &'a Mutex<Option<Instant>>,
+    // Messages for the local client, shown by the clientbound loop
+    pub send_to_client: &'a Arc<Mutex<VecDeque<Chat<'static>>>>,
+}
+
+impl SessionContext<'_> {
+    // Cloned so /reload takes effect on the next packet
+    pub fn settings(&self) -> DynamicConfig {
+        self.dynamic_config.read().unwrap().clone()
+    }
+
+    // Uses the API key from the settings if there is one
+    pub fn hypixel(&self, settings: &DynamicConfig) -> Hypixel {
+        match settings.api_key {
+            Some(ref api_key) => self.hypixel.clone_with_key(api_key),
+            None => self.hypixel.clone(),
+        }
+    }
+
+    // Spectators are usually staff or players who are out, not opponents
+    pub fn is_spectator(&self, uuid: UUID) -> bool {
+        self.gamemodes.lock().unwrap().get(&uuid) == Some(&GAMEMODE_SPECTATOR)
+    }
+
+    // Whether a party list now is the response to /pstats. Hypixel may send
+    // it over several messages, so this doesn't stop at the first one.
+    pub fn party_list_requested(&self) -> bool {
+        self.party_list_requested
+            .lock()
+            .unwrap()
+            .is_some_and(|requested| requested.elapsed() < PARTY_LIST_TIMEOUT)
+    }
+
+    pub fn reply(&self, chat: Chat<'static>) {
+        self.send_to_client.lock().unwrap().push_back(chat);
+    }
+}
+
+pub trait PacketTransform: Send + Sync {
+    // Packets nothing wants are forwarded without being buffered, so this
+    // should be as narrow as possible
+    fn wants(&self, id: i32, direction: Direction) -> bool;
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>>;
+}
+
+// Runs transforms in the order they were added
+#[derive(Default)]
+pub struct PacketTransformChain {
+    transforms: Vec<Box<dyn PacketTransform>>,
+}
+
+impl PacketTransformChain {
+    pub fn new() -> PacketTransformChain {
+        Default::default()
+    }
+
+    pub fn then(mut self, transform: impl PacketTransform + 'static) -> PacketTransformChain {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    pub fn wants(&self, id: i32, direction: Direction) -> bool {
+        self.transforms
+            .iter()
+            .any(|transform| transform.wants(id, direction))
+    }
+
+    pub async fn apply(
+        &self,
+        mut packet: OwnedPacket,
+        direction: Direction,
+        ctx: &SessionContext<'_>,
+    ) -> Result<PacketAction, TransformError> {
+        let mut replaced = false;
+        for transform in &self.transforms {
+            if !transform.wants(packet.id, direction) {
+                continue;
+            }
+            packet = match transform.transform(packet, direction, ctx).await? {
+                PacketAction::Pass(packet) => packet,
+                PacketAction::Replace(packet) => {
+                    replaced = true;
+                    packet
+                }
+                action => return Ok(action),
+            };
+        }
+        Ok(if replaced {
+            PacketAction::Replace(packet)
+        } else {
+            PacketAction::Pass(packet)
+        })
+    }
+}
+
+// Serverbound chat, i.e. messages and commands typed by the player
+pub const SERVERBOUND_CHAT_ID: i32 = 1;
+
+pub async fn decode_chat_message(
+    packet: &OwnedPacket,
+    version: ProtocolVersion,
+) -> Result<String, TransformError> {
+    let mut content = Cursor::new(&packet.content);
+    let message = LengthCappedString::<256>::decode(&mut content, version).await?;
+    if content.position() as usize != packet.content.len() {
+        return Err(ProtocolError::Malformed.into());
+    }
+    Ok(message.0.into_owned())
+}
+
+// Everything a SessionContext borrows, for testing transforms without a
+// connection
+#[cfg(test)]
+pub(crate) struct TestSession {
+    pub hypixel: Hypixel,
+    pub mojang: Arc<RateLimitedMojang<'static>>,
+    pub nick: Mutex<Option<String>>,
+    pub dynamic_config: RwLock<DynamicConfig>,
+    pub all_local_players: Mutex<PlayerList>,
+    pub pings: Mutex<HashMap<UUID, i32>>,
+    pub gamemodes: Mutex<HashMap<UUID, u8>>,
+    pub pending_stats: Mutex<HashSet<String>>,
+    pub session_stats: Mutex<HashMap<UUID, Option<PlayerInfo>>>,
+    pub party_list_requested: Mutex<Option<Instant>>,
+    pub send_to_client: Arc<Mutex<VecDeque<Chat<'static>>>>,
+}
+
+#[cfg(test)]
+impl TestSession {
+    pub fn new() -> TestSession {
+        use crate::web::{hypixel::HypixelCacheConfig, mojang::Mojang};
+        TestSession {
+            hypixel: Hypixel::new("", None, HypixelCacheConfig::default()),
+            mojang: Arc::new(RateLimitedMojang::new(Mojang::new(None, None, None))),
+            nick: Mutex::new(None),
+            dynamic_config: RwLock::new(DynamicConfig::default()),
+            all_local_players: Mutex::new(PlayerList::new()),
+            pings: Mutex::new(HashMap::new()),
+            gamemodes: Mutex::new(HashMap::new()),
+            pending_stats: Mutex::new(HashSet::new()),
+            session_stats: Mutex::new(HashMap::new()),
+            party_list_requested: Mutex::new(None),
+            send_to_client: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn ctx(&self) -> SessionContext<'_> {
+        SessionContext {
+            conn_id: UUID([0; 16]),
+            version: ProtocolVersion::V1_8_9,
+            username: "Player",
+            uuid: UUID([1; 16]),
+            display_name: "Player",
+            nick: &self.nick,
+            hypixel: &self.hypixel,
+            mojang: &self.mojang,
+            stats_db: None,
+            dynamic_config: &self.dynamic_config,
+            config_path: None,
+            watchlist_path: None,
+            all_local_players: &self.all_local_players,
+            pings: &self.pings,
+            gamemodes: &self.gamemodes,
+            pending_stats: &self.pending_stats,
+            session_stats: &self.session_stats,
+            party_list_requested: &self.party_list_requested,
+            send_to_client: &self.send_to_client,
+        }
+    }
+}
+
+// A serverbound chat packet, as the local client sends it
+#[cfg(test)]
+pub(crate) async fn chat(msg: &str) -> OwnedPacket {
+    use crate::protocol::types::Encode;
+    let mut content = Vec::new();
+    LengthCappedString::<256>(msg.into())
+        .encode(&mut content, ProtocolVersion::V1_8_9)
+        .await
+        .unwrap();
+    OwnedPacket::new(SERVERBOUND_CHAT_ID, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::test;
+
+    // Counts what it sees and answers chat with a fixed action
+    struct Fixed {
+        action: fn(OwnedPacket) -> PacketAction,
+        seen: Mutex<usize>,
+    }
+
+    impl Fixed {
+        fn new(action: fn(OwnedPacket) -> PacketAction) -> Arc<Fixed> {
+            Arc::new(Fixed {
+                action,
+                seen: Mutex::new(0),
+            })
+        }
+    }
+
+    impl PacketTransform for Arc<Fixed> {
+        fn wants(&self, id: i32, direction: Direction) -> bool {
+            direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+        }
+
+        fn transform<'a>(
+            &'a self,
+            packet: OwnedPacket,
+            _direction: Direction,
+            _ctx: &'a SessionContext<'a>,
+        ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+            *self.seen.lock().unwrap() += 1;
+            Box::pin(async move { Ok((self.action)(packet)) })
+        }
+    }
+
+    #[test]
+    async fn chain_short_circuits() {
+        let session = TestSession::new();
+        let ctx = session.ctx();
+
+        let replace = Fixed::new(|mut packet| {
+            packet.content.push(0);
+            PacketAction::Replace(packet)
+        });
+        let pass = Fixed::new(PacketAction::Pass);
+        let chain = PacketTransformChain::new()
+            .then(replace.clone())
+            .then(pass.clone());
+        assert!(chain.wants(SERVERBOUND_CHAT_ID, Direction::Serverbound));
+        assert!(!chain.wants(SERVERBOUND_CHAT_ID, Direction::Clientbound));
+        let packet = chat("hello").await;
+        match chain
+            .apply(packet.clone(), Direction::Serverbound, &ctx)
+            .await
+        {
+            Ok(PacketAction::Replace(out)) => {
+                assert_eq!(out.content.len(), packet.content.len() + 1)
+            }
+            action => panic!("unexpected action {:?}", action),
+        }
+        assert_eq!(*pass.seen.lock().unwrap(), 1);
+
+        let discard = Fixed::new(|_| PacketAction::Discard);
+        let after = Fixed::new(PacketAction::Pass);
+        let chain = PacketTransformChain::new()
+            .then(discard.clone())
+            .then(after.clone());
+        assert!(matches!(
+            chain
+                .apply(chat("/hidden").await, Direction::Serverbound, &ctx)
+                .await,
+            Ok(PacketAction::Discard)
+        ));
+        assert_eq!(*discard.seen.lock().unwrap(), 1);
+        assert_eq!(*after.seen.lock().unwrap(), 0);
+    }
+
+    #[test]
+    async fn chat_message() {
+        let packet = chat("/stats *").await;
+        assert_eq!(
+            decode_chat_message(&packet, ProtocolVersion::V1_8_9)
+                .await
+                .unwrap(),
+            "/stats *"
+        );
+        let mut trailing = packet;
+        trailing.content.push(0);
+        assert!(decode_chat_message(&trailing, ProtocolVersion::V1_8_9)
+            .await
+            .is_err());
+    }
+}
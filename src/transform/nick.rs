@@ -0,0 +1,126 @@
+use super::{
+    decode_chat_message, Direction, PacketAction, PacketTransform, SessionContext, TransformError,
+    SERVERBOUND_CHAT_ID,
+};
+use crate::{
+    connection::codec::OwnedPacket,
+    protocol::types::{Chat, Encode, LengthCappedString},
+    web::mojang::is_valid_username,
+    MAX_CHAT_MESSAGE_LEN,
+};
+use futures::future::BoxFuture;
+
+// Replaces every word that is `from` (ignoring case) with `to`, or None if
+// there are none
+fn substitute_name(msg: &str, from: &str, to: &str) -> Option<String> {
+    let mut found = false;
+    let out = msg
+        .split(' ')
+        .map(|word| {
+            if word.eq_ignore_ascii_case(from) {
+                found = true;
+                to
+            } else {
+                word
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    if found {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+// The local client only knows the player by their nick, so it has to be
+// swapped for the real name before the server sees it, e.g. in /stats <nick>
+pub struct NickSubstitution;
+
+impl PacketTransform for NickSubstitution {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            if ctx.display_name == ctx.username {
+                return Ok(PacketAction::Pass(packet));
+            }
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let msg = match substitute_name(&msg, ctx.display_name, ctx.username) {
+                // The real name can be longer than the nick, in which case the
+                // message is sent as typed rather than rejected by the server
+                Some(msg) if msg.encode_utf16().count() <= MAX_CHAT_MESSAGE_LEN => msg,
+                _ => return Ok(PacketAction::Pass(packet)),
+            };
+            let mut content = Vec::new();
+            LengthCappedString::<MAX_CHAT_MESSAGE_LEN>(msg.into())
+                .encode(&mut content, ctx.version)
+                .await?;
+            Ok(PacketAction::Replace(OwnedPacket::new(packet.id, content)))
+        })
+    }
+}
+
+// /nick <name> sets the name the local client is shown from its next login,
+// and /nick off goes back to the real one
+pub struct NickCommand;
+
+impl PacketTransform for NickCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            if msg == "/nick off" {
+                *ctx.nick.lock().unwrap() = None;
+                ctx.reply(Chat::Raw(
+                    "§aNick removed, reconnect to see your real name".into(),
+                ));
+                return Ok(PacketAction::Discard);
+            }
+            let new_nick = match msg.strip_prefix("/nick ") {
+                Some(new_nick) => new_nick,
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let reply = if is_valid_username(new_nick) {
+                *ctx.nick.lock().unwrap() = Some(new_nick.to_string());
+                format!(
+                    "§aReconnect to appear as {} to yourself. The server still sees {}.",
+                    new_nick, ctx.username
+                )
+            } else {
+                format!("§c{} is not a valid username", new_nick)
+            };
+            ctx.reply(Chat::Raw(reply.into()));
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::substitute_name;
+
+    #[test]
+    fn substitute() {
+        assert_eq!(
+            substitute_name("/stats notch Alice nOtCh", "Notch", "RealName"),
+            Some("/stats RealName Alice RealName".into())
+        );
+        assert_eq!(substitute_name("/stats Notches", "Notch", "RealName"), None);
+    }
+}
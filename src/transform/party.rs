@@ -0,0 +1,110 @@
+use super::{Direction, PacketAction, PacketTransform, SessionContext, TransformError};
+use crate::{
+    config::DynamicConfig,
+    connection::{
+        codec::OwnedPacket,
+        packets::play::{clientbound_chat_id, ChatPacket, CHAT_POSITION_ACTION_BAR},
+    },
+    protocol::{
+        types::{Chat, UUID},
+        version::ProtocolVersion,
+    },
+    stats_chat, throttled_message,
+    web::{error::Error as WebError, hypixel::Hypixel, mojang::RateLimitedMojang},
+};
+use futures::future::BoxFuture;
+
+async fn party_member_stats(
+    username: String,
+    uuid: Option<UUID>,
+    hypixel: &Hypixel,
+    mojang: &RateLimitedMojang<'_>,
+    settings: &DynamicConfig,
+) -> Result<Chat<'static>, WebError> {
+    let (uuid, username) = match uuid {
+        Some(uuid) => (uuid, username),
+        None => match mojang.get_uuid(&username).await {
+            Ok(found) => found,
+            Err(_) => return Ok(Chat::Raw(format!("§4[NICKED] {}", username).into())),
+        },
+    };
+    Ok(match hypixel.info(uuid).await {
+        Err(WebError::Throttled(delay)) => throttled_message(delay),
+        Err(err) => return Err(err),
+        Ok(Some(info)) => stats_chat(&info, settings),
+        Ok(None) => Chat::Raw(format!("§7{}§r: Unknown", username).into()),
+    })
+}
+
+// Shows the stats of players joining the party, and of everyone in the party
+// list /pstats asked for, while auto_party_stats is on
+pub struct PartyChatStats {
+    chat_id: i32,
+}
+
+impl PartyChatStats {
+    pub fn new(version: ProtocolVersion) -> PartyChatStats {
+        PartyChatStats {
+            chat_id: clientbound_chat_id(version),
+        }
+    }
+}
+
+impl PacketTransform for PartyChatStats {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Clientbound && id == self.chat_id
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let settings = ctx.settings();
+            if !settings.auto_party_stats {
+                return Ok(PacketAction::Pass(packet));
+            }
+            let mut packet = packet;
+            let ChatPacket {
+                message: chat,
+                position,
+                ..
+            } = packet.parse::<ChatPacket>(ctx.version).await?.packet;
+            let mut usernames = Vec::new();
+            if position != CHAT_POSITION_ACTION_BAR {
+                usernames.extend(Hypixel::parse_party_join_message(&chat));
+                if ctx.party_list_requested() {
+                    usernames.extend(
+                        Hypixel::parse_party_list_message(&chat)
+                            .into_iter()
+                            .filter(|username| !username.eq_ignore_ascii_case(ctx.username)),
+                    );
+                }
+            }
+            for username in usernames {
+                let uuid = ctx
+                    .all_local_players
+                    .lock()
+                    .unwrap()
+                    .get_uuid_by_name(&username);
+                let settings = settings.clone();
+                let hypixel = ctx.hypixel(&settings);
+                let mojang = ctx.mojang.clone();
+                let send_to_client = ctx.send_to_client.clone();
+                let conn_id = ctx.conn_id;
+                // Looked up in the background so the game isn't held up
+                tokio::spawn(async move {
+                    match party_member_stats(username, uuid, &hypixel, &mojang, &settings).await {
+                        Ok(reply) => send_to_client.lock().unwrap().push_back(reply),
+                        Err(err) => {
+                            println!("[{}] Failed to get party member stats: {}", conn_id, err)
+                        }
+                    }
+                });
+            }
+            Ok(PacketAction::Pass(packet))
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Direction, PacketAction, PacketTransform, SessionContext, TransformError};
+use crate::{
+    connection::{codec::OwnedPacket, packets::play::PlayerInfoPacket},
+    protocol::types::{Chat, ChatObject, Color, UUID},
+    track_player_info,
+};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+
+const PLAYER_LIST_ITEM_ID: i32 = 0x38;
+
+// The players in the tab list, looked up by UUID or by name ignoring case.
+// Usernames are ASCII, so the lowercased name is used as the key
+#[derive(Debug, Default)]
+pub struct PlayerList {
+    by_uuid: HashMap<UUID, String>,
+    by_name: HashMap<String, UUID>,
+}
+
+impl PlayerList {
+    pub fn new() -> PlayerList {
+        Default::default()
+    }
+
+    // Returns the name the UUID was listed as and the UUID the name was
+    // listed as before, both of which are replaced
+    pub fn add(&mut self, uuid: UUID, name: String) -> (Option<String>, Option<UUID>) {
+        let old_name = self.remove_by_uuid(uuid);
+        let old_uuid = self.by_name.insert(name.to_ascii_lowercase(), uuid);
+        if let Some(old_uuid) = old_uuid {
+            self.by_uuid.remove(&old_uuid);
+        }
+        self.by_uuid.insert(uuid, name);
+        (old_name, old_uuid)
+    }
+
+    pub fn remove_by_uuid(&mut self, uuid: UUID) -> Option<String> {
+        let name = self.by_uuid.remove(&uuid)?;
+        self.by_name.remove(&name.to_ascii_lowercase());
+        Some(name)
+    }
+
+    pub fn get_uuid_by_name(&self, name: &str) -> Option<UUID> {
+        self.by_name.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    pub fn get_name_by_uuid(&self, uuid: UUID) -> Option<&str> {
+        self.by_uuid.get(&uuid).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (UUID, &str)> {
+        self.by_uuid
+            .iter()
+            .map(|(&uuid, name)| (uuid, name.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_uuid.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_uuid.is_empty()
+    }
+}
+
+// Keeps the tab list, pings and gamemodes used by the commands up to date, and warns
+// about watched players joining
+pub struct PlayerListTracker;
+
+impl PacketTransform for PlayerListTracker {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Clientbound && id == PLAYER_LIST_ITEM_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let mut packet = packet;
+            let info = packet.parse::<PlayerInfoPacket>(ctx.version).await?.packet;
+            let added = track_player_info(info, ctx.all_local_players, ctx.pings, ctx.gamemodes);
+            for (uuid, name) in added {
+                if ctx.hypixel.is_watched(uuid) {
+                    ctx.reply(Chat::Object(
+                        ChatObject::text(format!("⚠ [WATCHED] {} has joined!", name))
+                            .color(Color::Red),
+                    ));
+                }
+            }
+            Ok(PacketAction::Pass(packet))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player_list() {
+        let steve = UUID::new_v4();
+        let alex = UUID::new_v4();
+        let mut players = PlayerList::new();
+        assert_eq!(players.add(steve, "Steve".into()), (None, None));
+        assert_eq!(players.get_uuid_by_name("sTEVE"), Some(steve));
+        assert_eq!(players.get_name_by_uuid(steve), Some("Steve"));
+
+        // Taking Steve's name drops Steve entirely
+        assert_eq!(players.add(alex, "steve".into()), (None, Some(steve)));
+        assert_eq!(players.len(), 1);
+        assert_eq!(players.get_name_by_uuid(steve), None);
+        assert_eq!(players.get_uuid_by_name("Steve"), Some(alex));
+
+        assert_eq!(
+            players.add(alex, "Alex".into()),
+            (Some("steve".into()), None)
+        );
+        assert_eq!(players.get_uuid_by_name("steve"), None);
+        assert_eq!(players.remove_by_uuid(alex), Some("Alex".into()));
+        assert_eq!(players.remove_by_uuid(alex), None);
+        assert!(players.is_empty());
+    }
+}
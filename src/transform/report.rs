@@ -0,0 +1,178 @@
+use super::{
+    decode_chat_message, Direction, PacketAction, PacketTransform, SessionContext, TransformError,
+    SERVERBOUND_CHAT_ID,
+};
+use crate::{
+    connection::codec::OwnedPacket,
+    persistence::StatsSnapshot,
+    protocol::types::{Chat, UUID},
+    web::hypixel::RecentGame,
+};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::{
+    io::Error as IOError,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    // Seconds since the Unix epoch
+    timestamp: i64,
+    reporter: UUID,
+    // None if the target is nicked
+    target: Option<UUID>,
+    target_name: &'a str,
+    reason: &'a str,
+    stats: Option<StatsSnapshot>,
+    recent_games: Option<Vec<RecentGame>>,
+}
+
+// The log is only ever appended to, one report per line
+async fn append_report(path: &Path, report: &Report<'_>) -> Result<(), IOError> {
+    let mut line = serde_json::to_vec(report)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&line).await?;
+    // tokio finishes writes in the background, so they could still be
+    // pending when the file is dropped
+    file.flush().await
+}
+
+// /report <player> <reason> keeps a copy of the report in the log set in the
+// settings, along with what the target looked like at the time. The command
+// still goes on to the server, so Hypixel gets the report too.
+pub struct ReportCommand;
+
+impl PacketTransform for ReportCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let (uname, reason) = match msg
+                .strip_prefix("/report ")
+                .and_then(|args| args.split_once(' '))
+            {
+                Some((uname, reason)) if !reason.trim().is_empty() => (uname, reason.trim()),
+                _ => return Ok(PacketAction::Pass(packet)),
+            };
+            let settings = ctx.settings();
+            let path = match settings.report_log_path {
+                Some(ref path) => path.clone(),
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs() as i64);
+            let known_uuid = ctx
+                .all_local_players
+                .lock()
+                .unwrap()
+                .get_uuid_by_name(uname);
+            let uname = uname.to_string();
+            let reason = reason.to_string();
+            let reporter = ctx.uuid;
+            let hypixel = ctx.hypixel(&settings);
+            let mojang = ctx.mojang.clone();
+            let send_to_client = ctx.send_to_client.clone();
+            // Looked up and logged in the background so the report isn't
+            // held up on its way to the server
+            tokio::spawn(async move {
+                let target = match known_uuid {
+                    Some(uuid) => Some((uuid, uname.clone())),
+                    None => mojang.get_uuid(&uname).await.ok(),
+                };
+                // Whatever can be looked up; the report is logged either way
+                let (stats, recent_games) = match target {
+                    Some((uuid, _)) => (
+                        match hypixel.info(uuid).await {
+                            Ok(Some(info)) => StatsSnapshot::from_info(&info),
+                            _ => None,
+                        },
+                        hypixel.recent_games(uuid).await.ok(),
+                    ),
+                    None => (None, None),
+                };
+                let report = Report {
+                    timestamp,
+                    reporter,
+                    target: target.as_ref().map(|target| target.0),
+                    target_name: target.as_ref().map_or(&uname, |target| &target.1),
+                    reason: &reason,
+                    stats,
+                    recent_games,
+                };
+                let reply = match append_report(&path, &report).await {
+                    Ok(()) => format!("§aLogged your report of {}", report.target_name),
+                    Err(err) => format!("§cFailed to log the report: {}", err),
+                };
+                send_to_client
+                    .lock()
+                    .unwrap()
+                    .push_back(Chat::Raw(reply.into()));
+            });
+            Ok(PacketAction::Pass(packet))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append_report, Report};
+    use crate::protocol::types::UUID;
+    use serde_json::Value;
+    use std::{
+        env::temp_dir,
+        fs::{read_to_string, remove_file},
+        process,
+    };
+    use tokio::test;
+
+    #[test]
+    async fn append_only() {
+        let path = temp_dir().join(format!("mc-proxy-reports-{}.ndjson", process::id()));
+        let _ = remove_file(&path);
+        let target = UUID::new_v4();
+        for (target, reason) in [(Some(target), "killaura"), (None, "fly")] {
+            append_report(
+                &path,
+                &Report {
+                    timestamp: 0,
+                    reporter: UUID::new_v4(),
+                    target,
+                    target_name: "Cheater",
+                    reason,
+                    stats: None,
+                    recent_games: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let log = read_to_string(&path).unwrap();
+        remove_file(&path).unwrap();
+        let reports = log
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0]["target"], target.to_string());
+        assert_eq!(reports[0]["reason"], "killaura");
+        assert!(reports[1]["target"].is_null());
+        assert_eq!(reports[1]["target_name"], "Cheater");
+    }
+}
@@ -0,0 +1,146 @@
+use super::{
+    decode_chat_message, Direction, PacketAction, PacketTransform, PlayerList, SessionContext,
+    TransformError, SERVERBOUND_CHAT_ID,
+};
+use crate::{
+    connection::codec::OwnedPacket,
+    protocol::types::Chat,
+    throttled_message,
+    web::{error::Error as WebError, hypixel::LeaderboardEntry},
+};
+use futures::future::BoxFuture;
+
+// Shown by /top
+const MAX_TOP_PLAYERS: usize = 5;
+
+// The best placing of each player in the lobby on any of a game's
+// leaderboards, best first, as (name, 1-based place, leaderboard)
+fn leaderboard_placings<'a>(
+    leaderboards: &'a [LeaderboardEntry],
+    all_local_players: &PlayerList,
+) -> Vec<(String, usize, &'a LeaderboardEntry)> {
+    let mut placings = all_local_players
+        .iter()
+        .filter_map(|(uuid, name)| {
+            leaderboards
+                .iter()
+                .filter_map(|leaderboard| {
+                    let place = leaderboard
+                        .leaders
+                        .iter()
+                        .position(|&leader| leader == uuid)?;
+                    Some((name.to_string(), place + 1, leaderboard))
+                })
+                .min_by_key(|placing| placing.1)
+        })
+        .collect::<Vec<_>>();
+    placings.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    placings.truncate(MAX_TOP_PLAYERS);
+    placings
+}
+
+// /top <game> shows who in the lobby is on the game's leaderboards
+pub struct TopCommand;
+
+impl PacketTransform for TopCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let game = match msg.strip_prefix("/top ") {
+                Some(game) => game.to_ascii_uppercase(),
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let hypixel = ctx.hypixel(&ctx.settings());
+            ctx.reply(match hypixel.leaderboards().await {
+                Err(WebError::Throttled(delay)) => throttled_message(delay),
+                Err(err) => Chat::Raw(format!("§cFailed to get the leaderboards: {}", err).into()),
+                Ok(leaderboards) => match leaderboards.get(&game) {
+                    None => Chat::Raw(format!("§c{} has no leaderboards", game).into()),
+                    Some(leaderboards) => {
+                        let placings = leaderboard_placings(
+                            leaderboards,
+                            &ctx.all_local_players.lock().unwrap(),
+                        );
+                        if placings.is_empty() {
+                            Chat::Raw(
+                                format!("§7Nobody in this lobby is on the {} leaderboards", game)
+                                    .into(),
+                            )
+                        } else {
+                            let mut lines = vec![Chat::Raw(
+                                format!(
+                                    "§aTop players in this lobby on the {} leaderboards:",
+                                    game
+                                )
+                                .into(),
+                            )];
+                            for (name, place, leaderboard) in placings {
+                                lines.push(Chat::Raw(
+                                    format!(
+                                        "\n§e#{} §a{}§7 in {} {}",
+                                        place, name, leaderboard.prefix, leaderboard.title
+                                    )
+                                    .into(),
+                                ));
+                            }
+                            Chat::Array(lines)
+                        }
+                    }
+                },
+            });
+            Ok(PacketAction::Discard)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{leaderboard_placings, PlayerList};
+    use crate::{protocol::types::UUID, web::hypixel::LeaderboardEntry};
+
+    fn uuid(last: u8) -> UUID {
+        let mut uuid = [0; 16];
+        uuid[15] = last;
+        UUID(uuid)
+    }
+
+    #[test]
+    fn leaderboard_placings_in_lobby() {
+        let leaderboard = |title: &str, leaders: &[u8]| LeaderboardEntry {
+            path: String::new(),
+            prefix: "Overall".into(),
+            title: title.into(),
+            count: 10,
+            leaders: leaders.iter().map(|&i| uuid(i)).collect(),
+        };
+        let leaderboards = [
+            leaderboard("Wins", &[9, 3, 1]),
+            leaderboard("Final Kills", &[1, 2]),
+        ];
+        let mut players = PlayerList::new();
+        for (i, name) in [(1, "First"), (2, "Second"), (3, "Third"), (4, "Nobody")] {
+            players.add(uuid(i), name.into());
+        }
+        let placings = leaderboard_placings(&leaderboards, &players)
+            .into_iter()
+            .map(|(name, place, leaderboard)| (name, place, leaderboard.title.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            placings,
+            [
+                ("First".to_string(), 1, "Final Kills"),
+                ("Second".to_string(), 2, "Final Kills"),
+                ("Third".to_string(), 2, "Wins"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use super::{
+    decode_chat_message, Direction, PacketAction, PacketTransform, SessionContext, TransformError,
+    SERVERBOUND_CHAT_ID,
+};
+use crate::{
+    config::save_watchlist, connection::codec::OwnedPacket, lookup_player, protocol::types::Chat,
+};
+use futures::future::BoxFuture;
+
+// /watch <player> and /unwatch <player> change who gets a warning when they
+// join the lobby. The watchlist is saved if there is a file for it.
+pub struct WatchCommand;
+
+impl PacketTransform for WatchCommand {
+    fn wants(&self, id: i32, direction: Direction) -> bool {
+        direction == Direction::Serverbound && id == SERVERBOUND_CHAT_ID
+    }
+
+    fn transform<'a>(
+        &'a self,
+        packet: OwnedPacket,
+        _direction: Direction,
+        ctx: &'a SessionContext<'a>,
+    ) -> BoxFuture<'a, Result<PacketAction, TransformError>> {
+        Box::pin(async move {
+            let msg = decode_chat_message(&packet, ctx.version).await?;
+            let (watch, uname) = match msg
+                .strip_prefix("/watch ")
+                .map(|uname| (true, uname))
+                .or_else(|| msg.strip_prefix("/unwatch ").map(|uname| (false, uname)))
+            {
+                Some(command) => command,
+                None => return Ok(PacketAction::Pass(packet)),
+            };
+            let (uuid, name) = match lookup_player(uname, ctx.all_local_players, ctx.mojang).await {
+                Some(player) => player,
+                None => {
+                    ctx.reply(Chat::Raw(
+                        format!("§c{} is nicked or does not exist", uname).into(),
+                    ));
+                    return Ok(PacketAction::Discard);
+                }
+            };
+            let changed = if watch {
+                ctx.hypixel.add_to_watchlist(uuid)
+            } else {
+                ctx.hypixel.remove_from_watchlist(uuid)
+            };
+            let saved = match ctx.watchlist_path {
+                Some(path) if changed => save_watchlist(path, &ctx.hypixel.watchlist()),
+                _ => Ok(()),
+            };
+            ctx.reply(Chat::Raw(
+                match (saved, watch, changed) {
+                    (Err(err), _, _) => format!("§cFailed to save the watchlist: {}", err),
+                    (Ok(()), true, true) => format!("§aAdded {} to the watchlist", name),
+                    (Ok(()), true, false) => format!("§7{} is already on the watchlist", name),
+                    (Ok(()), false, true) => format!("§aRemoved {} from the watchlist", name),
+                    (Ok(()), false, false) => format!("§7{} is not on the watchlist", name),
+                }
+                .into(),
+            ));
+            Ok(PacketAction::Discard)
+        })
+    }
+}
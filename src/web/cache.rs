@@ -0,0 +1,103 @@
+use super::error::Error as WebError;
+use moka::future::Cache;
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+}
+
+impl CacheStats {
+    // None until something has been looked up
+    pub fn hit_rate(&self) -> Option<f64> {
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let total = hits + self.cache_misses.load(Ordering::Relaxed);
+        if total == 0 {
+            None
+        } else {
+            Some(hits as f64 / total as f64)
+        }
+    }
+}
+
+// Clones share the same underlying cache
+#[derive(Clone)]
+pub struct CachedEndpoint<K, V> {
+    cache: Cache<K, V>,
+}
+
+impl<K: Hash + Eq + Send + Sync + 'static, V: Clone + Send + Sync + 'static> CachedEndpoint<K, V> {
+    pub fn new(ttl: Duration, capacity: u64) -> CachedEndpoint<K, V> {
+        CachedEndpoint {
+            cache: Cache::builder()
+                .time_to_live(ttl)
+                .max_capacity(capacity)
+                .build(),
+        }
+    }
+
+    // Errors are never cached. Concurrent misses on the same key share a
+    // single fetch, e.g. a player listed under both their nick and real name
+    pub async fn get_or_fetch(
+        &self,
+        key: K,
+        stats: &CacheStats,
+        fetch: impl Future<Output = Result<V, WebError>>,
+    ) -> Result<V, WebError> {
+        if let Some(value) = self.cache.get(&key).await {
+            stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+        stats.cache_misses.fetch_add(1, Ordering::Relaxed);
+        self.cache
+            .try_get_with(key, fetch)
+            .await
+            .map_err(unshare_error)
+    }
+}
+
+// Every caller waiting on a failed fetch gets the same error
+fn unshare_error(err: Arc<WebError>) -> WebError {
+    Arc::try_unwrap(err).unwrap_or_else(|err| match *err {
+        // Kept as-is so callers can still tell how long to back off for
+        WebError::Throttled(delay) => WebError::Throttled(delay),
+        _ => WebError::Shared(err),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheStats, CachedEndpoint};
+    use futures::future::join_all;
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    };
+    use tokio::{test, time::sleep};
+
+    #[test]
+    async fn concurrent_misses_share_fetch() {
+        let endpoint = CachedEndpoint::<u32, u32>::new(Duration::from_secs(60), 16);
+        let stats = CacheStats::default();
+        let fetches = AtomicU64::new(0);
+        let values = join_all((0..16).map(|_| {
+            endpoint.get_or_fetch(1, &stats, async {
+                fetches.fetch_add(1, Ordering::Relaxed);
+                sleep(Duration::from_millis(50)).await;
+                Ok(42)
+            })
+        }))
+        .await;
+        assert!(values.into_iter().all(|value| value.unwrap() == 42));
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+    }
+}
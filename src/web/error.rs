@@ -1,6 +1,8 @@
 use super::hypixel::Error as HypixelError;
+use super::microsoft::Error as MicrosoftError;
 use super::yggdrasil::Error as YggdrasilError;
 use reqwest::Error as HTTPError;
+use std::{sync::Arc, time::Duration};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -9,8 +11,15 @@ pub enum Error {
     HTTP(#[from] HTTPError),
     #[error("Yggdrasil error")]
     Yggdrasil(#[from] YggdrasilError),
-    #[error("Hypixel error")]
+    #[error("Hypixel error: {0}")]
     Hypixel(#[from] HypixelError),
+    #[error("Microsoft error: {0}")]
+    Microsoft(#[from] MicrosoftError),
     #[error("no access token")]
     NoAccessToken,
+    #[error("throttled by the Hypixel API for {0:?}")]
+    Throttled(Duration),
+    // From a request shared by several callers
+    #[error(transparent)]
+    Shared(Arc<Error>),
 }
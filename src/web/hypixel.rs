@@ -1,11 +1,29 @@
-use super::error::Error as WebError;
-use crate::protocol::types::{Chat, ChatObject, ChatValue, Color, UUID};
+use super::{
+    cache::{CacheStats, CachedEndpoint},
+    error::Error as WebError,
+    mojang::is_valid_username,
+};
+use crate::protocol::types::{
+    serde_raw_uuid, strip_formatting_codes, Chat, ChatObject, Color, UUID,
+};
+use futures::stream::{self, StreamExt};
 use reqwest::{Client, RequestBuilder};
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_json::{Map, Value};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::{Infallible, TryFrom},
+    fmt::{self, Display},
+    future::Future,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+use tokio::time::Instant;
 
 #[derive(Clone, Debug, Deserialize, thiserror::Error)]
-#[error("{cause:?}")]
+#[error("{cause}")]
 pub struct Error {
     cause: String,
     throttle: Option<bool>,
@@ -40,7 +58,7 @@ impl<'de, T: Deserialize<'de>> Deserialize<'de> for HypixelResponse<T> {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PlayerBedwarsStats {
     #[serde(rename = "final_kills_bedwars")]
     pub final_kills: Option<u32>,
@@ -53,13 +71,25 @@ pub struct PlayerBedwarsStats {
     pub winstreak: Option<u32>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+// Missing deaths and losses count as 1 so new players don't divide by zero
+impl PlayerBedwarsStats {
+    pub fn fkdr(&self) -> f64 {
+        self.final_kills.map_or(0.0, |v| v as f64) / self.final_deaths.map_or(1.0, |v| v as f64)
+    }
+
+    pub fn wlr(&self) -> f64 {
+        self.wins.map_or(0.0, |v| v as f64) / self.losses.map_or(1.0, |v| v as f64)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PlayerStats {
     #[serde(rename = "Bedwars")]
     pub bedwars: Option<PlayerBedwarsStats>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Serialized as the rank's name followed by its colors, e.g. "MVP++ gold red"
+#[derive(Clone, Debug, PartialEq, SerializeDisplay, DeserializeFromStr)]
 pub enum Rank {
     Default,
     VIP,
@@ -72,14 +102,151 @@ pub enum Rank {
     Custom(String),
 }
 
-#[derive(Clone, Debug)]
+impl Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rank::Default => f.write_str("Default"),
+            Rank::VIP => f.write_str("VIP"),
+            Rank::VIPPlus => f.write_str("VIP+"),
+            Rank::MVP => f.write_str("MVP"),
+            Rank::MVPPlus(plus_color) => write!(f, "MVP+ {}", plus_color),
+            Rank::MVPPlusPlus(color, plus_color) => write!(f, "MVP++ {} {}", color, plus_color),
+            Rank::Youtuber => f.write_str("YouTube"),
+            Rank::Admin => f.write_str("Admin"),
+            Rank::Custom(prefix) => f.write_str(prefix),
+        }
+    }
+}
+
+impl Rank {
+    // e.g. "[MVP+]", or nothing for players without a rank
+    pub fn to_prefix_string(&self) -> String {
+        match self {
+            Rank::Default => String::new(),
+            Rank::VIP => "[VIP]".into(),
+            Rank::VIPPlus => "[VIP+]".into(),
+            Rank::MVP => "[MVP]".into(),
+            Rank::MVPPlus(_) => "[MVP+]".into(),
+            Rank::MVPPlusPlus(..) => "[MVP++]".into(),
+            Rank::Youtuber => "[YOUTUBE]".into(),
+            Rank::Admin => "[ADMIN]".into(),
+            Rank::Custom(prefix) => strip_formatting_codes(prefix),
+        }
+    }
+
+    pub fn to_coloured_prefix(&self) -> Chat<'static> {
+        match self {
+            &Rank::MVPPlus(plus_color) => {
+                Chat::Object(ChatObject::text("[MVP").color(Color::Aqua).extra(vec![
+                    Chat::Object(ChatObject::text("+").color(plus_color)),
+                    Chat::Raw("]".into()),
+                ]))
+            }
+            &Rank::MVPPlusPlus(color, plus_color) => {
+                Chat::Object(ChatObject::text("[MVP").color(color).extra(vec![
+                    Chat::Object(ChatObject::text("++").color(plus_color)),
+                    Chat::Raw("]".into()),
+                ]))
+            }
+            Rank::Default => Chat::Raw("".into()),
+            Rank::Admin => Chat::Raw("§c[ADMIN]".into()),
+            Rank::Youtuber => Chat::Raw("§c[§fYOUTUBE§c]".into()),
+            Rank::MVP => Chat::Raw("§b[MVP]".into()),
+            Rank::VIPPlus => Chat::Raw("§a[VIP§6+§a]".into()),
+            Rank::VIP => Chat::Raw("§a[VIP]".into()),
+            Rank::Custom(prefix) => Chat::Raw(prefix.clone().into()),
+        }
+    }
+}
+
+impl FromStr for Rank {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(' ');
+        let rank = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("Default"), None, ..) => Some(Rank::Default),
+            (Some("VIP"), None, ..) => Some(Rank::VIP),
+            (Some("VIP+"), None, ..) => Some(Rank::VIPPlus),
+            (Some("MVP"), None, ..) => Some(Rank::MVP),
+            (Some("MVP+"), Some(plus_color), None, _) => plus_color.parse().ok().map(Rank::MVPPlus),
+            (Some("MVP++"), Some(color), Some(plus_color), None) => color
+                .parse()
+                .and_then(|color| Ok(Rank::MVPPlusPlus(color, plus_color.parse()?)))
+                .ok(),
+            (Some("YouTube"), None, ..) => Some(Rank::Youtuber),
+            (Some("Admin"), None, ..) => Some(Rank::Admin),
+            _ => None,
+        };
+        Ok(rank.unwrap_or_else(|| Rank::Custom(s.into())))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PlayerInfo {
     pub stats: PlayerStats,
     pub rank: Rank,
     pub name: String,
+    #[serde(default)]
+    pub network_exp: f64,
+    // Progress towards each tiered achievement, keyed by lowercase names
+    // like bedwars_wins
+    #[serde(default)]
+    pub achievements: HashMap<String, u32>,
+}
+
+impl PlayerInfo {
+    // Each level needs 2500 more experience than the last, starting at 10000
+    pub fn network_level_exact(&self) -> f64 {
+        if self.network_exp < 0.0 {
+            1.0
+        } else {
+            (12.25 + 0.0008 * self.network_exp).sqrt() - 2.5
+        }
+    }
+
+    pub fn network_level(&self) -> u32 {
+        self.network_level_exact() as u32
+    }
+
+    // The level badge followed by the rank and name
+    pub fn full_badge(&self) -> Chat<'static> {
+        Chat::Array(vec![
+            network_level_badge(self.network_level()),
+            Chat::Raw(" ".into()),
+            self.into(),
+        ])
+    }
+}
+
+// Upper bound of each tier: stone, iron, gold, diamond, emerald, sapphire,
+// ruby and crystal above that
+const NETWORK_LEVEL_COLORS: [(u32, Color); 7] = [
+    (10, Color::Gray),
+    (25, Color::White),
+    (50, Color::Gold),
+    (100, Color::Aqua),
+    (150, Color::DarkGreen),
+    (200, Color::DarkAqua),
+    (250, Color::DarkRed),
+];
+
+pub fn network_level_color(level: u32) -> Color {
+    NETWORK_LEVEL_COLORS
+        .iter()
+        .find(|&&(max_level, _)| level <= max_level)
+        .map_or(Color::LightPurple, |&(_, color)| color)
+}
+
+// e.g. [✫42]
+pub fn network_level_badge(level: u32) -> Chat<'static> {
+    Chat::Object(ChatObject::text(format!("[✫{}]", level)).color(network_level_color(level)))
 }
 
-impl<'de> Deserialize<'de> for PlayerInfo {
+// The raw player object returned by the Hypixel API
+struct HypixelPlayerInfo(PlayerInfo);
+
+impl<'de> Deserialize<'de> for HypixelPlayerInfo {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let map = Map::deserialize(deserializer)?;
 
@@ -155,7 +322,7 @@ impl<'de> Deserialize<'de> for PlayerInfo {
             }
         };
 
-        Ok(PlayerInfo {
+        Ok(HypixelPlayerInfo(PlayerInfo {
             stats: map
                 .get("stats")
                 .ok_or_else(|| de::Error::missing_field("stats"))
@@ -167,101 +334,1034 @@ impl<'de> Deserialize<'de> for PlayerInfo {
                 .map(Deserialize::deserialize)?
                 .map_err(de::Error::custom)?,
             rank,
-        })
+            network_exp: map.get("networkExp").and_then(Value::as_f64).unwrap_or(0.0),
+            achievements: map
+                .get("achievements")
+                .and_then(Value::as_object)
+                .map(|achievements| {
+                    achievements
+                        .iter()
+                        .filter_map(|(name, progress)| {
+                            Some((
+                                name.to_ascii_lowercase(),
+                                u32::try_from(progress.as_u64()?).ok()?,
+                            ))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }))
     }
 }
 
 impl From<&PlayerInfo> for Chat<'static> {
     fn from(info: &PlayerInfo) -> Self {
-        match &info.rank {
-            &Rank::MVPPlus(plus_color) => Chat::Object(ChatObject {
-                color: Some(Color::Aqua),
-                value: ChatValue::Text {
-                    text: "[MVP".into(),
-                },
-                extra: Some(vec![
-                    Chat::Object(ChatObject {
-                        color: Some(plus_color),
-                        value: ChatValue::Text { text: "+".into() },
-                        ..Default::default()
-                    }),
-                    Chat::Raw("] ".into()),
-                    Chat::Raw(info.name.clone().into()),
-                ]),
-                ..Default::default()
-            }),
-            &Rank::MVPPlusPlus(color, plus_color) => Chat::Object(ChatObject {
-                color: Some(color),
-                value: ChatValue::Text {
-                    text: "[MVP".into(),
-                },
-                extra: Some(vec![
-                    Chat::Object(ChatObject {
-                        color: Some(plus_color),
-                        value: ChatValue::Text { text: "++".into() },
-                        ..Default::default()
-                    }),
-                    Chat::Raw("] ".into()),
-                    Chat::Raw(info.name.clone().into()),
-                ]),
-                ..Default::default()
-            }),
-            Rank::Default => Chat::Raw(["§7", &info.name].concat().into()),
-            rank => Chat::Raw(
-                [
-                    match rank {
-                        Rank::Admin => "§c[ADMIN]",
-                        Rank::Youtuber => "§c[§fYOUTUBE§c]",
-                        Rank::MVP => "§b[MVP]",
-                        Rank::VIPPlus => "§a[VIP§6+§a]",
-                        Rank::VIP => "§a[VIP]",
-                        Rank::Custom(rank) => rank,
-                        _ => unreachable!(),
-                    },
-                    " ",
-                    &info.name,
-                ]
-                .concat()
-                .into(),
-            ),
+        if info.rank == Rank::Default {
+            return Chat::Raw(["§7", &info.name].concat().into());
+        }
+        // The name takes on the colour the prefix ends with
+        match info.rank.to_coloured_prefix() {
+            Chat::Object(mut prefix) => {
+                prefix
+                    .extra
+                    .get_or_insert_with(Vec::new)
+                    .push(Chat::Raw([" ", &info.name].concat().into()));
+                Chat::Object(prefix)
+            }
+            Chat::Raw(prefix) => Chat::Raw([&prefix, " ", &info.name[..]].concat().into()),
+            prefix => Chat::Array(vec![prefix, Chat::Raw([" ", &info.name].concat().into())]),
         }
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Deserialize)]
 struct PlayerResponse {
-    player: Option<PlayerInfo>,
+    player: Option<HypixelPlayerInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentGame {
+    pub game_type: String,
+    pub mode: Option<String>,
+    pub map: Option<String>,
+    // Both in milliseconds since the Unix epoch; ended is missing while the
+    // game is still in progress
+    pub ended: Option<u64>,
+    pub date: u64,
+}
+
+#[derive(Deserialize)]
+struct RecentGamesResponse {
+    games: Vec<RecentGame>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerOnlineStatus {
+    pub game_type: String,
+    pub mode: Option<String>,
+    pub map: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HypixelSession {
+    online: bool,
+    game_type: Option<String>,
+    mode: Option<String>,
+    map: Option<String>,
+}
+
+impl HypixelSession {
+    fn into_status(self) -> Option<PlayerOnlineStatus> {
+        if self.online {
+            Some(PlayerOnlineStatus {
+                game_type: self.game_type?,
+                mode: self.mode,
+                map: self.map,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StatusResponse {
+    session: HypixelSession,
+}
+
+// One of the leaderboards shown in a game's lobby
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct LeaderboardEntry {
+    // The stat it ranks by, e.g. "bedwars_level"
+    pub path: String,
+    // e.g. "Overall" or "Weekly"
+    pub prefix: String,
+    pub title: String,
+    pub count: i64,
+    // Best first
+    pub leaders: Vec<UUID>,
+}
+
+#[derive(Deserialize)]
+struct LeaderboardsResponse {
+    leaderboards: HashMap<String, Vec<LeaderboardEntry>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuildMember {
+    #[serde(with = "serde_raw_uuid")]
+    pub uuid: UUID,
+    // e.g. "Guild Master" or "Member"
+    pub rank: String,
+    // In milliseconds since the Unix epoch
+    pub joined: u64,
+    // Guild XP earned each day over the last week, keyed by date (e.g. "2021-08-14")
+    #[serde(default)]
+    pub exp_history: HashMap<String, u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Guild {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub name: String,
+    pub members: Vec<GuildMember>,
+}
+
+impl Guild {
+    pub fn has_member(&self, uuid: UUID) -> bool {
+        self.members.iter().any(|member| member.uuid == uuid)
+    }
+}
+
+// Ban counts across the whole network, where rolling daily covers the last 24
+// hours
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct PunishmentStats {
+    pub watchdog_total: u64,
+    #[serde(rename = "watchdog_rollingDaily")]
+    pub watchdog_rolling_daily: u64,
+    pub staff_total: u64,
+    #[serde(rename = "staff_rollingDaily")]
+    pub staff_rolling_daily: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FriendRecord {
+    // Whoever sent the friend request
+    #[serde(with = "serde_raw_uuid")]
+    pub uuid_sender: UUID,
+    #[serde(with = "serde_raw_uuid")]
+    pub uuid_receiver: UUID,
+    // In milliseconds since the Unix epoch
+    pub started: u64,
+}
+
+impl FriendRecord {
+    // The other side of the friendship from the given player
+    pub fn friend_of(&self, uuid: UUID) -> UUID {
+        if self.uuid_sender == uuid {
+            self.uuid_receiver
+        } else {
+            self.uuid_sender
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FriendsResponse {
+    records: Vec<FriendRecord>,
+}
+
+#[derive(Deserialize)]
+struct GuildResponse {
+    // null if the player isn't in a guild
+    guild: Option<Guild>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkyBlockProfile {
+    pub profile_id: UUID,
+    pub cute_name: String,
+    // Whether this is the profile the player last played on
+    pub selected: bool,
+    // The player's own XP in this profile, keyed by skill (e.g. "SKILL_MINING")
+    pub skill_experience: HashMap<String, f64>,
+}
+
+// Skills counted towards the skill average. Runecrafting and social are
+// cosmetic and left out, like on Hypixel's own leaderboards.
+pub const SKYBLOCK_AVERAGE_SKILLS: [&str; 9] = [
+    "SKILL_FARMING",
+    "SKILL_MINING",
+    "SKILL_COMBAT",
+    "SKILL_FORAGING",
+    "SKILL_FISHING",
+    "SKILL_ENCHANTING",
+    "SKILL_ALCHEMY",
+    "SKILL_TAMING",
+    "SKILL_CARPENTRY",
+];
+
+// XP needed to go from each level to the next, starting at level 0
+const SKILL_XP_TABLE: [f64; 60] = [
+    50.0, 125.0, 200.0, 300.0, 500.0, 750.0, 1000.0, 1500.0, 2000.0, 3500.0, 5000.0, 7500.0,
+    10000.0, 15000.0, 20000.0, 30000.0, 50000.0, 75000.0, 100000.0, 200000.0, 300000.0, 400000.0,
+    500000.0, 600000.0, 700000.0, 800000.0, 900000.0, 1000000.0, 1100000.0, 1200000.0, 1300000.0,
+    1400000.0, 1500000.0, 1600000.0, 1700000.0, 1800000.0, 1900000.0, 2000000.0, 2100000.0,
+    2200000.0, 2300000.0, 2400000.0, 2500000.0, 2600000.0, 2750000.0, 2900000.0, 3100000.0,
+    3400000.0, 3700000.0, 4000000.0, 4300000.0, 4600000.0, 4900000.0, 5200000.0, 5500000.0,
+    5800000.0, 6100000.0, 6400000.0, 6700000.0, 7000000.0,
+];
+
+// Levels past a skill's cap (50 for some skills) aren't accounted for
+pub fn skill_level(xp: f64) -> u32 {
+    let mut remaining = xp;
+    let mut level = 0;
+    for &needed in SKILL_XP_TABLE.iter() {
+        if remaining < needed {
+            break;
+        }
+        remaining -= needed;
+        level += 1;
+    }
+    level
+}
+
+impl SkyBlockProfile {
+    pub fn skill_average(&self) -> f64 {
+        SKYBLOCK_AVERAGE_SKILLS
+            .iter()
+            .map(|skill| {
+                skill_level(self.skill_experience.get(*skill).copied().unwrap_or(0.0)) as f64
+            })
+            .sum::<f64>()
+            / SKYBLOCK_AVERAGE_SKILLS.len() as f64
+    }
+}
+
+#[derive(Deserialize)]
+struct HypixelSkyBlockMember {
+    #[serde(default)]
+    player_data: HypixelSkyBlockPlayerData,
+}
+
+#[derive(Default, Deserialize)]
+struct HypixelSkyBlockPlayerData {
+    #[serde(default)]
+    experience: HashMap<String, f64>,
+}
+
+#[derive(Deserialize)]
+struct HypixelSkyBlockProfile {
+    #[serde(with = "serde_raw_uuid")]
+    profile_id: UUID,
+    cute_name: String,
+    #[serde(default)]
+    selected: bool,
+    // Keyed by unhyphenated member UUID
+    members: HashMap<String, HypixelSkyBlockMember>,
+}
+
+impl HypixelSkyBlockProfile {
+    fn into_profile(self, uuid: UUID) -> SkyBlockProfile {
+        let skill_experience = self
+            .members
+            .into_iter()
+            .find(|(member, _)| member.parse() == Ok(uuid))
+            .map(|(_, member)| member.player_data.experience)
+            .unwrap_or_default();
+        SkyBlockProfile {
+            profile_id: self.profile_id,
+            cute_name: self.cute_name,
+            selected: self.selected,
+            skill_experience,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SkyBlockProfilesResponse {
+    // null for players that have never joined SkyBlock
+    profiles: Option<Vec<HypixelSkyBlockProfile>>,
 }
 
+// Upper bound on simultaneous API requests from a single bulk lookup
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+// How long to stop sending requests after the API reports throttling
+const THROTTLE_BACKOFF: Duration = Duration::from_secs(60);
+// The leaderboards only change every few minutes and are one big response
+const LEADERBOARDS_TTL: Duration = Duration::from_secs(300);
+// Only interesting as a trend, so no need to keep it fresher than this
+const PUNISHMENT_STATS_TTL: Duration = Duration::from_secs(300);
+
 #[derive(Clone, Debug)]
-pub struct Hypixel<'a> {
-    api_key: &'a str,
+pub struct HypixelCacheConfig {
+    pub player_ttl: Duration,
+    pub guild_ttl: Duration,
+    pub friends_ttl: Duration,
+    pub recent_games_ttl: Duration,
+    pub online_status_ttl: Duration,
+    pub skyblock_ttl: Duration,
+    // Per endpoint
+    pub capacity: u64,
+}
+
+impl Default for HypixelCacheConfig {
+    fn default() -> Self {
+        HypixelCacheConfig {
+            player_ttl: Duration::from_secs(60),
+            guild_ttl: Duration::from_secs(300),
+            friends_ttl: Duration::from_secs(300),
+            recent_games_ttl: Duration::from_secs(30),
+            online_status_ttl: Duration::from_secs(30),
+            skyblock_ttl: Duration::from_secs(60),
+            capacity: 1024,
+        }
+    }
+}
+
+// Clones share caches, so a single instance should be cloned rather than
+// creating a new one for each connection
+#[derive(Clone)]
+pub struct Hypixel {
+    api_key: Arc<str>,
     client: Client,
+    players: CachedEndpoint<UUID, Option<PlayerInfo>>,
+    recent_games: CachedEndpoint<UUID, Vec<RecentGame>>,
+    online_statuses: CachedEndpoint<UUID, Option<PlayerOnlineStatus>>,
+    skyblock_profiles: CachedEndpoint<UUID, Vec<SkyBlockProfile>>,
+    leaderboards: CachedEndpoint<(), HashMap<String, Vec<LeaderboardEntry>>>,
+    // By member
+    guilds: CachedEndpoint<UUID, Option<Guild>>,
+    friends: CachedEndpoint<UUID, Vec<FriendRecord>>,
+    punishment_stats: CachedEndpoint<(), PunishmentStats>,
+    cache_stats: Arc<CacheStats>,
+    throttled_until: Arc<Mutex<Option<Instant>>>,
+    // Players to warn about when they show up in the tab list
+    watchlist: Arc<RwLock<HashSet<UUID>>>,
 }
 
-impl Hypixel<'_> {
-    pub fn new(api_key: &str, client: Option<Client>) -> Hypixel {
+impl Hypixel {
+    pub fn new(
+        api_key: impl Into<Arc<str>>,
+        client: Option<Client>,
+        cache_config: HypixelCacheConfig,
+    ) -> Hypixel {
         Hypixel {
-            api_key,
+            api_key: api_key.into(),
             client: client.unwrap_or_default(),
+            players: CachedEndpoint::new(cache_config.player_ttl, cache_config.capacity),
+            recent_games: CachedEndpoint::new(cache_config.recent_games_ttl, cache_config.capacity),
+            online_statuses: CachedEndpoint::new(
+                cache_config.online_status_ttl,
+                cache_config.capacity,
+            ),
+            skyblock_profiles: CachedEndpoint::new(
+                cache_config.skyblock_ttl,
+                cache_config.capacity,
+            ),
+            leaderboards: CachedEndpoint::new(LEADERBOARDS_TTL, 1),
+            guilds: CachedEndpoint::new(cache_config.guild_ttl, cache_config.capacity),
+            friends: CachedEndpoint::new(cache_config.friends_ttl, cache_config.capacity),
+            punishment_stats: CachedEndpoint::new(PUNISHMENT_STATS_TTL, 1),
+            cache_stats: Default::default(),
+            throttled_until: Default::default(),
+            watchlist: Default::default(),
+        }
+    }
+
+    // Shares the HTTP client, caches and throttling with self
+    pub fn clone_with_key(&self, new_key: &str) -> Hypixel {
+        Hypixel {
+            api_key: new_key.into(),
+            ..self.clone()
+        }
+    }
+
+    pub fn cache_stats(&self) -> &CacheStats {
+        &self.cache_stats
+    }
+
+    // Whether the player wasn't watched yet
+    pub fn add_to_watchlist(&self, uuid: UUID) -> bool {
+        self.watchlist.write().unwrap().insert(uuid)
+    }
+
+    // Whether the player was watched
+    pub fn remove_from_watchlist(&self, uuid: UUID) -> bool {
+        self.watchlist.write().unwrap().remove(&uuid)
+    }
+
+    pub fn is_watched(&self, uuid: UUID) -> bool {
+        self.watchlist.read().unwrap().contains(&uuid)
+    }
+
+    pub fn watchlist(&self) -> HashSet<UUID> {
+        self.watchlist.read().unwrap().clone()
+    }
+
+    // e.g. "[MVP+] Name joined the party." as sent by Hypixel when someone
+    // accepts an invite
+    pub fn parse_party_join_message(chat: &Chat<'_>) -> Option<String> {
+        let text = chat.to_plain_text();
+        let text = text.trim().strip_suffix(" joined the party.")?;
+        let text = text.strip_prefix("[PARTY] ").unwrap_or(text);
+        let mut words = text.split(' ').rev();
+        let name = words.next()?;
+        // Anything else has to be the rank, which rules out player chat
+        let rank_ok = match (words.next(), words.next()) {
+            (None, _) => true,
+            (Some(rank), None) => rank.starts_with('[') && rank.ends_with(']'),
+            _ => false,
+        };
+        if rank_ok && is_valid_username(name) {
+            Some(name.to_string())
+        } else {
+            None
         }
     }
 
+    // The names in Hypixel's /party list output, which lists the leader,
+    // moderators and members on lines of their own, e.g.
+    // "Party Members: [MVP+] Name ● Other ●"
+    pub fn parse_party_list_message(chat: &Chat<'_>) -> Vec<String> {
+        const PREFIXES: [&str; 3] = ["Party Leader: ", "Party Moderators: ", "Party Members: "];
+        chat.to_plain_text()
+            .lines()
+            .filter_map(|line| {
+                PREFIXES
+                    .iter()
+                    .find_map(|prefix| line.trim().strip_prefix(prefix))
+            })
+            .flat_map(|members| {
+                members
+                    .split(['●', ','])
+                    // The name comes after the rank, if there is one
+                    .filter_map(|member| member.split_whitespace().last())
+                    .filter(|name| is_valid_username(name))
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
     fn with_auth(&self, builder: RequestBuilder) -> RequestBuilder {
-        builder.header("API-Key", self.api_key)
+        builder.header("API-Key", self.api_key.as_ref())
+    }
+
+    fn throttle(&self) {
+        *self.throttled_until.lock().unwrap() = Some(Instant::now() + THROTTLE_BACKOFF);
+    }
+
+    // Fails straight away while throttled rather than waiting, since the
+    // reply to a command would come minutes late anyway
+    async fn with_throttle<T, F: Future<Output = Result<T, WebError>>>(
+        &self,
+        request: impl FnOnce() -> F,
+    ) -> Result<T, WebError> {
+        let throttled_until = *self.throttled_until.lock().unwrap();
+        if let Some(throttled_until) = throttled_until {
+            let now = Instant::now();
+            if throttled_until > now {
+                return Err(WebError::Throttled(throttled_until - now));
+            }
+        }
+        match request().await {
+            Err(WebError::Hypixel(Error {
+                throttle: Some(true),
+                ..
+            })) => {
+                self.throttle();
+                Err(WebError::Throttled(THROTTLE_BACKOFF))
+            }
+            res => res,
+        }
     }
 
     pub async fn info(&self, uuid: UUID) -> Result<Option<PlayerInfo>, WebError> {
-        match self
-            .with_auth(self.client.get("https://api.hypixel.net/player"))
-            .query(&[("uuid", uuid)])
-            .send()
-            .await?
-            .json::<HypixelResponse<PlayerResponse>>()
+        self.players
+            .get_or_fetch(
+                uuid,
+                &self.cache_stats,
+                self.with_throttle(|| async {
+                    match self
+                        .with_auth(self.client.get("https://api.hypixel.net/player"))
+                        .query(&[("uuid", uuid)])
+                        .send()
+                        .await?
+                        .json::<HypixelResponse<PlayerResponse>>()
+                        .await?
+                    {
+                        HypixelResponse::Ok(PlayerResponse { player }) => {
+                            Ok(player.map(|player| player.0))
+                        }
+                        HypixelResponse::Err(err) => Err(err.into()),
+                    }
+                }),
+            )
+            .await
+    }
+
+    // From the same response as info, so this shares its cache. Empty for
+    // players who have never joined.
+    pub async fn achievements(&self, uuid: UUID) -> Result<HashMap<String, u32>, WebError> {
+        Ok(self
+            .info(uuid)
             .await?
-        {
-            HypixelResponse::Ok(PlayerResponse { player }) => Ok(player),
-            HypixelResponse::Err(err) => Err(err.into()),
+            .map(|info| info.achievements)
+            .unwrap_or_default())
+    }
+
+    // Most recent first. Players can hide these in their API settings, in
+    // which case the list is empty.
+    pub async fn recent_games(&self, uuid: UUID) -> Result<Vec<RecentGame>, WebError> {
+        self.recent_games
+            .get_or_fetch(
+                uuid,
+                &self.cache_stats,
+                self.with_throttle(|| async {
+                    match self
+                        .with_auth(self.client.get("https://api.hypixel.net/recentgames"))
+                        .query(&[("uuid", uuid)])
+                        .send()
+                        .await?
+                        .json::<HypixelResponse<RecentGamesResponse>>()
+                        .await?
+                    {
+                        HypixelResponse::Ok(RecentGamesResponse { games }) => Ok(games),
+                        HypixelResponse::Err(err) => Err(err.into()),
+                    }
+                }),
+            )
+            .await
+    }
+
+    // None if the player is offline, which is also what the API reports for
+    // players who hide their online status
+    pub async fn online_status(&self, uuid: UUID) -> Result<Option<PlayerOnlineStatus>, WebError> {
+        self.online_statuses
+            .get_or_fetch(
+                uuid,
+                &self.cache_stats,
+                self.with_throttle(|| async {
+                    match self
+                        .with_auth(self.client.get("https://api.hypixel.net/status"))
+                        .query(&[("uuid", uuid)])
+                        .send()
+                        .await?
+                        .json::<HypixelResponse<StatusResponse>>()
+                        .await?
+                    {
+                        HypixelResponse::Ok(StatusResponse { session }) => {
+                            Ok(session.into_status())
+                        }
+                        HypixelResponse::Err(err) => Err(err.into()),
+                    }
+                }),
+            )
+            .await
+    }
+
+    pub async fn skyblock_profiles(&self, uuid: UUID) -> Result<Vec<SkyBlockProfile>, WebError> {
+        self.skyblock_profiles
+            .get_or_fetch(
+                uuid,
+                &self.cache_stats,
+                self.with_throttle(|| async {
+                    match self
+                        .with_auth(self.client.get("https://api.hypixel.net/skyblock/profiles"))
+                        .query(&[("uuid", uuid)])
+                        .send()
+                        .await?
+                        .json::<HypixelResponse<SkyBlockProfilesResponse>>()
+                        .await?
+                    {
+                        HypixelResponse::Ok(SkyBlockProfilesResponse { profiles }) => Ok(profiles
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|profile| profile.into_profile(uuid))
+                            .collect()),
+                        HypixelResponse::Err(err) => Err(err.into()),
+                    }
+                }),
+            )
+            .await
+    }
+
+    // Keyed by game type, e.g. "BEDWARS"
+    pub async fn leaderboards(&self) -> Result<HashMap<String, Vec<LeaderboardEntry>>, WebError> {
+        self.leaderboards
+            .get_or_fetch(
+                (),
+                &self.cache_stats,
+                self.with_throttle(|| async {
+                    match self
+                        .with_auth(self.client.get("https://api.hypixel.net/leaderboards"))
+                        .send()
+                        .await?
+                        .json::<HypixelResponse<LeaderboardsResponse>>()
+                        .await?
+                    {
+                        HypixelResponse::Ok(LeaderboardsResponse { leaderboards }) => {
+                            Ok(leaderboards)
+                        }
+                        HypixelResponse::Err(err) => Err(err.into()),
+                    }
+                }),
+            )
+            .await
+    }
+
+    pub async fn punishment_stats(&self) -> Result<PunishmentStats, WebError> {
+        self.punishment_stats
+            .get_or_fetch(
+                (),
+                &self.cache_stats,
+                self.with_throttle(|| async {
+                    match self
+                        .with_auth(self.client.get("https://api.hypixel.net/punishmentstats"))
+                        .send()
+                        .await?
+                        .json::<HypixelResponse<PunishmentStats>>()
+                        .await?
+                    {
+                        HypixelResponse::Ok(stats) => Ok(stats),
+                        HypixelResponse::Err(err) => Err(err.into()),
+                    }
+                }),
+            )
+            .await
+    }
+
+    // Players can hide these in their API settings, in which case the list is
+    // empty
+    pub async fn friends(&self, uuid: UUID) -> Result<Vec<FriendRecord>, WebError> {
+        self.friends
+            .get_or_fetch(
+                uuid,
+                &self.cache_stats,
+                self.with_throttle(|| async {
+                    match self
+                        .with_auth(self.client.get("https://api.hypixel.net/friends"))
+                        .query(&[("uuid", uuid)])
+                        .send()
+                        .await?
+                        .json::<HypixelResponse<FriendsResponse>>()
+                        .await?
+                    {
+                        HypixelResponse::Ok(FriendsResponse { records }) => Ok(records),
+                        HypixelResponse::Err(err) => Err(err.into()),
+                    }
+                }),
+            )
+            .await
+    }
+
+    async fn fetch_guild(&self, uuid: UUID) -> Result<Option<Guild>, WebError> {
+        self.with_throttle(|| async {
+            match self
+                .with_auth(self.client.get("https://api.hypixel.net/guild"))
+                .query(&[("player", uuid.to_string())])
+                .send()
+                .await?
+                .json::<HypixelResponse<GuildResponse>>()
+                .await?
+            {
+                HypixelResponse::Ok(GuildResponse { guild }) => Ok(guild),
+                HypixelResponse::Err(err) => Err(err.into()),
+            }
+        })
+        .await
+    }
+
+    // The guild the player is in, if any
+    pub async fn guild(&self, uuid: UUID) -> Result<Option<Guild>, WebError> {
+        self.guilds
+            .get_or_fetch(uuid, &self.cache_stats, self.fetch_guild(uuid))
+            .await
+    }
+
+    // Results are in the same order as the input
+    pub async fn info_bulk(&self, uuids: &[UUID]) -> Vec<Result<Option<PlayerInfo>, WebError>> {
+        // Collected up front so the stream doesn't hold a closure borrowing self,
+        // which would keep the returned future from being Send
+        let requests = uuids
+            .iter()
+            .map(|&uuid| self.info(uuid))
+            .collect::<Vec<_>>();
+        stream::iter(requests)
+            .buffered(MAX_CONCURRENT_REQUESTS)
+            .collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        network_level_color, skill_level, FriendsResponse, GuildResponse, Hypixel,
+        LeaderboardEntry, LeaderboardsResponse, PlayerBedwarsStats, PlayerInfo, PlayerOnlineStatus,
+        PlayerResponse, PlayerStats, PunishmentStats, Rank, RecentGame, SkyBlockProfilesResponse,
+        StatusResponse,
+    };
+    use crate::protocol::types::{Chat, ChatObject, Color, UUID};
+    use std::sync::Arc;
+
+    #[test]
+    fn player_info_round_trip() {
+        for rank in [
+            Rank::Default,
+            Rank::VIPPlus,
+            Rank::MVPPlus(Color::DarkGreen),
+            Rank::MVPPlusPlus(Color::Aqua, Color::Red),
+            Rank::Youtuber,
+            Rank::Custom("§d[PIG§b+++§d]".into()),
+        ] {
+            let info = PlayerInfo {
+                stats: PlayerStats {
+                    bedwars: Some(PlayerBedwarsStats {
+                        final_kills: Some(120),
+                        final_deaths: Some(30),
+                        wins: Some(15),
+                        losses: None,
+                        winstreak: Some(2),
+                    }),
+                },
+                rank,
+                name: "Technoblade".into(),
+                network_exp: 1234567.0,
+                achievements: vec![("bedwars_wins".to_string(), 15)].into_iter().collect(),
+            };
+            let json = serde_json::to_string(&info).unwrap();
+            assert_eq!(serde_json::from_str::<PlayerInfo>(&json).unwrap(), info);
+        }
+    }
+
+    #[test]
+    fn player_achievements() {
+        let res = serde_json::from_str::<PlayerResponse>(
+            r#"{"player": {
+                "displayname": "Technoblade",
+                "stats": {},
+                "achievements": {"BEDWARS_WINS": 1200, "general_coins": 500000, "bad": "x", "too_big": 4294967296}
+            }}"#,
+        )
+        .unwrap();
+        let achievements = res.player.unwrap().0.achievements;
+        assert_eq!(achievements.len(), 2);
+        assert_eq!(achievements["bedwars_wins"], 1200);
+        assert_eq!(achievements["general_coins"], 500000);
+    }
+
+    #[test]
+    fn rank_prefix() {
+        assert_eq!(Rank::Default.to_prefix_string(), "");
+        assert_eq!(Rank::MVPPlus(Color::Red).to_prefix_string(), "[MVP+]");
+        assert_eq!(Rank::Admin.to_prefix_string(), "[ADMIN]");
+        assert_eq!(
+            Rank::Custom("§d[PIG§b+++§d]".into()).to_prefix_string(),
+            "[PIG+++]"
+        );
+    }
+
+    #[test]
+    fn recent_game_in_progress() {
+        let game: RecentGame = serde_json::from_str(
+            r#"{"date":1650000000000,"gameType":"BEDWARS","mode":"BEDWARS_EIGHT_TWO","map":"Lighthouse"}"#,
+        )
+        .unwrap();
+        assert_eq!(game.game_type, "BEDWARS");
+        assert_eq!(game.map.as_deref(), Some("Lighthouse"));
+        assert_eq!(game.ended, None);
+    }
+
+    #[test]
+    fn clone_with_key() {
+        let hypixel = Hypixel::new("old", None, Default::default());
+        let rotated = hypixel.clone_with_key("new");
+        assert_eq!((&*hypixel.api_key, &*rotated.api_key), ("old", "new"));
+        assert!(Arc::ptr_eq(&hypixel.cache_stats, &rotated.cache_stats));
+    }
+
+    #[test]
+    fn watchlist_is_shared() {
+        let hypixel = Hypixel::new("old", None, Default::default());
+        let rotated = hypixel.clone_with_key("new");
+        let uuid = UUID([1; 16]);
+        assert!(hypixel.add_to_watchlist(uuid));
+        assert!(!rotated.add_to_watchlist(uuid));
+        assert!(rotated.is_watched(uuid));
+        assert!(rotated.remove_from_watchlist(uuid));
+        assert!(!hypixel.is_watched(uuid));
+        assert!(hypixel.watchlist().is_empty());
+    }
+
+    #[test]
+    fn online_status() {
+        let response: StatusResponse = serde_json::from_str(
+            r#"{"session":{"online":true,"gameType":"BEDWARS","mode":"EIGHT_ONE","map":"Aquarium"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response.session.into_status(),
+            Some(PlayerOnlineStatus {
+                game_type: "BEDWARS".into(),
+                mode: Some("EIGHT_ONE".into()),
+                map: Some("Aquarium".into()),
+            })
+        );
+        let response: StatusResponse =
+            serde_json::from_str(r#"{"session":{"online":false}}"#).unwrap();
+        assert_eq!(response.session.into_status(), None);
+    }
+
+    #[test]
+    fn leaderboards() {
+        let response: LeaderboardsResponse = serde_json::from_str(
+            r#"{"leaderboards":{"BEDWARS":[{"path":"bedwars_level","prefix":"Overall","title":"Bed Wars Level","location":"-24,80,-8","count":10,"leaders":["4064d7ec-fe20-4f52-a7c8-fbd4e1ac5c53","069a79f4-44e9-4726-a5be-fca90e38aaf5"]}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response.leaderboards["BEDWARS"],
+            [LeaderboardEntry {
+                path: "bedwars_level".into(),
+                prefix: "Overall".into(),
+                title: "Bed Wars Level".into(),
+                count: 10,
+                leaders: vec![
+                    "4064d7ecfe204f52a7c8fbd4e1ac5c53".parse().unwrap(),
+                    "069a79f444e94726a5befca90e38aaf5".parse().unwrap(),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn punishment_stats() {
+        let stats: PunishmentStats = serde_json::from_str(
+            r#"{"watchdog_lastMinute":1,"staff_rollingDaily":2011,"watchdog_total":5501,"watchdog_rollingDaily":2754,"staff_total":1710}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            stats,
+            PunishmentStats {
+                watchdog_total: 5501,
+                watchdog_rolling_daily: 2754,
+                staff_total: 1710,
+                staff_rolling_daily: 2011,
+            }
+        );
+    }
+
+    #[test]
+    fn friends() {
+        let response: FriendsResponse = serde_json::from_str(
+            r#"{"uuid":"069a79f444e94726a5befca90e38aaf5","records":[
+                {"_id":"5a4f8c0e0cf2d3b1b5a3f1a7","uuidSender":"069a79f444e94726a5befca90e38aaf5","uuidReceiver":"853c80ef3c3749fdaa49938b674adae6","started":1515161614000},
+                {"_id":"5e8c5f2d0cf2a5f3a6a1c2b3","uuidSender":"4064d7ecfe204f52a7c8fbd4e1ac5c53","uuidReceiver":"069a79f444e94726a5befca90e38aaf5","started":1586257709000}
+            ]}"#,
+        )
+        .unwrap();
+        let player = "069a79f4-44e9-4726-a5be-fca90e38aaf5".parse().unwrap();
+        assert_eq!(
+            response
+                .records
+                .iter()
+                .map(|record| record.friend_of(player).to_string())
+                .collect::<Vec<_>>(),
+            [
+                "853c80ef-3c37-49fd-aa49-938b674adae6",
+                "4064d7ec-fe20-4f52-a7c8-fbd4e1ac5c53"
+            ]
+        );
+        assert_eq!(response.records[1].started, 1586257709000);
+    }
+
+    #[test]
+    fn guild_members() {
+        let response: GuildResponse = serde_json::from_str(
+            r#"{"guild":{"_id":"5af718d40cf2cbe7a9eeb063","name":"Test","members":[
+                {"uuid":"069a79f444e94726a5befca90e38aaf5","rank":"Guild Master","joined":1526143188000,"expHistory":{"2021-08-14":1200,"2021-08-13":0}},
+                {"uuid":"853c80ef3c3749fdaa49938b674adae6","rank":"Member","joined":1600000000000}
+            ]}}"#,
+        )
+        .unwrap();
+        let guild = response.guild.unwrap();
+        assert_eq!(guild.id, "5af718d40cf2cbe7a9eeb063");
+        assert_eq!(guild.members[0].rank, "Guild Master");
+        assert_eq!(guild.members[0].exp_history["2021-08-14"], 1200);
+        assert!(guild.members[1].exp_history.is_empty());
+        assert!(guild.has_member("853c80ef-3c37-49fd-aa49-938b674adae6".parse().unwrap()));
+        assert!(!guild.has_member("4064d7ec-fe20-4f52-a7c8-fbd4e1ac5c53".parse().unwrap()));
+
+        let response: GuildResponse = serde_json::from_str(r#"{"guild":null}"#).unwrap();
+        assert_eq!(response.guild, None);
+    }
+
+    #[test]
+    fn skyblock_skill_levels() {
+        assert_eq!(skill_level(0.0), 0);
+        assert_eq!(skill_level(49.9), 0);
+        assert_eq!(skill_level(50.0), 1);
+        assert_eq!(skill_level(175.0), 2);
+        assert_eq!(skill_level(55172425.0), 50);
+        assert_eq!(skill_level(111672425.0), 60);
+        assert_eq!(skill_level(1e12), 60);
+    }
+
+    #[test]
+    fn skyblock_profile_members() {
+        let res: SkyBlockProfilesResponse = serde_json::from_str(
+            r#"{"profiles":[{
+                "profile_id":"f7c8bbe0e5b04f1c9a1c21f3c4b2a2d1",
+                "cute_name":"Mango",
+                "selected":true,
+                "members":{
+                    "069a79f444e94726a5befca90e38aaf5":{"player_data":{"experience":{"SKILL_MINING":175.0}}},
+                    "853c80ef3c3749fdaa49938b674adae6":{"player_data":{"experience":{"SKILL_MINING":1e9}}}
+                }
+            }]}"#,
+        )
+        .unwrap();
+        let profile = res
+            .profiles
+            .unwrap()
+            .remove(0)
+            .into_profile("069a79f4-44e9-4726-a5be-fca90e38aaf5".parse().unwrap());
+        assert_eq!(profile.cute_name, "Mango");
+        assert!(profile.selected);
+        assert_eq!(profile.skill_average(), 2.0 / 9.0);
+    }
+
+    #[test]
+    fn party_list_message() {
+        let chat = Chat::Raw(
+            "§9§m-----------------------------\n§6Party Members (4)\n\n\
+             §eParty Leader: §b[MVP§c+§b] Technoblade §a●\n\
+             §eParty Moderators: §7Steve_2§a ●\n\
+             §eParty Members: §a[VIP] Alex§a ● §7Notch§c ●\n\
+             §9§m-----------------------------"
+                .into(),
+        );
+        assert_eq!(
+            Hypixel::parse_party_list_message(&chat),
+            ["Technoblade", "Steve_2", "Alex", "Notch"]
+        );
+        assert_eq!(
+            Hypixel::parse_party_list_message(&Chat::Raw("Party Members: Alex, Steve".into())),
+            ["Alex", "Steve"]
+        );
+        for text in ["Party Members (2)", "§7Steve: Party Members: Alex"] {
+            assert!(Hypixel::parse_party_list_message(&Chat::Raw(text.into())).is_empty());
+        }
+    }
+
+    #[test]
+    fn party_join_message() {
+        let chat = Chat::Object(
+            ChatObject::text("§b[MVP§c+§b] Technoblade ")
+                .extra(vec![Chat::Raw("§ejoined the party.".into())]),
+        );
+        assert_eq!(
+            Hypixel::parse_party_join_message(&chat).as_deref(),
+            Some("Technoblade")
+        );
+        assert_eq!(
+            Hypixel::parse_party_join_message(&Chat::Raw("§7Steve_2 joined the party.".into()))
+                .as_deref(),
+            Some("Steve_2")
+        );
+        for text in [
+            "§7Steve: Alex joined the party.",
+            "Steve left the party.",
+            "[MVP+] Not A Name joined the party.",
+        ] {
+            assert_eq!(
+                Hypixel::parse_party_join_message(&Chat::Raw(text.into())),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn network_level() {
+        let mut info: PlayerInfo =
+            serde_json::from_str(r#"{"stats":{"Bedwars":null},"rank":"Default","name":"Steve"}"#)
+                .unwrap();
+        assert_eq!(info.network_level(), 1);
+        info.network_exp = 10000.0;
+        assert_eq!(info.network_level(), 2);
+        info.network_exp = 22500.0;
+        assert_eq!(info.network_level(), 3);
+        info.network_exp = 24999.0;
+        assert_eq!(info.network_level(), 3);
+
+        assert_eq!(network_level_color(1), Color::Gray);
+        assert_eq!(network_level_color(11), Color::White);
+        assert_eq!(network_level_color(250), Color::DarkRed);
+        assert_eq!(network_level_color(251), Color::LightPurple);
+    }
+
+    #[tokio::test]
+    async fn throttled_requests_fail_fast() {
+        let hypixel = Hypixel::new("key", None, Default::default());
+        hypixel.throttle();
+        let mut sent = false;
+        let res = hypixel
+            .with_throttle(|| async {
+                sent = true;
+                Ok(())
+            })
+            .await;
+        assert!(!sent);
+        match res {
+            Err(super::WebError::Throttled(delay)) => assert!(delay <= super::THROTTLE_BACKOFF),
+            res => panic!("unexpected result {:?}", res),
         }
     }
 }
@@ -6,13 +6,68 @@ use crate::{
     },
     protocol::types::{serde_raw_uuid, UUID},
 };
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use reqwest::{Client, Error as HTTPError, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{borrow::Cow, future::Future};
 
-#[derive(Clone, Debug, Deserialize, thiserror::Error)]
-#[error("msft todo")]
-pub struct Error {}
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Xbox Live returned {code}: {message}")]
+    XboxLiveError { code: u64, message: String },
+    #[error("{}", describe_xsts_error(*.x_err))]
+    XSTSError { x_err: u64 },
+    #[error("this account does not own Minecraft")]
+    MinecraftNotOwned,
+    #[error("the Microsoft login has expired, please sign in again")]
+    TokenExpired,
+    #[error("network error: {0}")]
+    NetworkError(#[from] HTTPError),
+    #[error("unexpected response: {0}")]
+    InvalidResponse(#[from] serde_json::Error),
+}
+
+// See https://wiki.vg/Microsoft_Authentication_Scheme
+fn describe_xsts_error(x_err: u64) -> String {
+    match x_err {
+        2148916233 => "this Microsoft account has no Xbox account, sign up for one first".into(),
+        2148916235 => "Xbox Live is not available in this account's country".into(),
+        2148916236 | 2148916237 => "adult verification required".into(),
+        2148916238 => "this is a child account, it must be added to a family by an adult".into(),
+        _ => format!("Xbox Live authorization failed with error {}", x_err),
+    }
+}
+
+// Read as text first, so a body that isn't what was expected is told apart
+// from a network error
+async fn parse_json<T: DeserializeOwned>(res: Response) -> Result<T, Error> {
+    Ok(serde_json::from_str(&res.text().await?)?)
+}
+
+// Both Xbox Live endpoints answer with 401 and an XErr code when the account
+// isn't allowed to sign in
+async fn check_xbox_response<T: DeserializeOwned>(res: Response) -> Result<T, Error> {
+    #[derive(Debug, Clone, Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct XboxErrorResponse {
+        x_err: u64,
+    }
+
+    let status = res.status();
+    if status.is_success() {
+        return parse_json(res).await;
+    }
+    let message = res.text().await?;
+    if let Ok(XboxErrorResponse { x_err }) = serde_json::from_str(&message) {
+        Err(Error::XSTSError { x_err })
+    } else if status == StatusCode::UNAUTHORIZED {
+        Err(Error::TokenExpired)
+    } else {
+        Err(Error::XboxLiveError {
+            code: status.as_u16().into(),
+            message,
+        })
+    }
+}
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "camelCase")]
@@ -46,42 +101,48 @@ impl Authentication<'_> {
 
     pub async fn get_access_token(&mut self) -> Result<&str, WebError> {
         if self.mc_access_token.is_none() {
-            #[derive(Debug, Clone, Serialize)]
-            #[serde(rename_all = "PascalCase")]
-            struct XboxLiveRequestProperties<'a> {
-                auth_method: &'a str,
-                site_name: &'a str,
-                rps_ticket: &'a str,
-            }
-            #[derive(Debug, Clone, Serialize)]
-            #[serde(rename_all = "PascalCase")]
-            struct XboxLiveRequest<'a> {
-                properties: XboxLiveRequestProperties<'a>,
-                relying_party: &'a str,
-                token_type: &'a str,
-            }
-
-            #[derive(Debug, Clone, Deserialize)]
-            struct XboxLiveResponseDisplayClaim {
-                uhs: String,
-            }
-
-            #[derive(Debug, Clone, Deserialize)]
-            struct XboxLiveResponseDisplayClaims {
-                xui: Vec<XboxLiveResponseDisplayClaim>,
-            }
-
-            #[derive(Debug, Clone, Deserialize)]
-            #[serde(rename_all = "PascalCase")]
-            struct XboxLiveResponse {
-                token: String,
-                display_claims: XboxLiveResponseDisplayClaims,
-            }
-
-            // TODO: refresh logic
-
-            let xbl_res: XboxLiveResponse = self
-                .client
+            self.mc_access_token = Some(self.fetch_access_token().await?);
+        }
+        Ok(self.mc_access_token.as_ref().unwrap())
+    }
+
+    async fn fetch_access_token(&self) -> Result<String, Error> {
+        #[derive(Debug, Clone, Serialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct XboxLiveRequestProperties<'a> {
+            auth_method: &'a str,
+            site_name: &'a str,
+            rps_ticket: &'a str,
+        }
+        #[derive(Debug, Clone, Serialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct XboxLiveRequest<'a> {
+            properties: XboxLiveRequestProperties<'a>,
+            relying_party: &'a str,
+            token_type: &'a str,
+        }
+
+        #[derive(Debug, Clone, Deserialize)]
+        struct XboxLiveResponseDisplayClaim {
+            uhs: String,
+        }
+
+        #[derive(Debug, Clone, Deserialize)]
+        struct XboxLiveResponseDisplayClaims {
+            xui: Vec<XboxLiveResponseDisplayClaim>,
+        }
+
+        #[derive(Debug, Clone, Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct XboxLiveResponse {
+            token: String,
+            display_claims: XboxLiveResponseDisplayClaims,
+        }
+
+        // TODO: refresh logic
+
+        let xbl_res: XboxLiveResponse = check_xbox_response(
+            self.client
                 .post("https://user.auth.xboxlive.com/user/authenticate")
                 .json(&XboxLiveRequest {
                     properties: XboxLiveRequestProperties {
@@ -93,33 +154,33 @@ impl Authentication<'_> {
                     token_type: "JWT",
                 })
                 .send()
-                .await?
-                .json()
-                .await?;
-
-            #[derive(Debug, Clone, Serialize)]
-            #[serde(rename_all = "PascalCase")]
-            struct XSTSRequestProperties<'a> {
-                sandbox_id: &'a str,
-                user_tokens: [&'a str; 1],
-            }
-
-            #[derive(Debug, Clone, Serialize)]
-            #[serde(rename_all = "PascalCase")]
-            struct XSTSRequest<'a> {
-                properties: XSTSRequestProperties<'a>,
-                relying_party: &'a str,
-                token_type: &'a str,
-            }
-
-            #[derive(Debug, Clone, Deserialize)]
-            #[serde(rename_all = "PascalCase")]
-            struct XSTSResponse {
-                token: String,
-            }
-
-            let xsts_res: XSTSResponse = self
-                .client
+                .await?,
+        )
+        .await?;
+
+        #[derive(Debug, Clone, Serialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct XSTSRequestProperties<'a> {
+            sandbox_id: &'a str,
+            user_tokens: [&'a str; 1],
+        }
+
+        #[derive(Debug, Clone, Serialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct XSTSRequest<'a> {
+            properties: XSTSRequestProperties<'a>,
+            relying_party: &'a str,
+            token_type: &'a str,
+        }
+
+        #[derive(Debug, Clone, Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct XSTSResponse {
+            token: String,
+        }
+
+        let xsts_res: XSTSResponse = check_xbox_response(
+            self.client
                 .post("https://xsts.auth.xboxlive.com/xsts/authorize")
                 .header("Accept", "application/json")
                 .json(&XSTSRequest {
@@ -131,23 +192,23 @@ impl Authentication<'_> {
                     token_type: "JWT",
                 })
                 .send()
-                .await?
-                .json()
-                .await?;
-
-            #[derive(Debug, Clone, Serialize)]
-            #[serde(rename_all = "camelCase")]
-            struct MinecraftRequest<'a> {
-                identity_token: &'a str,
-            }
-
-            #[derive(Debug, Clone, Deserialize)]
-            struct MinecraftResponse {
-                access_token: String,
-            }
-
-            let mc_res: MinecraftResponse = self
-                .client
+                .await?,
+        )
+        .await?;
+
+        #[derive(Debug, Clone, Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct MinecraftRequest<'a> {
+            identity_token: &'a str,
+        }
+
+        #[derive(Debug, Clone, Deserialize)]
+        struct MinecraftResponse {
+            access_token: String,
+        }
+
+        let mc_res: MinecraftResponse = parse_json(
+            self.client
                 .post("https://api.minecraftservices.com/authentication/login_with_xbox")
                 .json(&MinecraftRequest {
                     identity_token: &[
@@ -160,25 +221,38 @@ impl Authentication<'_> {
                 })
                 .send()
                 .await?
-                .json()
-                .await?;
+                .error_for_status()?,
+        )
+        .await?;
 
-            self.mc_access_token = Some(mc_res.access_token);
-        }
-        Ok(&self.mc_access_token.as_ref().unwrap())
+        Ok(mc_res.access_token)
     }
 
     pub async fn get_info(&mut self) -> Result<UserInfo<'static>, WebError> {
         self.get_access_token().await?;
 
-        Ok(self
+        #[derive(Debug, Clone, Deserialize)]
+        #[serde(untagged)]
+        enum ProfileResponse {
+            Found(UserInfo<'static>),
+            // Accounts that never bought the game get an error object (e.g.
+            // NOT_FOUND) with no id instead of a profile
+            Missing {},
+        }
+
+        let res = self
             .client
             .get("https://api.minecraftservices.com/minecraft/profile")
             .bearer_auth(self.mc_access_token.as_ref().unwrap())
             .send()
-            .await?
-            .json()
-            .await?)
+            .await?;
+        if res.status() == StatusCode::NOT_FOUND || res.content_length() == Some(0) {
+            return Err(Error::MinecraftNotOwned.into());
+        }
+        match parse_json(res.error_for_status()?).await? {
+            ProfileResponse::Found(info) => Ok(info),
+            ProfileResponse::Missing {} => Err(Error::MinecraftNotOwned.into()),
+        }
     }
 }
 
@@ -209,3 +283,26 @@ impl Authenticator for OnlineMode<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+
+    #[test]
+    fn xsts_error_messages() {
+        assert_eq!(
+            Error::XSTSError { x_err: 2148916233 }.to_string(),
+            "this Microsoft account has no Xbox account, sign up for one first"
+        );
+        assert_eq!(
+            Error::XSTSError { x_err: 1 }.to_string(),
+            "Xbox Live authorization failed with error 1"
+        );
+    }
+
+    #[test]
+    fn invalid_response_message() {
+        let err: Error = serde_json::from_str::<u64>("<html>").unwrap_err().into();
+        assert!(err.to_string().starts_with("unexpected response: "));
+    }
+}
@@ -1,5 +1,7 @@
+pub mod cache;
 pub mod error;
 pub mod hypixel;
 pub mod microsoft;
 pub mod mojang;
+pub mod skin;
 pub mod yggdrasil;
@@ -2,21 +2,100 @@ use super::error::Error as WebError;
 use crate::protocol::types::{serde_raw_uuid, UUID};
 use reqwest::Client;
 use serde::Deserialize;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::{
+    sync::Mutex,
+    time::{interval, Interval, MissedTickBehavior},
+};
+
+const MOJANG_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+const MOJANG_PROFILES_URL: &str = "https://api.mojang.com/users/profiles/minecraft";
+
+// 1 to 16 letters, digits and underscores
+pub fn is_valid_username(name: &str) -> bool {
+    (1..=16).contains(&name.len())
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+// Where clients announce joins and servers verify them. Some third-party
+// launchers (e.g. Ely.by) run their own session servers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionServerConfig {
+    pub join_url: String,
+    pub has_joined_url: String,
+}
+
+impl SessionServerConfig {
+    // For session servers that use the same paths as Mojang's
+    pub fn from_base_url(base_url: &str) -> SessionServerConfig {
+        let base_url = base_url.trim_end_matches('/');
+        SessionServerConfig {
+            join_url: [base_url, "/session/minecraft/join"].concat(),
+            has_joined_url: [base_url, "/session/minecraft/hasJoined"].concat(),
+        }
+    }
+}
+
+impl Default for SessionServerConfig {
+    fn default() -> Self {
+        SessionServerConfig::from_base_url(MOJANG_SESSION_SERVER)
+    }
+}
+
+// Custom authentication servers (e.g. Ely.by) also have their own profile
+// lookup, under a different URL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MojangConfig {
+    // Followed by /<username>
+    pub profiles_url: String,
+    pub session_server: SessionServerConfig,
+}
+
+impl Default for MojangConfig {
+    fn default() -> Self {
+        MojangConfig {
+            profiles_url: MOJANG_PROFILES_URL.to_string(),
+            session_server: SessionServerConfig::default(),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Mojang<'a> {
     access_token: Option<&'a str>,
     client: Client,
+    profiles_url: String,
+    session_server: SessionServerConfig,
 }
 
 impl Mojang<'_> {
-    pub fn new(access_token: Option<&str>, client: Option<Client>) -> Mojang {
+    pub fn new(
+        access_token: Option<&str>,
+        client: Option<Client>,
+        config: Option<MojangConfig>,
+    ) -> Mojang {
+        let config = config.unwrap_or_default();
         Mojang {
             access_token,
             client: client.unwrap_or_default(),
+            profiles_url: config.profiles_url,
+            session_server: config.session_server,
         }
     }
 
+    pub fn session_server(&self) -> &SessionServerConfig {
+        &self.session_server
+    }
+
+    pub fn set_session_server_url(&mut self, base_url: &str) {
+        self.session_server = SessionServerConfig::from_base_url(base_url);
+    }
+
     pub async fn get_uuid(&self, name: &str) -> Result<(UUID, String), WebError> {
         #[derive(Deserialize)]
         struct UUIDResponse {
@@ -26,7 +105,7 @@ impl Mojang<'_> {
         }
         let res: UUIDResponse = self
             .client
-            .get(["https://api.mojang.com/users/profiles/minecraft/", name].concat())
+            .get([self.profiles_url.trim_end_matches('/'), "/", name].concat())
             .send()
             .await?
             .error_for_status()?
@@ -35,3 +114,106 @@ impl Mojang<'_> {
         Ok((res.id, res.name))
     }
 }
+
+// Mojang allows about 600 UUID lookups per 10 minutes per IP
+const UUID_LOOKUP_INTERVAL: Duration = Duration::from_secs(1);
+
+// Spaces out UUID lookups so large lobbies don't hit Mojang's rate limit. A
+// batch lookup should be preferred where possible, since it costs a single
+// request. Lookups are released in the order they were made because tokio's
+// Mutex is fair.
+#[derive(Debug)]
+pub struct RateLimitedMojang<'a> {
+    inner: Mojang<'a>,
+    interval: Mutex<Interval>,
+    queue_depth: AtomicUsize,
+}
+
+impl<'a> RateLimitedMojang<'a> {
+    // Must be called from within a Tokio runtime
+    pub fn new(inner: Mojang<'a>) -> RateLimitedMojang<'a> {
+        let mut interval = interval(UUID_LOOKUP_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        RateLimitedMojang {
+            inner,
+            interval: Mutex::new(interval),
+            queue_depth: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn session_server(&self) -> &SessionServerConfig {
+        self.inner.session_server()
+    }
+
+    // Lookups waiting for their turn
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    pub async fn get_uuid(&self, name: &str) -> Result<(UUID, String), WebError> {
+        {
+            let _queued = Queued::new(&self.queue_depth);
+            self.interval.lock().await.tick().await;
+        }
+        self.inner.get_uuid(name).await
+    }
+}
+
+// Counts a lookup as queued until dropped, so one whose command is cancelled
+// while waiting doesn't stay in the count
+struct Queued<'a>(&'a AtomicUsize);
+
+impl<'a> Queued<'a> {
+    fn new(depth: &'a AtomicUsize) -> Queued<'a> {
+        depth.fetch_add(1, Ordering::Relaxed);
+        Queued(depth)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mojang, MojangConfig, RateLimitedMojang, SessionServerConfig};
+    use futures::poll;
+
+    #[test]
+    fn session_server_urls() {
+        assert_eq!(
+            SessionServerConfig::default().join_url,
+            "https://sessionserver.mojang.com/session/minecraft/join"
+        );
+        let ely = SessionServerConfig::from_base_url("https://authserver.ely.by/");
+        assert_eq!(
+            ely.has_joined_url,
+            "https://authserver.ely.by/session/minecraft/hasJoined"
+        );
+    }
+
+    #[test]
+    fn default_config() {
+        let config = MojangConfig::default();
+        assert_eq!(
+            config.profiles_url,
+            "https://api.mojang.com/users/profiles/minecraft"
+        );
+        assert_eq!(config.session_server, SessionServerConfig::default());
+    }
+
+    #[tokio::test]
+    async fn cancelled_lookup_leaves_queue() {
+        let mojang = RateLimitedMojang::new(Mojang::new(None, None, None));
+        // Holding the interval keeps the lookup waiting for its turn
+        let interval = mojang.interval.lock().await;
+        let mut lookup = Box::pin(mojang.get_uuid("Player"));
+        assert!(poll!(lookup.as_mut()).is_pending());
+        assert_eq!(mojang.queue_depth(), 1);
+        drop(lookup);
+        assert_eq!(mojang.queue_depth(), 0);
+        drop(interval);
+    }
+}
@@ -0,0 +1,16 @@
+use super::error::Error as WebError;
+use crate::protocol::types::UUID;
+use reqwest::Client;
+
+// 8x8 face with the hat layer on top
+pub async fn face_png_bytes(uuid: UUID, client: &Client) -> Result<Vec<u8>, WebError> {
+    Ok(client
+        .get(format!("https://crafatar.com/avatars/{}", uuid))
+        .query(&[("size", "8"), ("overlay", "")])
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec())
+}
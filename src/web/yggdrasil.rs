@@ -38,11 +38,14 @@ pub struct UserInfo<'a> {
     pub id: UUID,
 }
 
+const MOJANG_AUTH_SERVER: &str = "https://authserver.mojang.com";
+
 #[derive(Clone, Debug)]
 pub struct Authentication<'a> {
     client_token: Option<&'a str>,
     access_token: Option<Cow<'a, str>>,
     client: Client,
+    auth_server: &'a str,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -90,6 +93,12 @@ struct RefreshRequest<'a> {
     client_token: Option<&'a str>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct SignoutRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
 enum RawAuthenticationResponse {
@@ -103,18 +112,26 @@ const MINECRAFT_AGENT: AuthenticationRequestAgent = AuthenticationRequestAgent {
 };
 
 impl Authentication<'_> {
+    // auth_server is the base URL of a Yggdrasil compatible server, Mojang's
+    // if None
     pub fn new<'a>(
         client_token: Option<&'a str>,
         access_token: Option<Cow<'a, str>>,
         client: Option<Client>,
+        auth_server: Option<&'a str>,
     ) -> Authentication<'a> {
         Authentication {
             client_token,
             access_token,
             client: client.unwrap_or_default(),
+            auth_server: auth_server.unwrap_or(MOJANG_AUTH_SERVER),
         }
     }
 
+    fn endpoint(&self, path: &str) -> String {
+        endpoint(self.auth_server, path)
+    }
+
     pub async fn authenticate<'a, 'b: 'a>(
         &'b mut self,
         username: &'a str,
@@ -122,7 +139,7 @@ impl Authentication<'_> {
     ) -> Result<AuthenticationResponse<'_>, WebError> {
         let res = self
             .client
-            .post("https://authserver.mojang.com/authenticate")
+            .post(self.endpoint("/authenticate"))
             .json(&AuthenticationRequest {
                 client_token: self.client_token,
                 username,
@@ -149,7 +166,7 @@ impl Authentication<'_> {
         if let Some(access_token) = &self.access_token {
             let res = self
                 .client
-                .post("https://authserver.mojang.com/refresh")
+                .post(self.endpoint("/refresh"))
                 .json(&ValidationRefreshRequest {
                     client_token: self.client_token,
                     access_token,
@@ -173,11 +190,56 @@ impl Authentication<'_> {
         }
     }
 
+    pub async fn invalidate(&mut self) -> Result<(), WebError> {
+        if let Some(access_token) = &self.access_token {
+            let res = self
+                .client
+                .post(self.endpoint("/invalidate"))
+                .json(&ValidationRefreshRequest {
+                    client_token: self.client_token,
+                    access_token,
+                })
+                .send()
+                .await?;
+            // A token Mojang doesn't know about is as good as invalidated
+            if res.status().is_success() || res.status() == StatusCode::NOT_FOUND {
+                self.access_token = None;
+                Ok(())
+            } else {
+                Err(res.json::<Error>().await?.into())
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn signout(
+        username: &str,
+        password: &str,
+        client: Option<Client>,
+        auth_server: Option<&str>,
+    ) -> Result<(), WebError> {
+        let res = client
+            .unwrap_or_default()
+            .post(endpoint(
+                auth_server.unwrap_or(MOJANG_AUTH_SERVER),
+                "/signout",
+            ))
+            .json(&SignoutRequest { username, password })
+            .send()
+            .await?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(res.json::<Error>().await?.into())
+        }
+    }
+
     pub async fn get_access_token(&mut self) -> Result<Option<&str>, WebError> {
         if let Some(access_token) = self.access_token.take() {
             if self
                 .client
-                .post("https://authserver.mojang.com/validate")
+                .post(self.endpoint("/validate"))
                 .json(&ValidationRefreshRequest {
                     client_token: self.client_token,
                     access_token: &access_token,
@@ -200,6 +262,10 @@ impl Authentication<'_> {
     }
 }
 
+fn endpoint(auth_server: &str, path: &str) -> String {
+    [auth_server.trim_end_matches('/'), path].concat()
+}
+
 pub struct OnlineMode<'a> {
     user_info: UserInfo<'a>,
     auth: Authentication<'a>,
@@ -231,3 +297,22 @@ impl Authenticator for OnlineMode<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Authentication;
+
+    #[test]
+    fn auth_server_endpoints() {
+        let mojang = Authentication::new(None, None, None, None);
+        assert_eq!(
+            mojang.endpoint("/refresh"),
+            "https://authserver.mojang.com/refresh"
+        );
+        let ely = Authentication::new(None, None, None, Some("https://authserver.ely.by/auth/"));
+        assert_eq!(
+            ely.endpoint("/validate"),
+            "https://authserver.ely.by/auth/validate"
+        );
+    }
+}
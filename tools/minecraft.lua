@@ -0,0 +1,71 @@
+-- Wireshark dissector for pcap files written by
+-- `packet_dumper --export-pcap <capture> <output.pcap>`.
+-- Install by copying it into Wireshark's personal Lua plugins folder
+-- (Help > About Wireshark > Folders).
+--
+-- Each frame is the connection number (u32, big endian), the direction (u8,
+-- 0 for client to server and 1 for server to client) and the packet as it
+-- would be sent uncompressed: VarInt length, VarInt ID and the body.
+
+local minecraft = Proto("minecraft", "Minecraft")
+
+local directions = { [0] = "Client to server", [1] = "Server to client" }
+
+local f_conn = ProtoField.uint32("minecraft.conn", "Connection")
+local f_direction = ProtoField.uint8("minecraft.direction", "Direction", base.DEC, directions)
+local f_length = ProtoField.uint32("minecraft.length", "Length")
+local f_id = ProtoField.uint32("minecraft.id", "Packet ID", base.HEX)
+local f_data = ProtoField.bytes("minecraft.data", "Data")
+
+minecraft.fields = { f_conn, f_direction, f_length, f_id, f_data }
+
+-- Returns the value and how many bytes it took, or nil if it runs past the end
+local function read_varint(tvb, offset)
+    local value = 0
+    for i = 0, 4 do
+        if offset + i >= tvb:len() then
+            return nil
+        end
+        local byte = tvb(offset + i, 1):uint()
+        value = value + bit.lshift(bit.band(byte, 0x7F), 7 * i)
+        if bit.band(byte, 0x80) == 0 then
+            return value, i + 1
+        end
+    end
+    return nil
+end
+
+function minecraft.dissector(tvb, pinfo, tree)
+    pinfo.cols.protocol = "Minecraft"
+    local subtree = tree:add(minecraft, tvb(), "Minecraft packet")
+    if tvb:len() < 5 then
+        return
+    end
+    local conn = tvb(0, 4):uint()
+    local direction = tvb(4, 1):uint()
+    subtree:add(f_conn, tvb(0, 4))
+    subtree:add(f_direction, tvb(4, 1))
+
+    local length, length_len = read_varint(tvb, 5)
+    if length == nil then
+        return
+    end
+    subtree:add(f_length, tvb(5, length_len), length)
+    local offset = 5 + length_len
+    local id, id_len = read_varint(tvb, offset)
+    if id == nil then
+        return
+    end
+    subtree:add(f_id, tvb(offset, id_len), id)
+    offset = offset + id_len
+    if offset < tvb:len() then
+        subtree:add(f_data, tvb(offset))
+    end
+
+    pinfo.cols.src = "#" .. conn .. (direction == 0 and " client" or " server")
+    pinfo.cols.dst = "#" .. conn .. (direction == 0 and " server" or " client")
+    pinfo.cols.info = string.format("%s id 0x%02X, %d bytes", directions[direction] or "?", id,
+        tvb:len() - offset)
+end
+
+DissectorTable.get("wtap_encap"):add(wtap.USER0, minecraft)